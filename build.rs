@@ -0,0 +1,17 @@
+use std::io;
+
+fn main() -> io::Result<()> {
+    // The vendored protoc and well-known types, so that building does not need protoc installed.
+    let protoc = protoc_bin_vendored::protoc_bin_path().map_err(io::Error::other)?;
+    let include = protoc_bin_vendored::include_path().map_err(io::Error::other)?;
+    let mut config = prost_build::Config::new();
+    config.protoc_executable(protoc);
+
+    tonic_prost_build::configure()
+        .build_client(false)
+        .compile_with_config(
+            config,
+            &["proto/home_environments.proto".into()],
+            &["proto".into(), include],
+        )
+}
@@ -0,0 +1,37 @@
+CREATE TABLE switchbot_measurements_hourly (
+  device_id BYTES NOT NULL REFERENCES switchbot_devices (id),
+  bucket_start TIMESTAMPTZ NOT NULL,
+  sample_count INT NOT NULL,
+  temperature_celsius_min FLOAT NOT NULL,
+  temperature_celsius_avg FLOAT NOT NULL,
+  temperature_celsius_max FLOAT NOT NULL,
+  humidity_percent_min INT NOT NULL,
+  humidity_percent_avg FLOAT NOT NULL,
+  humidity_percent_max INT NOT NULL,
+  co2_ppm_min INT,
+  co2_ppm_avg FLOAT,
+  co2_ppm_max INT,
+  light_level_min INT,
+  light_level_avg FLOAT,
+  light_level_max INT,
+  PRIMARY KEY (device_id, bucket_start)
+);
+
+CREATE TABLE switchbot_measurements_daily (
+  device_id BYTES NOT NULL REFERENCES switchbot_devices (id),
+  bucket_start TIMESTAMPTZ NOT NULL,
+  sample_count INT NOT NULL,
+  temperature_celsius_min FLOAT NOT NULL,
+  temperature_celsius_avg FLOAT NOT NULL,
+  temperature_celsius_max FLOAT NOT NULL,
+  humidity_percent_min INT NOT NULL,
+  humidity_percent_avg FLOAT NOT NULL,
+  humidity_percent_max INT NOT NULL,
+  co2_ppm_min INT,
+  co2_ppm_avg FLOAT,
+  co2_ppm_max INT,
+  light_level_min INT,
+  light_level_avg FLOAT,
+  light_level_max INT,
+  PRIMARY KEY (device_id, bucket_start)
+);
@@ -0,0 +1,2 @@
+ALTER TABLE switchbot_devices
+ADD COLUMN notes STRING;
@@ -0,0 +1,2 @@
+ALTER TABLE switchbot_devices
+ADD COLUMN enabled BOOL NOT NULL DEFAULT true;
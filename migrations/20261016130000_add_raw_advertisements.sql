@@ -0,0 +1,8 @@
+CREATE TABLE raw_advertisements (
+  device_id BYTES NOT NULL REFERENCES switchbot_devices (id),
+  received_at TIMESTAMPTZ NOT NULL,
+  rssi_dbm INT,
+  manufacturer_data JSONB NOT NULL,
+  service_data JSONB NOT NULL,
+  PRIMARY KEY (device_id, received_at)
+);
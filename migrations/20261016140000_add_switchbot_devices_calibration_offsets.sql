@@ -0,0 +1,5 @@
+ALTER TABLE switchbot_devices
+ADD COLUMN temperature_offset_celsius FLOAT NOT NULL DEFAULT 0;
+
+ALTER TABLE switchbot_devices
+ADD COLUMN humidity_offset_percent INT NOT NULL DEFAULT 0;
@@ -0,0 +1,2 @@
+ALTER TABLE switchbot_measurements
+ADD COLUMN source STRING;
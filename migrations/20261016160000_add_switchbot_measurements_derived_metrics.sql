@@ -0,0 +1,5 @@
+ALTER TABLE switchbot_measurements
+ADD COLUMN dew_point_celsius FLOAT;
+
+ALTER TABLE switchbot_measurements
+ADD COLUMN absolute_humidity_g_m3 FLOAT;
@@ -0,0 +1,95 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::{Context as _, Error, Result, anyhow, bail};
+use btleplug::{api::Central as _, platform::Adapter};
+
+/// Identifies a Bluetooth adapter by its position in the adapter list or by its name (e.g.
+/// `hci1`). `all` selects every adapter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdapterSelector {
+    All,
+    Index(usize),
+    Name(String),
+}
+
+impl FromStr for AdapterSelector {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            bail!("adapter selector is empty");
+        }
+
+        if s == "all" {
+            return Ok(AdapterSelector::All);
+        }
+
+        match s.parse::<usize>() {
+            Ok(index) => Ok(AdapterSelector::Index(index)),
+            Err(_) => Ok(AdapterSelector::Name(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for AdapterSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdapterSelector::All => f.write_str("all"),
+            AdapterSelector::Index(index) => write!(f, "#{index}"),
+            AdapterSelector::Name(name) => f.write_str(name),
+        }
+    }
+}
+
+/// Picks the adapters matching `selectors`, or the first adapter if no selector is given.
+pub async fn select_adapters(
+    adapters: Vec<Adapter>,
+    selectors: &[AdapterSelector],
+) -> Result<Vec<Adapter>> {
+    if selectors.is_empty() {
+        let adapter = adapters
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("no Bluetooth adapters found"))?;
+        return Ok(vec![adapter]);
+    }
+
+    if selectors.contains(&AdapterSelector::All) {
+        if adapters.is_empty() {
+            bail!("no Bluetooth adapters found");
+        }
+        return Ok(adapters);
+    }
+
+    let mut infos = Vec::with_capacity(adapters.len());
+    let mut selected = Vec::with_capacity(selectors.len());
+    let mut unmatched: Vec<&AdapterSelector> = selectors.iter().collect();
+    for (index, adapter) in adapters.into_iter().enumerate() {
+        // On Linux the info looks like `hci0 (usb:v1D6Bp0246d0540)`.
+        let info = adapter
+            .adapter_info()
+            .await
+            .context("failed to get Bluetooth adapter info")?;
+
+        let is_match = |selector: &AdapterSelector| match selector {
+            AdapterSelector::All => true,
+            AdapterSelector::Index(i) => *i == index,
+            AdapterSelector::Name(name) => info.split_whitespace().next() == Some(name.as_str()),
+        };
+        if selectors.iter().any(is_match) {
+            unmatched.retain(|selector| !is_match(selector));
+            selected.push(adapter);
+        }
+
+        infos.push(format!("#{index}: {info}"));
+    }
+
+    if let Some(selector) = unmatched.first() {
+        bail!(
+            "Bluetooth adapter {selector} not found, available adapters: [{}]",
+            infos.join(", ")
+        );
+    }
+
+    Ok(selected)
+}
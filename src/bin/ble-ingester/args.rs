@@ -1,11 +1,220 @@
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
+
 use chrono_tz::Tz;
 use clap::Parser;
+use home_environments::{
+    ingest::{Preference, QueuePolicy, Strategy},
+    logging::LogFormat,
+};
+use macaddr::MacAddr6;
+
+use crate::{adapter::AdapterSelector, device_filter::DeviceSelector, sink::Sink};
 
-#[derive(Debug, Parser)]
+#[derive(Debug, Clone, Parser)]
 pub struct Args {
+    /// TOML config file. Options given on the command line or via environment variables take
+    /// precedence over the file.
+    #[arg(long, env = "CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Timezone that buckets are aligned in and times are displayed in. Timestamps are always
+    /// stored in UTC.
     #[arg(long, env = "TZ")]
-    pub timezone: Tz,
+    pub timezone: Option<Tz>,
+
+    /// Name stored with every measurement, to tell several ingesters writing to the same database
+    /// apart. Defaults to the hostname.
+    #[arg(long, env = "SOURCE")]
+    pub source: Option<String>,
 
     #[arg(long, env = "DATABASE_URL")]
-    pub database_url: String,
+    pub database_url: Option<String>,
+
+    #[arg(long, env = "READ_DATABASE_URL")]
+    pub read_database_url: Option<String>,
+
+    /// Bluetooth adapters to scan with concurrently, given as indexes, names such as `hci1`, or
+    /// `all`. Defaults to the first adapter.
+    #[arg(long = "adapter", env = "ADAPTERS", value_delimiter = ',')]
+    pub adapters: Vec<AdapterSelector>,
+
+    /// Only collect from these devices, given as MAC addresses or names. Can be repeated.
+    #[arg(long = "only-device", env = "ONLY_DEVICES", value_delimiter = ',')]
+    pub only_devices: Vec<DeviceSelector>,
+
+    /// Ignore these devices, given as MAC addresses or names. Can be repeated.
+    #[arg(
+        long = "exclude-device",
+        env = "EXCLUDE_DEVICES",
+        value_delimiter = ','
+    )]
+    pub exclude_devices: Vec<DeviceSelector>,
+
+    /// Receive advertisements from every BLE device in range instead of only those advertising the
+    /// service UUIDs of registered device types.
+    #[arg(long, env = "PROMISCUOUS")]
+    pub promiscuous: bool,
+
+    /// Storage resolution: advertisements are rounded to buckets of this size, e.g. `30s` or `5m`.
+    #[arg(long, env = "INTERVAL", value_parser = humantime::parse_duration)]
+    pub interval: Option<Duration>,
+
+    /// Only advertisements received within this distance of a bucket boundary are accepted.
+    #[arg(long, env = "WINDOW", value_parser = humantime::parse_duration)]
+    pub window: Option<Duration>,
+
+    /// Restart the scan of an adapter when it delivers no events for this long, e.g. `5m`.
+    #[arg(long, env = "SCAN_TIMEOUT", value_parser = humantime::parse_duration)]
+    pub scan_timeout: Option<Duration>,
+
+    /// Warn about devices that have not been heard for this long, e.g. `1h`.
+    #[arg(long, env = "STALE_AFTER", value_parser = humantime::parse_duration)]
+    pub stale_after: Option<Duration>,
+
+    /// Connect to a Meter and read its measurement over GATT after this many consecutive
+    /// advertisements that could not be decoded.
+    #[arg(long, env = "GATT_FALLBACK_AFTER")]
+    pub gatt_fallback_after: Option<u32>,
+
+    /// Reject temperatures further than this many °C from the device's recent median.
+    #[arg(long, env = "MAX_TEMPERATURE_DELTA")]
+    pub max_temperature_delta: Option<f32>,
+
+    /// Reject humidities further than this many percentage points from the device's recent median.
+    #[arg(long, env = "MAX_HUMIDITY_DELTA")]
+    pub max_humidity_delta: Option<f32>,
+
+    /// Reject CO2 concentrations further than this many ppm from the device's recent median.
+    #[arg(long, env = "MAX_CO2_DELTA")]
+    pub max_co2_delta: Option<f32>,
+
+    /// Which sample represents a bucket: `closest` to the boundary (the default), `latest` or
+    /// `strongest` signal. Its values are stored for the fields aggregated with `closest`.
+    #[arg(long, env = "PREFER", value_enum)]
+    pub prefer: Option<Preference>,
+
+    /// How the temperatures received within the window of a bucket are combined. Defaults to
+    /// `closest`, the value of the sample picked by `--prefer`.
+    #[arg(long, env = "AGGREGATE_TEMPERATURE", value_enum)]
+    pub aggregate_temperature: Option<Strategy>,
+
+    /// How the humidities received within the window of a bucket are combined.
+    #[arg(long, env = "AGGREGATE_HUMIDITY", value_enum)]
+    pub aggregate_humidity: Option<Strategy>,
+
+    /// How the CO2 concentrations received within the window of a bucket are combined.
+    #[arg(long, env = "AGGREGATE_CO2", value_enum)]
+    pub aggregate_co2: Option<Strategy>,
+
+    /// How the light levels received within the window of a bucket are combined.
+    #[arg(long, env = "AGGREGATE_LIGHT_LEVEL", value_enum)]
+    pub aggregate_light_level: Option<Strategy>,
+
+    /// How often the device list is reloaded from the database, e.g. `5m`.
+    #[arg(long, env = "DEVICE_REFRESH_INTERVAL", value_parser = humantime::parse_duration)]
+    pub device_refresh_interval: Option<Duration>,
+
+    /// How often buffered measurements are inserted into the database, e.g. `1m` or `15m`.
+    #[arg(long, env = "FLUSH_INTERVAL", value_parser = humantime::parse_duration)]
+    pub flush_interval: Option<Duration>,
+
+    /// Drop rows that could not be written once they are this old, e.g. `1d`. Without a spool,
+    /// failed rows are otherwise retried until the buffer is full.
+    #[arg(long, env = "MAX_BUFFER_AGE", value_parser = humantime::parse_duration)]
+    pub max_buffer_age: Option<Duration>,
+
+    /// Drop the oldest rows that could not be written once more than this many measurements (or
+    /// raw advertisements) are waiting. Defaults to 100000.
+    #[arg(long, env = "MAX_BUFFER_SIZE")]
+    pub max_buffer_size: Option<usize>,
+
+    /// Number of flushed batches that may wait for a slow database before `--queue-policy`
+    /// applies. Defaults to 4.
+    #[arg(long, env = "QUEUE_CAPACITY")]
+    pub queue_capacity: Option<usize>,
+
+    /// Whether a full queue holds up the scans (`block`, the default) or drops the oldest batch.
+    #[arg(long, env = "QUEUE_POLICY", value_enum)]
+    pub queue_policy: Option<QueuePolicy>,
+
+    /// Also store the raw advertisement data so it can be decoded again later.
+    #[arg(long, env = "STORE_RAW_ADVERTISEMENTS")]
+    pub store_raw_advertisements: bool,
+
+    /// Where accepted measurements are written. Defaults to `postgres`.
+    #[arg(long = "output", env = "OUTPUT", value_enum, value_delimiter = ',')]
+    pub sinks: Vec<Sink>,
+
+    /// File to keep batches in while the database is unreachable. They are inserted once it is
+    /// back, even after a restart.
+    #[arg(long, env = "SPOOL_PATH")]
+    pub spool_path: Option<PathBuf>,
+
+    /// Print decoded measurements to stdout instead of writing anything to the database. The
+    /// device list is still read from it.
+    #[arg(long, env = "DRY_RUN")]
+    pub dry_run: bool,
+
+    /// Number of recent advertisements kept per device for `SIGUSR1` dumps. `0` disables it.
+    #[arg(long, env = "CAPTURE_SIZE")]
+    pub capture_size: Option<usize>,
+
+    /// File the captured advertisements are written to on `SIGUSR1`.
+    #[arg(long, env = "CAPTURE_PATH")]
+    pub capture_path: Option<PathBuf>,
+
+    /// Append every advertisement of a registered device to this file, in the format `--replay`
+    /// reads.
+    #[arg(long = "record", env = "RECORD")]
+    pub record_path: Option<PathBuf>,
+
+    /// Instead of scanning, feed the advertisements of this capture file through the pipeline
+    /// with their original timestamps, then exit.
+    #[arg(long = "replay", env = "REPLAY")]
+    pub replay_path: Option<PathBuf>,
+
+    /// Log filter such as `debug` or `info,sqlx=warn`. Overrides `RUST_LOG`.
+    #[arg(long, env = "LOG_LEVEL")]
+    pub log_level: Option<String>,
+
+    #[arg(long, env = "LOG_FORMAT", value_enum)]
+    pub log_format: Option<LogFormat>,
+
+    /// Serve Prometheus metrics on this address, e.g. `0.0.0.0:9100`.
+    #[arg(long, env = "METRICS_ADDR")]
+    pub metrics_addr: Option<SocketAddr>,
+
+    /// Publish live measurements to this MQTT broker, e.g. `mqtt://localhost:1883`.
+    #[arg(long, env = "MQTT_URL")]
+    pub mqtt_url: Option<String>,
+
+    /// MQTT topic template with the `{device_id}`, `{device_name}` and `{metric}` placeholders.
+    /// Defaults to `home/{device_name}/{metric}`.
+    #[arg(long, env = "MQTT_TOPIC")]
+    pub mqtt_topic: Option<String>,
+
+    /// Topic prefix of the Home Assistant discovery configs. Defaults to `homeassistant`.
+    #[arg(long, env = "MQTT_DISCOVERY_PREFIX")]
+    pub mqtt_discovery_prefix: Option<String>,
+
+    /// Token of the SwitchBot Open API, from the developer options of the app.
+    #[arg(long, env = "SWITCHBOT_TOKEN", hide_env_values = true)]
+    pub switchbot_token: Option<String>,
+
+    /// Secret of the SwitchBot Open API.
+    #[arg(long, env = "SWITCHBOT_SECRET", hide_env_values = true)]
+    pub switchbot_secret: Option<String>,
+
+    /// Poll these devices through the SwitchBot Open API, given as MAC addresses. They must be
+    /// paired to a Hub. Can be repeated.
+    #[arg(long = "cloud-device", env = "CLOUD_DEVICES", value_delimiter = ',')]
+    pub cloud_devices: Vec<MacAddr6>,
+
+    /// How often the cloud devices are polled, a multiple of the interval. Defaults to `5m`.
+    #[arg(long, env = "CLOUD_POLL_INTERVAL", value_parser = humantime::parse_duration)]
+    pub cloud_poll_interval: Option<Duration>,
+
+    /// Serve the latest measurements (`/latest`) and the live stream (`/ws`) on this address, e.g. `0.0.0.0:8080`.
+    #[arg(long, env = "HTTP_ADDR")]
+    pub http_addr: Option<SocketAddr>,
 }
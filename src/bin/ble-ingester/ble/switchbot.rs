@@ -1,23 +1,144 @@
 use std::collections::HashMap;
 
 use anyhow::{Context as _, Result, anyhow, bail};
-use home_environments::switchbot::DeviceType;
+use home_environments::{ingest::DecodedMeasurement, switchbot::DeviceType};
+use macaddr::MacAddr6;
+use tracing::debug;
 use uuid::{Uuid, uuid};
 
-#[derive(Debug)]
-pub struct DecodedMeasurement {
-    pub temperature_celsius: f32,
-    pub humidity_percent: u8,
-    pub co2_ppm: Option<u16>,
-    pub light_level: Option<u8>,
-}
-
 // Ref: https://github.com/OpenWonderLabs/SwitchBotAPI-BLE/blob/2bd727ecf7c0898b25ac2df58a4886b5930c9138/README.md?plain=1#L44
 const SWITCHBOT_MANUFACTURER_DATA_COMPANY_ID: u16 = 0x0969;
 
 // Ref: https://github.com/OpenWonderLabs/SwitchBotAPI-BLE/blob/2bd727ecf7c0898b25ac2df58a4886b5930c9138/README.md?plain=1#L45
 const SWITCHBOT_SERVICE_DATA_UUID: Uuid = uuid!("0000fd3d-0000-1000-8000-00805f9b34fb");
 
+// Ref: https://github.com/OpenWonderLabs/SwitchBotAPI-BLE/blob/2bd727ecf7c0898b25ac2df58a4886b5930c9138/README.md
+pub const SWITCHBOT_GATT_SERVICE_UUID: Uuid = uuid!("cba20d00-224d-11e6-9fb8-0002a5d5c51b");
+pub const SWITCHBOT_GATT_WRITE_CHARACTERISTIC_UUID: Uuid =
+    uuid!("cba20002-224d-11e6-9fb8-0002a5d5c51b");
+pub const SWITCHBOT_GATT_NOTIFY_CHARACTERISTIC_UUID: Uuid =
+    uuid!("cba20003-224d-11e6-9fb8-0002a5d5c51b");
+
+/// Asks a meter for its current temperature and humidity.
+pub const SWITCHBOT_GATT_READ_MEASUREMENT_COMMAND: [u8; 3] = [0x57, 0x0f, 0x31];
+
+/// Service UUID advertised by `device_type`, used to narrow down BLE scans.
+pub fn service_uuid(device_type: &DeviceType) -> Uuid {
+    match device_type {
+        DeviceType::Hub
+        | DeviceType::HubMini
+        | DeviceType::Hub2
+        | DeviceType::Hub3
+        | DeviceType::Meter
+        | DeviceType::MeterPlus
+        | DeviceType::WoIOSensor
+        | DeviceType::MeterPro
+        | DeviceType::MeterProCO2 => SWITCHBOT_SERVICE_DATA_UUID,
+    }
+}
+
+/// Works around a device whose advertisements the built-in decoders get wrong, e.g. after a
+/// firmware update changed the type byte or the layout.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DecoderOverride {
+    /// Decode as this type instead of the one detected from the service data.
+    pub device_type: Option<DeviceType>,
+    /// Offsets into the SwitchBot manufacturer data, replacing those of the decoder.
+    pub temperature_byte: Option<usize>,
+    pub humidity_byte: Option<usize>,
+    pub co2_byte: Option<usize>,
+}
+
+impl DecoderOverride {
+    fn has_offsets(&self) -> bool {
+        self.temperature_byte.is_some() || self.humidity_byte.is_some() || self.co2_byte.is_some()
+    }
+}
+
+/// Decodes the advertisement of a device registered as `device_type`: as the type detected from
+/// the service data, falling back to `device_type`, unless `decoder_override` says otherwise.
+pub fn decode_advertisement(
+    device_type: &DeviceType,
+    decoder_override: Option<&DecoderOverride>,
+    manufacturer_data: &HashMap<u16, Vec<u8>>,
+    service_data: &HashMap<Uuid, Vec<u8>>,
+) -> Result<DecodedMeasurement> {
+    let decoded = match decoder_override.and_then(|o| o.device_type) {
+        Some(device_type) => decode_manufacturer_data(&device_type, manufacturer_data),
+        None => decode_ble_data(manufacturer_data, service_data)
+            .inspect_err(|err| {
+                debug!(
+                    error = format!("{err:#}"),
+                    "failed to decode BLE service data, falling back to manufacturer data"
+                );
+            })
+            .or_else(|_| decode_manufacturer_data(device_type, manufacturer_data)),
+    };
+
+    match decoder_override {
+        Some(decoder_override) if decoder_override.has_offsets() => {
+            apply_offsets(decoded, decoder_override, manufacturer_data)
+        }
+        _ => decoded,
+    }
+}
+
+/// Reads the values at the offsets of `decoder_override` over those of `decoded`. A decoder that
+/// failed is only replaced when the offsets cover both the temperature and the humidity.
+fn apply_offsets(
+    decoded: Result<DecodedMeasurement>,
+    decoder_override: &DecoderOverride,
+    manufacturer_data: &HashMap<u16, Vec<u8>>,
+) -> Result<DecodedMeasurement> {
+    let mut decoded = match decoded {
+        Ok(decoded) => decoded,
+        Err(_)
+            if decoder_override.temperature_byte.is_some()
+                && decoder_override.humidity_byte.is_some() =>
+        {
+            DecodedMeasurement {
+                temperature_celsius: 0.0,
+                humidity_percent: 0,
+                co2_ppm: None,
+                light_level: None,
+            }
+        }
+        Err(err) => return Err(err),
+    };
+
+    let data = get_switch_bot_manufacturer_data(manufacturer_data)
+        .context("failed to get SwitchBot manufacturer data")?;
+    // The offsets are configured, so `offset + index` may not fit in a usize.
+    let byte = |offset: usize, index: usize| {
+        offset
+            .checked_add(index)
+            .and_then(|i| data.get(i))
+            .copied()
+            .with_context(|| {
+                format!(
+                    "manufacturer data too short for override: expected at least {} bytes, got {}",
+                    offset.saturating_add(index).saturating_add(1),
+                    data.len()
+                )
+            })
+    };
+
+    if let Some(offset) = decoder_override.temperature_byte {
+        decoded.temperature_celsius = decode_temperature([byte(offset, 0)?, byte(offset, 1)?])
+            .context("failed to decode temperature")?;
+    }
+    if let Some(offset) = decoder_override.humidity_byte {
+        decoded.humidity_percent =
+            decode_humidity(byte(offset, 0)?).context("failed to decode humidity")?;
+    }
+    if let Some(offset) = decoder_override.co2_byte {
+        decoded.co2_ppm =
+            Some(decode_co2([byte(offset, 0)?, byte(offset, 1)?]).context("failed to decode CO2")?);
+    }
+
+    Ok(decoded)
+}
+
 pub fn decode_ble_data(
     manufacturer_data: &HashMap<u16, Vec<u8>>,
     service_data: &HashMap<Uuid, Vec<u8>>,
@@ -176,6 +297,53 @@ pub fn decode_meter_pro_co2_manufacturer_data(
     })
 }
 
+/// The MAC address that SwitchBot devices put at the start of their manufacturer data.
+pub fn mac_address_from_manufacturer_data(
+    manufacturer_data: &HashMap<u16, Vec<u8>>,
+) -> Option<MacAddr6> {
+    let switchbot_manufacturer_data = get_switch_bot_manufacturer_data(manufacturer_data).ok()?;
+    let bytes: [u8; 6] = switchbot_manufacturer_data.get(..6)?.try_into().ok()?;
+    Some(bytes.into())
+}
+
+/// Whether `device_type` answers [`SWITCHBOT_GATT_READ_MEASUREMENT_COMMAND`].
+pub fn supports_gatt_read(device_type: &DeviceType) -> bool {
+    match device_type {
+        DeviceType::Meter | DeviceType::MeterPlus | DeviceType::WoIOSensor => true,
+        DeviceType::Hub
+        | DeviceType::HubMini
+        | DeviceType::Hub2
+        | DeviceType::Hub3
+        | DeviceType::MeterPro
+        | DeviceType::MeterProCO2 => false,
+    }
+}
+
+/// Decodes the notification sent in response to [`SWITCHBOT_GATT_READ_MEASUREMENT_COMMAND`]: a
+/// status byte followed by the temperature and humidity, encoded as in the advertisements.
+pub fn decode_gatt_response(response: &[u8]) -> Result<DecodedMeasurement> {
+    if response.len() < 4 {
+        bail!(
+            "GATT response is too short: expected at least 4 bytes, got {}",
+            response.len()
+        );
+    }
+    if response[0] != 0x01 {
+        bail!("GATT command failed: status 0x{:02x}", response[0]);
+    }
+
+    let temperature_celsius =
+        decode_temperature([response[1], response[2]]).context("failed to decode temperature")?;
+    let humidity_percent = decode_humidity(response[3]).context("failed to decode humidity")?;
+
+    Ok(DecodedMeasurement {
+        temperature_celsius,
+        humidity_percent,
+        co2_ppm: None,
+        light_level: None,
+    })
+}
+
 fn get_switch_bot_manufacturer_data(manufacturer_data: &HashMap<u16, Vec<u8>>) -> Result<&[u8]> {
     Ok(manufacturer_data
         .get(&SWITCHBOT_MANUFACTURER_DATA_COMPANY_ID)
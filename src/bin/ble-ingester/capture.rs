@@ -0,0 +1,105 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context as _, Result};
+use home_environments::raw_advertisement::{CapturedAdvertisement, RawAdvertisement};
+use macaddr::MacAddr6;
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::AsyncWriteExt as _,
+};
+
+/// The last advertisements of every registered device, kept so that the payload format of a new
+/// device can be studied from live captures.
+#[derive(Debug)]
+pub struct Capture {
+    capacity: usize,
+    devices: HashMap<MacAddr6, VecDeque<RawAdvertisement>>,
+}
+
+impl Capture {
+    /// Keeps up to `capacity` advertisements per device. With `0` nothing is kept.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            devices: HashMap::new(),
+        }
+    }
+
+    pub fn push(&mut self, advertisement: RawAdvertisement) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let buffer = self.devices.entry(advertisement.device_id).or_default();
+        if buffer.len() == self.capacity {
+            buffer.pop_front();
+        }
+        buffer.push_back(advertisement);
+    }
+
+    /// Writes the captured advertisements to `path` as one JSON object per line, grouped by device
+    /// and oldest first, and returns how many were written.
+    pub async fn dump(&self, path: &Path) -> Result<usize> {
+        let mut device_ids: Vec<&MacAddr6> = self.devices.keys().collect();
+        device_ids.sort();
+
+        let mut content = String::new();
+        let mut count = 0;
+        for device_id in device_ids {
+            for advertisement in &self.devices[device_id] {
+                let line = CapturedAdvertisement::from(advertisement);
+                content.push_str(
+                    &serde_json::to_string(&line).context("failed to serialize advertisement")?,
+                );
+                content.push('\n');
+                count += 1;
+            }
+        }
+
+        fs::write(path, content)
+            .await
+            .with_context(|| format!("failed to write capture file: {path:?}"))?;
+
+        Ok(count)
+    }
+}
+
+/// Appends every advertisement of a registered device to a capture file as it is received, in the
+/// format of [`Capture::dump`], building a corpus that `--replay` can feed through the pipeline.
+#[derive(Debug)]
+pub struct Recorder {
+    path: PathBuf,
+    file: File,
+}
+
+impl Recorder {
+    pub async fn open(path: PathBuf) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+            .with_context(|| format!("failed to open capture file: {path:?}"))?;
+
+        Ok(Self { path, file })
+    }
+
+    /// Writes one line and flushes it, so that nothing is lost when the ingester is stopped.
+    pub async fn record(&mut self, advertisement: &RawAdvertisement) -> Result<()> {
+        let mut line = serde_json::to_string(&CapturedAdvertisement::from(advertisement))
+            .context("failed to serialize advertisement")?;
+        line.push('\n');
+
+        self.file
+            .write_all(line.as_bytes())
+            .await
+            .with_context(|| format!("failed to write capture file: {:?}", self.path))?;
+        self.file
+            .flush()
+            .await
+            .with_context(|| format!("failed to write capture file: {:?}", self.path))
+    }
+}
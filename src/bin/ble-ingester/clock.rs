@@ -0,0 +1,45 @@
+use std::time::Instant;
+
+use chrono::{DateTime, TimeDelta, Utc};
+
+/// 2026-01-01. No measurement can be taken before this, so an earlier system clock has not been set
+/// yet, e.g. on a Raspberry Pi without an RTC that has booted but not synchronized with NTP.
+const EARLIEST_SANE_TIME: DateTime<Utc> = DateTime::from_timestamp_nanos(1_767_225_600_000_000_000);
+
+/// A larger difference between the wall clock and the monotonic clock between two readings is
+/// treated as the wall clock being set, not as drift.
+const MAX_DRIFT: TimeDelta = TimeDelta::seconds(30);
+
+/// Reads the wall clock and notices when it is unset or jumps, by comparing it with the monotonic
+/// clock that is not affected by NTP or `date -s`.
+#[derive(Debug, Default)]
+pub struct Clock {
+    reference: Option<(Instant, DateTime<Utc>)>,
+}
+
+#[derive(Debug)]
+pub struct Reading {
+    /// `None` while the clock has not been set.
+    pub now: Option<DateTime<Utc>>,
+    /// How far the wall clock was set since the previous reading.
+    pub jump: Option<TimeDelta>,
+}
+
+impl Clock {
+    pub fn read(&mut self) -> Reading {
+        let instant = Instant::now();
+        let now = Utc::now();
+
+        let jump = self.reference.and_then(|(ref_instant, ref_now)| {
+            let elapsed = TimeDelta::from_std(instant - ref_instant).ok()?;
+            let drift = now - ref_now - elapsed;
+            (drift.abs() > MAX_DRIFT).then_some(drift)
+        });
+        self.reference = Some((instant, now));
+
+        Reading {
+            now: (now >= EARLIEST_SANE_TIME).then_some(now),
+            jump,
+        }
+    }
+}
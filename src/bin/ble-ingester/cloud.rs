@@ -0,0 +1,234 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{Context as _, Result, bail};
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use chrono::{DateTime, DurationRound as _, TimeDelta, Utc};
+use home_environments::ingest::{
+    AcceptedMeasurement, BoxFuture, DecodedMeasurement, Event, Source,
+};
+use macaddr::MacAddr6;
+use reqwest::Client;
+use ring::hmac;
+use serde::Deserialize;
+use tokio::{sync::mpsc, task::JoinSet};
+use tracing::{Instrument as _, debug, info, info_span, warn};
+use uuid::Uuid;
+
+use crate::{State, accept_measurement, telemetry};
+
+const API_URL: &str = "https://api.switch-bot.com/v1.1";
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The `statusCode` of a successful response.
+const STATUS_SUCCESS: u32 = 100;
+
+/// The SwitchBot Open API account and the devices read through it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CloudConfig {
+    pub token: String,
+    pub secret: String,
+    pub devices: Vec<MacAddr6>,
+    /// A multiple of the interval, so that every poll happens at a bucket boundary.
+    pub poll_interval: TimeDelta,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Response {
+    status_code: u32,
+    message: String,
+    body: Option<DeviceStatus>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DeviceStatus {
+    temperature: Option<f32>,
+    humidity: Option<u8>,
+    #[serde(rename = "CO2")]
+    co2: Option<u16>,
+    light_level: Option<u8>,
+}
+
+/// Polls the SwitchBot Open API for devices that are out of BLE range but paired to a Hub, and
+/// sends their readings like advertisements that were received at the bucket boundary.
+pub struct CloudSource {
+    client: Client,
+    config: CloudConfig,
+    state: Arc<State>,
+}
+
+impl CloudSource {
+    pub fn new(config: CloudConfig, state: Arc<State>) -> Result<Self> {
+        let client = Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .context("failed to build HTTP client")?;
+
+        Ok(Self {
+            client,
+            config,
+            state,
+        })
+    }
+
+    async fn poll_forever(self: Arc<Self>, events: mpsc::Sender<Event>) -> Result<()> {
+        info!(
+            devices = self.config.devices.len(),
+            poll_interval = %self.config.poll_interval,
+            "polling the SwitchBot API"
+        );
+
+        loop {
+            let now = Utc::now();
+            let next = next_poll(now, self.config.poll_interval, &self.state)?;
+            tokio::time::sleep((next - now).to_std().unwrap_or_default()).await;
+
+            if events.is_closed() {
+                return Ok(());
+            }
+            // The devices are polled at once, and their readings belong to the bucket the poll
+            // started at however long the API takes to answer.
+            let mut polls = JoinSet::new();
+            for &device_id in &self.config.devices {
+                let (source, events) = (self.clone(), events.clone());
+                polls.spawn(
+                    async move { source.poll(device_id, next, &events).await }
+                        .instrument(info_span!("poll", mac_address = %device_id)),
+                );
+            }
+            polls.join_all().await;
+        }
+    }
+
+    async fn poll(
+        &self,
+        device_id: MacAddr6,
+        polled_at: DateTime<Utc>,
+        events: &mpsc::Sender<Event>,
+    ) {
+        let Some((device_name, temperature_offset, humidity_offset)) =
+            self.state.devices.read().await.get(&device_id).map(|d| {
+                (
+                    d.name.clone(),
+                    d.temperature_offset_celsius,
+                    d.humidity_offset_percent,
+                )
+            })
+        else {
+            debug!("device is not registered, skipping");
+            return;
+        };
+
+        let mut decoded = match self.fetch_status(device_id).await {
+            Ok(decoded) => {
+                telemetry::record_cloud_poll(true);
+                decoded
+            }
+            Err(err) => {
+                telemetry::record_cloud_poll(false);
+                warn!(
+                    error = format!("{err:#}"),
+                    "failed to read device status from the SwitchBot API"
+                );
+                return;
+            }
+        };
+
+        let Some(bucket) = self.state.settings.borrow().bucket(polled_at) else {
+            warn!(
+                %polled_at,
+                "polled outside the window, dropping the reading"
+            );
+            return;
+        };
+        decoded.calibrate(temperature_offset, humidity_offset);
+
+        accept_measurement(
+            &self.state,
+            events,
+            &device_name,
+            AcceptedMeasurement {
+                device_id,
+                bucket,
+                received_at: polled_at,
+                rssi_dbm: None,
+                decoded,
+            },
+        )
+        .await;
+    }
+
+    async fn fetch_status(&self, device_id: MacAddr6) -> Result<DecodedMeasurement> {
+        let t = Utc::now().timestamp_millis().to_string();
+        let nonce = Uuid::new_v4().to_string();
+        let key = hmac::Key::new(hmac::HMAC_SHA256, self.config.secret.as_bytes());
+        let string_to_sign = format!("{}{t}{nonce}", self.config.token);
+        let sign = STANDARD.encode(hmac::sign(&key, string_to_sign.as_bytes()));
+
+        let response: Response = self
+            .client
+            .get(format!(
+                "{API_URL}/devices/{}/status",
+                api_device_id(device_id)
+            ))
+            .header("Authorization", &self.config.token)
+            .header("sign", sign)
+            .header("t", t)
+            .header("nonce", nonce)
+            .send()
+            .await
+            .context("failed to request device status")?
+            .error_for_status()
+            .context("SwitchBot API returned an error")?
+            .json()
+            .await
+            .context("failed to parse device status")?;
+
+        if response.status_code != STATUS_SUCCESS {
+            bail!(
+                "SwitchBot API returned status {}: {}",
+                response.status_code,
+                response.message
+            );
+        }
+        let status = response.body.context("device status has no body")?;
+
+        Ok(DecodedMeasurement {
+            temperature_celsius: status
+                .temperature
+                .context("device status has no temperature")?,
+            humidity_percent: status.humidity.context("device status has no humidity")?,
+            co2_ppm: status.co2,
+            light_level: status.light_level,
+        })
+    }
+}
+
+impl Source for CloudSource {
+    fn run(self: Box<Self>, events: mpsc::Sender<Event>) -> BoxFuture<'static, Result<()>> {
+        Box::pin(Arc::new(*self).poll_forever(events))
+    }
+}
+
+/// The next multiple of `poll_interval` after `now`, aligned in the configured timezone like the
+/// buckets.
+fn next_poll(now: DateTime<Utc>, poll_interval: TimeDelta, state: &State) -> Result<DateTime<Utc>> {
+    let timezone = state.settings.borrow().timezone;
+    let last = now
+        .with_timezone(&timezone)
+        .duration_trunc(poll_interval)
+        .context("failed to align the poll interval")?;
+
+    Ok((last + poll_interval).with_timezone(&Utc))
+}
+
+/// The API identifies BLE devices by their MAC address without separators, e.g. `C271111EC0AB`.
+fn api_device_id(device_id: MacAddr6) -> String {
+    device_id
+        .as_bytes()
+        .iter()
+        .map(|b| format!("{b:02X}"))
+        .collect()
+}
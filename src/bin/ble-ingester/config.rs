@@ -0,0 +1,616 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context as _, Result, anyhow, bail};
+use chrono::TimeDelta;
+use chrono_tz::Tz;
+use home_environments::{
+    ingest::{Preference, QueuePolicy, Settings, Strategies, Strategy},
+    logging::LogFormat,
+};
+use macaddr::MacAddr6;
+use serde::Deserialize;
+
+use crate::{
+    adapter::AdapterSelector,
+    args::Args,
+    ble::switchbot::DecoderOverride,
+    cloud::CloudConfig,
+    device_filter::{DeviceFilter, DeviceSelector},
+    outlier::MaxDeltas,
+    sink::Sink,
+};
+
+const DEFAULT_INTERVAL: Duration = Duration::from_mins(1);
+
+const DEFAULT_WINDOW: Duration = Duration::from_secs(20);
+
+const DEFAULT_SCAN_TIMEOUT: Duration = Duration::from_mins(5);
+
+const DEFAULT_DEVICE_REFRESH_INTERVAL: Duration = Duration::from_mins(5);
+
+const DEFAULT_STALE_AFTER: Duration = Duration::from_hours(1);
+
+const DEFAULT_CAPTURE_SIZE: usize = 100;
+
+const DEFAULT_CAPTURE_PATH: &str = "ble-ingester-capture.jsonl";
+
+const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_mins(1);
+
+const DEFAULT_MAX_BUFFER_SIZE: usize = 100_000;
+
+const DEFAULT_QUEUE_CAPACITY: usize = 4;
+
+const DEFAULT_CLOUD_POLL_INTERVAL: Duration = Duration::from_mins(5);
+
+const DEFAULT_MQTT_TOPIC: &str = "home/{device_name}/{metric}";
+
+const DEFAULT_MQTT_DISCOVERY_PREFIX: &str = "homeassistant";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    timezone: Option<Tz>,
+    source: Option<String>,
+    database: DatabaseConfigFile,
+    scan: ScanConfigFile,
+    output: OutputConfigFile,
+    log: LogConfigFile,
+    capture: CaptureConfigFile,
+    record: RecordConfigFile,
+    metrics: MetricsConfigFile,
+    mqtt: MqttConfigFile,
+    http: HttpConfigFile,
+    outliers: OutliersConfigFile,
+    aggregation: AggregationConfigFile,
+    cloud: CloudConfigFile,
+    /// Keyed by MAC address.
+    decoders: BTreeMap<String, DecoderConfigFile>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct DatabaseConfigFile {
+    url: Option<String>,
+    read_url: Option<String>,
+    #[serde(with = "humantime_serde")]
+    device_refresh_interval: Option<Duration>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ScanConfigFile {
+    adapters: Vec<String>,
+    promiscuous: bool,
+    only_devices: Vec<String>,
+    exclude_devices: Vec<String>,
+    #[serde(with = "humantime_serde")]
+    interval: Option<Duration>,
+    #[serde(with = "humantime_serde")]
+    window: Option<Duration>,
+    #[serde(with = "humantime_serde")]
+    timeout: Option<Duration>,
+    #[serde(with = "humantime_serde")]
+    stale_after: Option<Duration>,
+    gatt_fallback_after: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct OutputConfigFile {
+    #[serde(with = "humantime_serde")]
+    flush_interval: Option<Duration>,
+    #[serde(with = "humantime_serde")]
+    max_buffer_age: Option<Duration>,
+    max_buffer_size: Option<usize>,
+    queue_capacity: Option<usize>,
+    queue_policy: Option<QueuePolicy>,
+    sinks: Vec<Sink>,
+    store_raw_advertisements: bool,
+    spool_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct LogConfigFile {
+    level: Option<String>,
+    format: Option<LogFormat>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct CaptureConfigFile {
+    size: Option<usize>,
+    path: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RecordConfigFile {
+    path: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct MetricsConfigFile {
+    addr: Option<SocketAddr>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct MqttConfigFile {
+    url: Option<String>,
+    topic: Option<String>,
+    discovery_prefix: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct HttpConfigFile {
+    addr: Option<SocketAddr>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct OutliersConfigFile {
+    max_temperature_delta: Option<f32>,
+    max_humidity_delta: Option<f32>,
+    max_co2_delta: Option<f32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct AggregationConfigFile {
+    prefer: Option<Preference>,
+    temperature: Option<Strategy>,
+    humidity: Option<Strategy>,
+    co2: Option<Strategy>,
+    light_level: Option<Strategy>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct CloudConfigFile {
+    token: Option<String>,
+    secret: Option<String>,
+    devices: Vec<String>,
+    #[serde(with = "humantime_serde")]
+    poll_interval: Option<Duration>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct DecoderConfigFile {
+    r#type: Option<String>,
+    temperature_byte: Option<usize>,
+    humidity_byte: Option<usize>,
+    co2_byte: Option<usize>,
+}
+
+/// Effective settings after merging the command line, environment variables and config file.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub timezone: Tz,
+    /// Stored with every measurement to tell the ingesters writing to the same database apart.
+    pub source: Option<String>,
+    pub database_url: String,
+    pub read_database_url: Option<String>,
+    pub device_refresh_interval: Duration,
+    pub adapters: Vec<AdapterSelector>,
+    pub device_filter: DeviceFilter,
+    pub promiscuous: bool,
+    pub interval: TimeDelta,
+    pub window: TimeDelta,
+    pub scan_timeout: Duration,
+    pub stale_after: TimeDelta,
+    /// Read the measurement over GATT after this many consecutive undecodable advertisements.
+    pub gatt_fallback_after: Option<u32>,
+    pub max_deltas: MaxDeltas,
+    /// Per device, how to decode advertisements the built-in decoders get wrong.
+    pub decoder_overrides: HashMap<MacAddr6, DecoderOverride>,
+    /// How the advertisements received within the window of a bucket are combined.
+    pub aggregation: Strategies,
+    pub flush_interval: Duration,
+    /// Rows that could not be written are dropped once they are this old.
+    pub max_buffer_age: Option<TimeDelta>,
+    /// Rows that could not be written are dropped, oldest first, beyond this many per table.
+    pub max_buffer_size: usize,
+    /// Batches that may wait for the writer before `queue_policy` applies.
+    pub queue_capacity: usize,
+    pub queue_policy: QueuePolicy,
+    pub sinks: Vec<Sink>,
+    pub store_raw_advertisements: bool,
+    pub spool_path: Option<PathBuf>,
+    pub dry_run: bool,
+    pub capture_size: usize,
+    pub capture_path: PathBuf,
+    /// File every advertisement of a registered device is appended to.
+    pub record_path: Option<PathBuf>,
+    pub replay_path: Option<PathBuf>,
+    pub log_level: Option<String>,
+    pub log_format: LogFormat,
+    pub metrics_addr: Option<SocketAddr>,
+    pub mqtt_url: Option<String>,
+    pub mqtt_topic: String,
+    pub mqtt_discovery_prefix: String,
+    pub http_addr: Option<SocketAddr>,
+    /// Devices read through the SwitchBot Open API, if any.
+    pub cloud: Option<CloudConfig>,
+}
+
+impl Config {
+    pub fn load(args: Args) -> Result<Self> {
+        let file = match &args.config {
+            Some(path) => read_config_file(path)?,
+            None => ConfigFile::default(),
+        };
+
+        let file_adapters = file
+            .scan
+            .adapters
+            .iter()
+            .map(|adapter| adapter.parse::<AdapterSelector>())
+            .collect::<Result<Vec<_>>>()
+            .context("invalid scan.adapters in config file")?;
+        let file_only_devices = parse_device_selectors(&file.scan.only_devices)
+            .context("invalid scan.only_devices in config file")?;
+        let file_exclude_devices = parse_device_selectors(&file.scan.exclude_devices)
+            .context("invalid scan.exclude_devices in config file")?;
+
+        let interval = args
+            .interval
+            .or(file.scan.interval)
+            .unwrap_or(DEFAULT_INTERVAL);
+        // A third of the interval matches the default for 1-minute buckets and keeps sub-minute
+        // intervals such as 10s valid without also setting the window.
+        let window = args
+            .window
+            .or(file.scan.window)
+            .unwrap_or(DEFAULT_WINDOW.min(interval / 3));
+        if interval.is_zero() {
+            bail!("interval must be greater than zero");
+        }
+        if window > interval / 2 {
+            bail!(
+                "window must be at most half of the interval: window {}, interval {}",
+                humantime::format_duration(window),
+                humantime::format_duration(interval)
+            );
+        }
+
+        let scan_timeout = args
+            .scan_timeout
+            .or(file.scan.timeout)
+            .unwrap_or(DEFAULT_SCAN_TIMEOUT);
+        if scan_timeout.is_zero() {
+            bail!("scan timeout must be greater than zero");
+        }
+
+        let device_refresh_interval = args
+            .device_refresh_interval
+            .or(file.database.device_refresh_interval)
+            .unwrap_or(DEFAULT_DEVICE_REFRESH_INTERVAL);
+        if device_refresh_interval.is_zero() {
+            bail!("device refresh interval must be greater than zero");
+        }
+
+        let flush_interval = args
+            .flush_interval
+            .or(file.output.flush_interval)
+            .unwrap_or(DEFAULT_FLUSH_INTERVAL);
+        if flush_interval.is_zero() {
+            bail!("flush interval must be greater than zero");
+        }
+
+        let gatt_fallback_after = args.gatt_fallback_after.or(file.scan.gatt_fallback_after);
+        if gatt_fallback_after == Some(0) {
+            bail!("GATT fallback threshold must be greater than zero");
+        }
+
+        let cloud = cloud_config(&args, file.cloud, interval)?;
+        let decoder_overrides = decoder_overrides(file.decoders)?;
+
+        let queue_capacity = args
+            .queue_capacity
+            .or(file.output.queue_capacity)
+            .unwrap_or(DEFAULT_QUEUE_CAPACITY);
+        if queue_capacity == 0 {
+            bail!("queue capacity must be greater than zero");
+        }
+
+        let sinks = if !args.sinks.is_empty() {
+            args.sinks
+        } else if !file.output.sinks.is_empty() {
+            file.output.sinks
+        } else {
+            vec![Sink::Postgres]
+        };
+        let store_raw_advertisements =
+            args.store_raw_advertisements || file.output.store_raw_advertisements;
+        if store_raw_advertisements && !sinks.contains(&Sink::Postgres) {
+            bail!("storing raw advertisements requires the postgres output");
+        }
+
+        Ok(Config {
+            timezone: args
+                .timezone
+                .or(file.timezone)
+                .ok_or_else(|| anyhow!("timezone is not set: use --timezone, TZ or config"))?,
+            source: args.source.or(file.source).or_else(hostname),
+            database_url: args.database_url.or(file.database.url).ok_or_else(|| {
+                anyhow!("database URL is not set: use --database-url, DATABASE_URL or config")
+            })?,
+            read_database_url: args.read_database_url.or(file.database.read_url),
+            device_refresh_interval,
+            adapters: if args.adapters.is_empty() {
+                file_adapters
+            } else {
+                args.adapters
+            },
+            device_filter: DeviceFilter {
+                only: if args.only_devices.is_empty() {
+                    file_only_devices
+                } else {
+                    args.only_devices
+                },
+                exclude: if args.exclude_devices.is_empty() {
+                    file_exclude_devices
+                } else {
+                    args.exclude_devices
+                },
+            },
+            promiscuous: args.promiscuous || file.scan.promiscuous,
+            interval: TimeDelta::from_std(interval).context("interval is too large")?,
+            window: TimeDelta::from_std(window).context("window is too large")?,
+            scan_timeout,
+            stale_after: TimeDelta::from_std(
+                args.stale_after
+                    .or(file.scan.stale_after)
+                    .unwrap_or(DEFAULT_STALE_AFTER),
+            )
+            .context("stale_after is too large")?,
+            gatt_fallback_after,
+            decoder_overrides,
+            max_deltas: MaxDeltas {
+                temperature_celsius: args
+                    .max_temperature_delta
+                    .or(file.outliers.max_temperature_delta),
+                humidity_percent: args.max_humidity_delta.or(file.outliers.max_humidity_delta),
+                co2_ppm: args.max_co2_delta.or(file.outliers.max_co2_delta),
+            },
+            aggregation: Strategies {
+                prefer: args.prefer.or(file.aggregation.prefer).unwrap_or_default(),
+                temperature_celsius: args
+                    .aggregate_temperature
+                    .or(file.aggregation.temperature)
+                    .unwrap_or_default(),
+                humidity_percent: args
+                    .aggregate_humidity
+                    .or(file.aggregation.humidity)
+                    .unwrap_or_default(),
+                co2_ppm: args
+                    .aggregate_co2
+                    .or(file.aggregation.co2)
+                    .unwrap_or_default(),
+                light_level: args
+                    .aggregate_light_level
+                    .or(file.aggregation.light_level)
+                    .unwrap_or_default(),
+            },
+            flush_interval,
+            max_buffer_age: args
+                .max_buffer_age
+                .or(file.output.max_buffer_age)
+                .map(TimeDelta::from_std)
+                .transpose()
+                .context("max_buffer_age is too large")?,
+            max_buffer_size: args
+                .max_buffer_size
+                .or(file.output.max_buffer_size)
+                .unwrap_or(DEFAULT_MAX_BUFFER_SIZE),
+            queue_capacity,
+            queue_policy: args
+                .queue_policy
+                .or(file.output.queue_policy)
+                .unwrap_or_default(),
+            sinks,
+            store_raw_advertisements,
+            spool_path: args.spool_path.or(file.output.spool_path),
+            dry_run: args.dry_run,
+            capture_size: args
+                .capture_size
+                .or(file.capture.size)
+                .unwrap_or(DEFAULT_CAPTURE_SIZE),
+            capture_path: args
+                .capture_path
+                .or(file.capture.path)
+                .unwrap_or_else(|| std::env::temp_dir().join(DEFAULT_CAPTURE_PATH)),
+            record_path: args.record_path.or(file.record.path),
+            replay_path: args.replay_path,
+            log_level: args.log_level.or(file.log.level),
+            log_format: args.log_format.or(file.log.format).unwrap_or_default(),
+            metrics_addr: args.metrics_addr.or(file.metrics.addr),
+            mqtt_url: args.mqtt_url.or(file.mqtt.url),
+            mqtt_topic: args
+                .mqtt_topic
+                .or(file.mqtt.topic)
+                .unwrap_or_else(|| DEFAULT_MQTT_TOPIC.to_string()),
+            mqtt_discovery_prefix: args
+                .mqtt_discovery_prefix
+                .or(file.mqtt.discovery_prefix)
+                .unwrap_or_else(|| DEFAULT_MQTT_DISCOVERY_PREFIX.to_string()),
+            http_addr: args.http_addr.or(file.http.addr),
+            cloud,
+        })
+    }
+
+    /// The part of the config the ingest engine uses.
+    pub fn settings(&self) -> Settings {
+        Settings {
+            timezone: self.timezone,
+            interval: self.interval,
+            window: self.window,
+            aggregation: self.aggregation,
+            source: self.source.clone(),
+            flush_interval: self.flush_interval,
+            max_buffer_age: self.max_buffer_age,
+            max_buffer_size: self.max_buffer_size,
+            queue_capacity: self.queue_capacity,
+            queue_policy: self.queue_policy,
+        }
+    }
+
+    /// Takes the settings of `new` that can change at runtime and keeps the current value of the
+    /// rest, which need a restart. Returns the merged config and the names of the settings that
+    /// changed but were kept, or an error if the merged config is invalid, e.g. a new interval
+    /// that the kept cloud poll interval is not a multiple of.
+    pub fn reload(&self, mut new: Config) -> Result<(Config, Vec<&'static str>)> {
+        let mut ignored = Vec::new();
+
+        macro_rules! keep {
+            ($($field:ident),* $(,)?) => {
+                $(
+                    if new.$field != self.$field {
+                        ignored.push(stringify!($field));
+                        new.$field = self.$field.clone();
+                    }
+                )*
+            };
+        }
+        keep!(
+            database_url,
+            read_database_url,
+            adapters,
+            promiscuous,
+            sinks,
+            store_raw_advertisements,
+            spool_path,
+            dry_run,
+            capture_size,
+            record_path,
+            replay_path,
+            log_format,
+            metrics_addr,
+            mqtt_url,
+            mqtt_topic,
+            mqtt_discovery_prefix,
+            http_addr,
+            cloud,
+        );
+
+        if let Some(cloud) = &new.cloud {
+            check_poll_interval(cloud.poll_interval.to_std()?, new.interval.to_std()?)?;
+        }
+
+        Ok((new, ignored))
+    }
+}
+
+/// The cloud devices of the command line, or else of the config file. Both the token and the secret
+/// are required once there are any.
+fn cloud_config(
+    args: &Args,
+    file: CloudConfigFile,
+    interval: Duration,
+) -> Result<Option<CloudConfig>> {
+    let devices = if !args.cloud_devices.is_empty() {
+        args.cloud_devices.clone()
+    } else {
+        file.devices
+            .iter()
+            .map(|device| {
+                device
+                    .parse()
+                    .with_context(|| format!("invalid cloud device: {device}"))
+            })
+            .collect::<Result<_>>()?
+    };
+    if devices.is_empty() {
+        return Ok(None);
+    }
+
+    let (Some(token), Some(secret)) = (
+        args.switchbot_token.clone().or(file.token),
+        args.switchbot_secret.clone().or(file.secret),
+    ) else {
+        bail!("cloud devices require a SwitchBot token and secret");
+    };
+
+    let poll_interval = args
+        .cloud_poll_interval
+        .or(file.poll_interval)
+        .unwrap_or(DEFAULT_CLOUD_POLL_INTERVAL);
+    check_poll_interval(poll_interval, interval)?;
+
+    Ok(Some(CloudConfig {
+        token,
+        secret,
+        devices,
+        poll_interval: TimeDelta::from_std(poll_interval)
+            .context("cloud poll interval is too large")?,
+    }))
+}
+
+/// The cloud devices are polled at the start of a bucket, so every poll must fall on one.
+fn check_poll_interval(poll_interval: Duration, interval: Duration) -> Result<()> {
+    if poll_interval.is_zero() || !poll_interval.as_nanos().is_multiple_of(interval.as_nanos()) {
+        bail!(
+            "cloud poll interval must be a multiple of the interval: poll interval {}, interval {}",
+            humantime::format_duration(poll_interval),
+            humantime::format_duration(interval)
+        );
+    }
+
+    Ok(())
+}
+
+fn decoder_overrides(
+    decoders: BTreeMap<String, DecoderConfigFile>,
+) -> Result<HashMap<MacAddr6, DecoderOverride>> {
+    decoders
+        .into_iter()
+        .map(|(device, decoder)| {
+            let device_id = device
+                .parse()
+                .with_context(|| format!("invalid device in decoders: {device}"))?;
+            let device_type = decoder
+                .r#type
+                .map(|device_type| device_type.parse())
+                .transpose()
+                .with_context(|| format!("invalid decoder type for {device}"))?;
+
+            Ok((
+                device_id,
+                DecoderOverride {
+                    device_type,
+                    temperature_byte: decoder.temperature_byte,
+                    humidity_byte: decoder.humidity_byte,
+                    co2_byte: decoder.co2_byte,
+                },
+            ))
+        })
+        .collect()
+}
+
+fn parse_device_selectors(selectors: &[String]) -> Result<Vec<DeviceSelector>> {
+    selectors.iter().map(|selector| selector.parse()).collect()
+}
+
+/// The hostname on Linux, the default source.
+fn hostname() -> Option<String> {
+    let hostname = fs::read_to_string("/proc/sys/kernel/hostname").ok()?;
+    let hostname = hostname.trim();
+    (!hostname.is_empty()).then(|| hostname.to_string())
+}
+
+fn read_config_file(path: &Path) -> Result<ConfigFile> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("failed to read config file: {path:?}"))?;
+
+    toml::from_str(&content).with_context(|| format!("failed to parse config file: {path:?}"))
+}
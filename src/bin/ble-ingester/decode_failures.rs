@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use chrono_tz::Tz;
+use home_environments::switchbot::DeviceType;
+use macaddr::MacAddr6;
+use serde::Serialize;
+
+/// The failures of a device are logged at most this often; the ones in between are only counted.
+const LOG_INTERVAL: TimeDelta = TimeDelta::minutes(1);
+
+#[derive(Debug)]
+struct DeviceFailures {
+    device_name: String,
+    device_type: DeviceType,
+    total: u64,
+    consecutive: u32,
+    last_error: String,
+    last_failed_at: DateTime<Utc>,
+    logged_at: Option<DateTime<Utc>>,
+    /// Failures since `logged_at` that were not logged.
+    suppressed: u64,
+}
+
+/// The outcome of [`DecodeFailures::record`].
+#[derive(Debug)]
+pub struct Recorded {
+    /// Failures since the last advertisement of the device that decoded.
+    pub consecutive: u32,
+    /// `Some` with the number of failures that were not logged since the last one, if this one
+    /// should be logged.
+    pub log: Option<u64>,
+}
+
+/// The decode failures of a device since the start, as served over HTTP.
+#[derive(Debug, Serialize)]
+pub struct DecodeFailuresSummary {
+    pub device_id: String,
+    pub device_name: String,
+    pub device_type: &'static str,
+    pub total: u64,
+    pub consecutive: u32,
+    pub last_error: String,
+    pub last_failed_at: DateTime<Tz>,
+}
+
+/// Counts the advertisements that failed to decode per device, keeps the last error and limits
+/// how often they are logged, so that one chatty device does not drown the rest of the log.
+#[derive(Debug, Default)]
+pub struct DecodeFailures {
+    devices: BTreeMap<MacAddr6, DeviceFailures>,
+}
+
+impl DecodeFailures {
+    pub fn record(
+        &mut self,
+        device_id: MacAddr6,
+        device_name: &str,
+        device_type: DeviceType,
+        error: &anyhow::Error,
+        now: DateTime<Utc>,
+    ) -> Recorded {
+        let failures = self
+            .devices
+            .entry(device_id)
+            .or_insert_with(|| DeviceFailures {
+                device_name: device_name.to_string(),
+                device_type,
+                total: 0,
+                consecutive: 0,
+                last_error: String::new(),
+                last_failed_at: now,
+                logged_at: None,
+                suppressed: 0,
+            });
+        failures.device_name = device_name.to_string();
+        failures.device_type = device_type;
+        failures.total += 1;
+        failures.consecutive += 1;
+        failures.last_error = format!("{error:#}");
+        failures.last_failed_at = now;
+
+        let log = match failures.logged_at {
+            Some(logged_at) if now - logged_at < LOG_INTERVAL => {
+                failures.suppressed += 1;
+                None
+            }
+            _ => {
+                failures.logged_at = Some(now);
+                Some(std::mem::take(&mut failures.suppressed))
+            }
+        };
+
+        Recorded {
+            consecutive: failures.consecutive,
+            log,
+        }
+    }
+
+    /// Resets the consecutive failures of `device_id` after an advertisement decoded.
+    pub fn record_success(&mut self, device_id: MacAddr6) {
+        if let Some(failures) = self.devices.get_mut(&device_id) {
+            failures.consecutive = 0;
+        }
+    }
+
+    /// Every device that failed to decode at least once, ordered by MAC address.
+    pub fn summaries(&self, timezone: Tz) -> Vec<DecodeFailuresSummary> {
+        self.devices
+            .iter()
+            .map(|(device_id, failures)| DecodeFailuresSummary {
+                device_id: device_id.to_string(),
+                device_name: failures.device_name.clone(),
+                device_type: failures.device_type.as_str(),
+                total: failures.total,
+                consecutive: failures.consecutive,
+                last_error: failures.last_error.clone(),
+                last_failed_at: failures.last_failed_at.with_timezone(&timezone),
+            })
+            .collect()
+    }
+}
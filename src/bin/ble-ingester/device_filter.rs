@@ -0,0 +1,60 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::{Error, Result, bail};
+use home_environments::switchbot::Device;
+use macaddr::MacAddr6;
+
+/// Identifies a device by its MAC address or, if the value is not one, by its name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceSelector {
+    MacAddress(MacAddr6),
+    Name(String),
+}
+
+impl DeviceSelector {
+    fn matches(&self, device: &Device) -> bool {
+        match self {
+            DeviceSelector::MacAddress(mac_address) => device.id == *mac_address,
+            DeviceSelector::Name(name) => device.name == *name,
+        }
+    }
+}
+
+impl FromStr for DeviceSelector {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            bail!("device selector is empty");
+        }
+
+        match s.parse::<MacAddr6>() {
+            Ok(mac_address) => Ok(DeviceSelector::MacAddress(mac_address)),
+            Err(_) => Ok(DeviceSelector::Name(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for DeviceSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceSelector::MacAddress(mac_address) => write!(f, "{mac_address}"),
+            DeviceSelector::Name(name) => f.write_str(name),
+        }
+    }
+}
+
+/// Narrows the registered devices down for a debugging session without touching the registry.
+#[derive(Debug, Clone, Default)]
+pub struct DeviceFilter {
+    pub only: Vec<DeviceSelector>,
+    pub exclude: Vec<DeviceSelector>,
+}
+
+impl DeviceFilter {
+    /// A device passes if it matches one of `only` (or `only` is empty) and none of `exclude`.
+    pub fn matches(&self, device: &Device) -> bool {
+        (self.only.is_empty() || self.only.iter().any(|s| s.matches(device)))
+            && !self.exclude.iter().any(|s| s.matches(device))
+    }
+}
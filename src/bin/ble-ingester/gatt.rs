@@ -0,0 +1,76 @@
+use std::time::Duration;
+
+use anyhow::{Context as _, Result, anyhow, bail};
+use btleplug::{
+    api::{Peripheral as _, WriteType},
+    platform::Peripheral,
+};
+use home_environments::ingest::DecodedMeasurement;
+use tokio_stream::StreamExt as _;
+use tracing::debug;
+
+use crate::ble::switchbot::{
+    SWITCHBOT_GATT_NOTIFY_CHARACTERISTIC_UUID, SWITCHBOT_GATT_READ_MEASUREMENT_COMMAND,
+    SWITCHBOT_GATT_SERVICE_UUID, SWITCHBOT_GATT_WRITE_CHARACTERISTIC_UUID, decode_gatt_response,
+};
+
+/// Upper bound for connecting, asking and waiting for the answer. The read runs beside the scan,
+/// but no other device can fall back to GATT until it is over.
+const GATT_READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Connects to `peripheral` and asks it for its current measurement, for devices whose
+/// advertisements cannot be decoded. The connection is closed afterwards, even on failure.
+pub async fn read_measurement(peripheral: &Peripheral) -> Result<DecodedMeasurement> {
+    let result = tokio::time::timeout(GATT_READ_TIMEOUT, request(peripheral))
+        .await
+        .unwrap_or_else(|_| Err(anyhow!("timed out")));
+
+    if let Err(err) = peripheral.disconnect().await {
+        debug!(error = format!("{err:#}"), "failed to disconnect");
+    }
+
+    result
+}
+
+async fn request(peripheral: &Peripheral) -> Result<DecodedMeasurement> {
+    peripheral.connect().await.context("failed to connect")?;
+    peripheral
+        .discover_services()
+        .await
+        .context("failed to discover services")?;
+
+    let characteristics = peripheral.characteristics();
+    let find = |uuid| {
+        characteristics
+            .iter()
+            .find(|c| c.service_uuid == SWITCHBOT_GATT_SERVICE_UUID && c.uuid == uuid)
+            .with_context(|| format!("characteristic not found: {uuid}"))
+    };
+    let write = find(SWITCHBOT_GATT_WRITE_CHARACTERISTIC_UUID)?;
+    let notify = find(SWITCHBOT_GATT_NOTIFY_CHARACTERISTIC_UUID)?;
+
+    let mut notifications = peripheral
+        .notifications()
+        .await
+        .context("failed to get notifications")?;
+    peripheral
+        .subscribe(notify)
+        .await
+        .context("failed to subscribe")?;
+    peripheral
+        .write(
+            write,
+            &SWITCHBOT_GATT_READ_MEASUREMENT_COMMAND,
+            WriteType::WithResponse,
+        )
+        .await
+        .context("failed to send command")?;
+
+    while let Some(notification) = notifications.next().await {
+        if notification.uuid == SWITCHBOT_GATT_NOTIFY_CHARACTERISTIC_UUID {
+            return decode_gatt_response(&notification.value);
+        }
+    }
+
+    bail!("connection closed before the response")
+}
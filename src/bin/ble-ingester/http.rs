@@ -0,0 +1,157 @@
+use std::{collections::BTreeMap, net::SocketAddr, sync::Arc};
+
+use anyhow::{Context as _, Result};
+use axum::{
+    Router,
+    extract::{
+        State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    response::{Json, Response},
+    routing::get,
+};
+use chrono::DateTime;
+use chrono_tz::Tz;
+use serde::Serialize;
+use tokio::{
+    net::TcpListener,
+    sync::{Mutex, broadcast, watch},
+};
+use tracing::{debug, warn};
+
+use crate::{
+    config::Config,
+    decode_failures::{DecodeFailures, DecodeFailuresSummary},
+};
+
+/// How many live measurements a slow WebSocket client may fall behind before it misses some.
+pub const LIVE_CHANNEL_CAPACITY: usize = 256;
+
+/// An accepted measurement as served over HTTP, published as soon as it is decoded.
+#[derive(Debug, Clone, Serialize)]
+pub struct LiveMeasurement {
+    pub device_id: String,
+    pub device_name: String,
+    pub received_at: DateTime<Tz>,
+    pub bucket: DateTime<Tz>,
+    pub temperature_celsius: f32,
+    pub humidity_percent: u8,
+    pub co2_ppm: Option<u16>,
+    pub light_level: Option<u8>,
+    pub rssi_dbm: Option<i16>,
+}
+
+#[derive(Debug, Clone)]
+struct ServerState {
+    live: broadcast::Sender<LiveMeasurement>,
+    /// The most recent measurement of every device, keyed by MAC address.
+    latest: Arc<Mutex<BTreeMap<String, LiveMeasurement>>>,
+    decode_failures: Arc<Mutex<DecodeFailures>>,
+    config: watch::Receiver<Arc<Config>>,
+}
+
+/// Binds `addr` so that a port in use fails the startup, then returns the server to spawn.
+pub async fn bind(
+    addr: SocketAddr,
+    live: broadcast::Sender<LiveMeasurement>,
+    decode_failures: Arc<Mutex<DecodeFailures>>,
+    config: watch::Receiver<Arc<Config>>,
+) -> Result<impl Future<Output = ()>> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("failed to listen on {addr}"))?;
+
+    let state = ServerState {
+        latest: Arc::default(),
+        live,
+        decode_failures,
+        config,
+    };
+    let router = Router::new()
+        .route("/latest", get(latest))
+        .route("/ws", get(stream))
+        .route("/decode-failures", get(decode_failure_summaries))
+        .with_state(state.clone());
+    tokio::spawn(keep_latest(state.live.subscribe(), state.latest));
+
+    Ok(async move {
+        if let Err(err) = axum::serve(listener, router).await {
+            warn!(error = format!("{err:#}"), "HTTP server stopped");
+        }
+    })
+}
+
+async fn keep_latest(
+    mut measurements: broadcast::Receiver<LiveMeasurement>,
+    latest: Arc<Mutex<BTreeMap<String, LiveMeasurement>>>,
+) {
+    loop {
+        match measurements.recv().await {
+            Ok(measurement) => {
+                latest
+                    .lock()
+                    .await
+                    .insert(measurement.device_id.clone(), measurement);
+            }
+            Err(broadcast::error::RecvError::Lagged(_)) => {}
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+/// `GET /latest`: the most recent measurement of every device heard since the start, ordered by
+/// MAC address, without querying the database.
+async fn latest(State(state): State<ServerState>) -> Json<Vec<LiveMeasurement>> {
+    Json(state.latest.lock().await.values().cloned().collect())
+}
+
+/// `GET /decode-failures`: the devices whose advertisements failed to decode since the start, with
+/// their counts and last error, ordered by MAC address.
+async fn decode_failure_summaries(
+    State(state): State<ServerState>,
+) -> Json<Vec<DecodeFailuresSummary>> {
+    let timezone = state.config.borrow().timezone;
+    Json(state.decode_failures.lock().await.summaries(timezone))
+}
+
+/// `GET /ws`: streams every accepted measurement as a JSON text message.
+async fn stream(ws: WebSocketUpgrade, State(state): State<ServerState>) -> Response {
+    let measurements = state.live.subscribe();
+    ws.on_upgrade(|socket| send_measurements(socket, measurements))
+}
+
+async fn send_measurements(
+    mut socket: WebSocket,
+    mut measurements: broadcast::Receiver<LiveMeasurement>,
+) {
+    loop {
+        tokio::select! {
+            measurement = measurements.recv() => {
+                let measurement = match measurement {
+                    Ok(measurement) => measurement,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        debug!(skipped, "WebSocket client is too slow, skipped measurements");
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+
+                let text = match serde_json::to_string(&measurement) {
+                    Ok(text) => text,
+                    Err(err) => {
+                        warn!(error = format!("{err:#}"), "failed to serialize measurement");
+                        continue;
+                    }
+                };
+                if socket.send(Message::Text(text.into())).await.is_err() {
+                    break;
+                }
+            }
+            // Incoming messages are ignored; the stream ends when the client goes away.
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
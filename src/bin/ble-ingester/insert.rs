@@ -0,0 +1,109 @@
+use std::time::Duration;
+
+use anyhow::{Error, Result};
+use home_environments::db::WriteBatch;
+use sqlx::PgPool;
+use tracing::{error, warn};
+
+use crate::telemetry;
+
+const MAX_ATTEMPTS: u32 = 4;
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Inserts `batch`, retrying with exponential backoff on connection or server errors.
+///
+/// If the database keeps rejecting the data itself (e.g. a constraint violation), the batch is
+/// split in halves until the offending rows are isolated. Those rows are logged and dropped so
+/// that they do not block the rest. Returns the number of dropped rows.
+pub async fn commit(pool: &PgPool, batch: &WriteBatch) -> Result<usize> {
+    let mut backoff = INITIAL_BACKOFF;
+    let mut attempt = 1;
+    let err = loop {
+        match batch.commit(pool).await {
+            Ok(()) => return Ok(0),
+            Err(err) if attempt >= MAX_ATTEMPTS || is_data_error(&err) => break err,
+            Err(err) => {
+                warn!(
+                    error = format!("{err:#}"),
+                    attempt,
+                    ?backoff,
+                    "failed to insert batch, retrying"
+                );
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+        }
+    };
+
+    if !is_data_error(&err) {
+        return Err(err);
+    }
+
+    warn!(
+        error = format!("{err:#}"),
+        rows = batch.len(),
+        "database rejected batch, isolating offending rows"
+    );
+    isolate(pool, batch).await
+}
+
+/// Bisects `batch` without retrying and returns the number of rows dropped.
+async fn isolate(pool: &PgPool, batch: &WriteBatch) -> Result<usize> {
+    match batch.commit(pool).await {
+        Ok(()) => Ok(0),
+        Err(err) if !is_data_error(&err) => Err(err),
+        Err(err) if batch.len() == 1 => {
+            error!(
+                error = format!("{err:#}"),
+                switchbot_measurements = ?batch.switchbot_measurements(),
+                raw_advertisements = ?batch.raw_advertisements(),
+                "dropping row rejected by the database"
+            );
+            telemetry::record_rejected_rows(1);
+            Ok(1)
+        }
+        Err(_) => {
+            let (left, right) = split(batch);
+            let left = Box::pin(isolate(pool, &left)).await?;
+            let right = Box::pin(isolate(pool, &right)).await?;
+            Ok(left + right)
+        }
+    }
+}
+
+/// Splits the rows of `batch`, measurements first, into two halves that are both non-empty when it
+/// has at least two rows.
+fn split(batch: &WriteBatch) -> (WriteBatch, WriteBatch) {
+    let measurements = batch.switchbot_measurements();
+    let advertisements = batch.raw_advertisements();
+    let mid = batch.len() / 2;
+
+    let (left_measurements, right_measurements) =
+        measurements.split_at(mid.min(measurements.len()));
+    let (left_advertisements, right_advertisements) =
+        advertisements.split_at(mid.saturating_sub(measurements.len()));
+
+    let mut left = WriteBatch::new();
+    left.extend_switchbot_measurements(left_measurements.iter().cloned());
+    left.extend_raw_advertisements(left_advertisements.iter().cloned());
+
+    let mut right = WriteBatch::new();
+    right.extend_switchbot_measurements(right_measurements.iter().cloned());
+    right.extend_raw_advertisements(right_advertisements.iter().cloned());
+
+    (left, right)
+}
+
+/// Whether the database rejected the data itself (SQLSTATE class 22 or 23), as opposed to a
+/// connection or server problem that retrying may resolve.
+fn is_data_error(err: &Error) -> bool {
+    err.chain()
+        .any(|cause| match cause.downcast_ref::<sqlx::Error>() {
+            Some(sqlx::Error::Database(db_err)) => db_err
+                .code()
+                .is_some_and(|code| code.starts_with("22") || code.starts_with("23")),
+            _ => false,
+        })
+}
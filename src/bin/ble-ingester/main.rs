@@ -1,59 +1,289 @@
+mod adapter;
 mod args;
 mod ble;
+mod capture;
+mod clock;
+mod cloud;
+mod config;
+mod decode_failures;
+mod device_filter;
+mod gatt;
+mod http;
+mod insert;
+mod mqtt;
+mod outlier;
+mod payload_cache;
+mod sink;
+mod spool;
+mod systemd;
+mod telemetry;
 
 use std::{
-    collections::{BTreeMap, HashMap},
+    collections::{HashMap, HashSet},
+    pin::Pin,
     process::ExitCode,
-    sync::Arc,
-    time::Duration,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
 };
 
-use anyhow::{Context as _, Result, anyhow};
+use anyhow::{Context as _, Result, bail};
 use args::Args;
 use btleplug::{
-    api::{Central, CentralEvent, Manager as _, Peripheral, ScanFilter},
-    platform::Manager,
+    api::{Central, CentralEvent, Manager as _, Peripheral as _, ScanFilter},
+    platform::{Adapter, Manager, Peripheral, PeripheralId},
 };
-use chrono::{DateTime, DurationRound, TimeDelta, Utc};
-use chrono_tz::Tz;
+use chrono::{DateTime, Utc};
 use clap::Parser as _;
 use home_environments::{
-    db::{get_switchbot_devices, new_pool},
-    switchbot::{Device, Measurement},
+    db::{get_switchbot_devices, new_pools},
+    ingest::{
+        AcceptedMeasurement, BoxFuture, DecodedMeasurement, Engine, Event, Preference,
+        ReplaySource, Settings, Source,
+    },
+    logging::{self, LogHandle},
+    raw_advertisement::RawAdvertisement,
+    switchbot::{Device, DeviceType},
 };
 use indexmap::IndexMap;
 use macaddr::MacAddr6;
-use tokio::sync::Mutex;
-use tokio_stream::StreamExt;
+use sqlx::PgPool;
+use tokio::sync::{Mutex, RwLock, broadcast, mpsc, watch};
+use tokio_stream::{Stream, StreamExt};
+use tracing::{Instrument as _, Span, debug, debug_span, error, field, info, info_span, warn};
 
-use home_environments::db::bulk_insert_switchbot_measurements;
+use crate::{
+    adapter::select_adapters,
+    ble::switchbot::{
+        DecoderOverride, decode_advertisement, mac_address_from_manufacturer_data, service_uuid,
+        supports_gatt_read,
+    },
+    capture::{Capture, Recorder},
+    clock::Clock,
+    cloud::CloudSource,
+    config::Config,
+    decode_failures::DecodeFailures,
+    device_filter::DeviceFilter,
+    http::LiveMeasurement,
+    mqtt::MqttSink,
+    outlier::OutlierFilter,
+    payload_cache::PayloadCache,
+    sink::{NdjsonSink, PostgresSink, Sink},
+    spool::Spool,
+};
+
+const STALENESS_CHECK_INTERVAL: Duration = Duration::from_mins(1);
 
-use crate::ble::switchbot::{DecodedMeasurement, decode_ble_data, decode_manufacturer_data};
+/// State shared between the scanning tasks and the background tasks. The measurement buffer itself
+/// is owned by the ingest engine.
+struct State {
+    /// Replaced when the config file is reloaded on SIGHUP.
+    config: watch::Sender<Arc<Config>>,
+    /// The part of `config` the engine uses, replaced along with it.
+    settings: watch::Sender<Settings>,
+    /// Shared with the ndjson sink.
+    devices: Arc<RwLock<IndexMap<MacAddr6, Device>>>,
+    last_event_at: Mutex<Instant>,
+    /// When each device last sent an advertisement that was decoded successfully.
+    last_seen: Mutex<HashMap<MacAddr6, DateTime<Utc>>>,
+    /// Shared with the HTTP server.
+    decode_failures: Arc<Mutex<DecodeFailures>>,
+    outliers: Mutex<OutlierFilter>,
+    scan_filter: ScanFilter,
+    capture: Mutex<Capture>,
+    recorder: Option<Mutex<Recorder>>,
+    /// Shared by the scanning tasks so that a jump is noticed, and reported to the aggregator, once.
+    clock: Mutex<Clock>,
+    clock_sane: AtomicBool,
+    /// Set while a GATT fallback read is connected to a device, so that only one runs at a time.
+    gatt_reading: AtomicBool,
+    mqtt: Option<MqttSink>,
+    /// Accepted measurements for the WebSocket clients, sent regardless of whether any listen.
+    live: broadcast::Sender<LiveMeasurement>,
+}
+
+impl State {
+    fn config(&self) -> Arc<Config> {
+        self.config.borrow().clone()
+    }
+}
 
 #[tokio::main]
 async fn main() -> ExitCode {
-    if let Err(e) = run().await {
-        eprintln!("{e:#}");
+    let args = Args::parse();
+    let config = match Config::load(args.clone()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("failed to load config: {e:#}");
+            return ExitCode::from(1);
+        }
+    };
+
+    let log_handle = match logging::init(config.log_level.as_deref(), config.log_format) {
+        Ok(log_handle) => log_handle,
+        Err(e) => {
+            eprintln!("failed to initialize logging: {e:#}");
+            return ExitCode::from(1);
+        }
+    };
+
+    if let Err(e) = run(args, config, log_handle).await {
+        error!("{e:#}");
         return ExitCode::from(1);
     }
 
     ExitCode::from(0)
 }
 
-async fn run() -> Result<()> {
-    let args = Args::parse();
+async fn run(args: Args, config: Config, log_handle: LogHandle) -> Result<()> {
+    if let Some(addr) = config.metrics_addr {
+        telemetry::install(addr)?;
+        info!(%addr, "serving metrics");
+    }
 
-    let pool = new_pool(&args.database_url)
+    let pools = new_pools(&config.database_url, config.read_database_url.as_deref())
         .await
         .context("failed to connect to database")?;
 
-    let devices: IndexMap<MacAddr6, Device> = get_switchbot_devices(&pool)
+    let devices = load_devices(&pools.read, &config.device_filter).await?;
+    if devices.is_empty() {
+        warn!("no devices to collect measurements from");
+    }
+
+    let scan_filter = if config.promiscuous {
+        ScanFilter::default()
+    } else {
+        scan_filter(devices.values())
+    };
+    debug!(services = ?scan_filter.services, "built scan filter");
+
+    let mqtt = config
+        .mqtt_url
+        .as_deref()
+        .map(|url| MqttSink::connect(url, &config.mqtt_topic, &config.mqtt_discovery_prefix))
+        .transpose()?;
+
+    let recorder = match config.record_path.clone() {
+        Some(path) => {
+            info!(?path, "recording advertisements");
+            Some(Mutex::new(Recorder::open(path).await?))
+        }
+        None => None,
+    };
+
+    let state = Arc::new(State {
+        scan_filter,
+        mqtt,
+        live: broadcast::Sender::new(http::LIVE_CHANNEL_CAPACITY),
+        last_event_at: Mutex::new(Instant::now()),
+        last_seen: Mutex::new(HashMap::new()),
+        decode_failures: Arc::default(),
+        outliers: Mutex::new(OutlierFilter::default()),
+        capture: Mutex::new(Capture::new(config.capture_size)),
+        recorder,
+        clock: Mutex::new(Clock::default()),
+        clock_sane: AtomicBool::new(true),
+        gatt_reading: AtomicBool::new(false),
+        devices: Arc::new(RwLock::new(devices)),
+        settings: watch::Sender::new(config.settings()),
+        config: watch::Sender::new(Arc::new(config)),
+    });
+
+    // Whether the watchdog is tied to BLE events, which only arrive while scanning.
+    let mut scanning = false;
+    let sources: Vec<Box<dyn Source>> = match &state.config().replay_path {
+        Some(path) => {
+            info!(?path, "replaying captured advertisements");
+            vec![Box::new(ReplaySource::new(
+                path,
+                state.settings.subscribe(),
+                replay_decoder(
+                    state.devices.read().await.clone(),
+                    state.config().decoder_overrides.clone(),
+                ),
+            ))]
+        }
+        None => {
+            let mut sources = ble_sources(&state).await?;
+            scanning = !sources.is_empty();
+            if let Some(cloud) = state.config().cloud.clone() {
+                sources.push(Box::new(CloudSource::new(cloud, state.clone())?));
+            }
+            sources
+        }
+    };
+
+    let mut engine = Engine::new(state.settings.subscribe());
+    let config = state.config();
+    if !config.dry_run {
+        if config.sinks.contains(&Sink::Postgres) {
+            engine = engine.sink(PostgresSink {
+                pool: pools.write.clone(),
+                spool: config.spool_path.clone().map(Spool::new),
+            });
+        }
+        if config.sinks.contains(&Sink::Ndjson) {
+            engine = engine.sink(NdjsonSink {
+                devices: state.devices.clone(),
+                settings: state.settings.subscribe(),
+            });
+        }
+    }
+
+    tokio::spawn(refresh_devices(state.clone(), pools.read.clone()));
+    tokio::spawn(check_staleness(state.clone()));
+    if let Some(addr) = config.http_addr {
+        tokio::spawn(
+            http::bind(
+                addr,
+                state.live.clone(),
+                state.decode_failures.clone(),
+                state.config.subscribe(),
+            )
+            .await?,
+        );
+        info!(%addr, "serving HTTP");
+    }
+    if state.mqtt.is_some() {
+        tokio::spawn(announce_devices(state.clone()));
+    }
+    #[cfg(unix)]
+    tokio::spawn(dump_capture_on_signal(state.clone()));
+    #[cfg(unix)]
+    tokio::spawn(reload_on_signal(state.clone(), args, log_handle));
+    #[cfg(not(unix))]
+    let _ = (args, log_handle);
+
+    if let Some(timeout) = systemd::watchdog_timeout() {
+        let scan_timeout = config.scan_timeout;
+        if scanning && scan_timeout >= timeout {
+            bail!(
+                "the scan timeout must be shorter than WatchdogSec, so that a stalled scan is \
+                 restarted before systemd restarts the service: scan timeout {}, WatchdogSec {}",
+                humantime::format_duration(scan_timeout),
+                humantime::format_duration(timeout)
+            );
+        }
+        info!(?timeout, "systemd watchdog enabled");
+        tokio::spawn(watchdog(state.clone(), timeout, scanning));
+    }
+    systemd::notify_ready();
+
+    engine
+        .run(sources, async {
+            shutdown_signal()
+                .await
+                .context("failed to listen for shutdown signals")?;
+            systemd::notify_stopping();
+            Ok(())
+        })
         .await
-        .context("failed to get SwitchBot devices")?
-        .into_iter()
-        .map(|d| (d.id, d))
-        .collect();
+}
 
+/// Starts a scan on every selected adapter.
+async fn ble_sources(state: &Arc<State>) -> Result<Vec<Box<dyn Source>>> {
     let manager = Manager::new()
         .await
         .context("failed to initialize Bluetooth manager")?;
@@ -63,166 +293,769 @@ async fn run() -> Result<()> {
         .await
         .context("failed to get Bluetooth adapters")?;
 
-    let adapter = adapters
-        .into_iter()
-        .next()
-        .ok_or_else(|| anyhow!("no Bluetooth adapters found"))?;
-
-    adapter
-        .start_scan(ScanFilter::default())
+    let adapters = select_adapters(adapters, &state.config().adapters)
         .await
-        .context("failed to start BLE scan")?;
+        .context("failed to select Bluetooth adapters")?;
 
-    type Db = HashMap<MacAddr6, BTreeMap<DateTime<Tz>, (DateTime<Tz>, DecodedMeasurement)>>;
-    let db: Arc<Mutex<Db>> = Arc::new(Mutex::new(
-        devices.keys().map(|id| (*id, BTreeMap::new())).collect(),
-    ));
-
-    let mut events = adapter.events().await?;
-
-    let db_for_ingester = db.clone();
-    let ingester_handle = tokio::spawn(async move {
-        while let Some(event) = events.next().await {
-            let peripheral_id = match &event {
-                CentralEvent::DeviceDiscovered(id) | CentralEvent::DeviceUpdated(id) => id,
-                _ => continue,
-            };
-
-            let peripheral = match adapter.peripheral(peripheral_id).await {
-                Ok(p) => p,
-                Err(err) => {
-                    eprintln!("failed to get peripheral {peripheral_id}: {err:#}");
-                    continue;
-                }
-            };
+    let mut sources: Vec<Box<dyn Source>> = Vec::new();
+    for adapter in adapters {
+        let adapter_info = adapter
+            .adapter_info()
+            .await
+            .context("failed to get Bluetooth adapter info")?;
 
-            let measured_at = Utc::now().with_timezone(&args.timezone);
+        adapter
+            .start_scan(state.scan_filter.clone())
+            .await
+            .context("failed to start BLE scan")?;
+        info!(adapter = %adapter_info, "started BLE scan");
 
-            let Ok(rounded_measured_at) = measured_at.duration_round(TimeDelta::minutes(1)) else {
-                eprintln!("failed to round measured_at to 1 minute: {measured_at}");
-                continue;
-            };
+        let events = adapter.events().await?;
 
-            let diff = (measured_at - rounded_measured_at).num_milliseconds().abs();
-            if diff > TimeDelta::seconds(20).num_milliseconds() {
-                continue;
+        sources.push(Box::new(BleSource {
+            adapter,
+            adapter_info,
+            events,
+            state: state.clone(),
+        }));
+    }
+
+    Ok(sources)
+}
+
+/// Decodes replayed advertisements of `devices` the way live ones are, without the GATT fallback
+/// and the outlier filter.
+fn replay_decoder(
+    devices: IndexMap<MacAddr6, Device>,
+    decoder_overrides: HashMap<MacAddr6, DecoderOverride>,
+) -> impl FnMut(&RawAdvertisement) -> Result<Option<DecodedMeasurement>> + Send + 'static {
+    move |advertisement| {
+        let Some(device) = devices.get(&advertisement.device_id) else {
+            return Ok(None);
+        };
+
+        let mut decoded = decode_advertisement(
+            &device.r#type,
+            decoder_overrides.get(&advertisement.device_id),
+            &advertisement.manufacturer_data,
+            &advertisement.service_data,
+        )?;
+        decoded.calibrate(
+            device.temperature_offset_celsius,
+            device.humidity_offset_percent,
+        );
+        Ok(Some(decoded))
+    }
+}
+
+/// Scans with one adapter and sends the accepted advertisements of registered devices.
+struct BleSource {
+    adapter: Adapter,
+    adapter_info: String,
+    events: Pin<Box<dyn Stream<Item = CentralEvent> + Send>>,
+    state: Arc<State>,
+}
+
+impl Source for BleSource {
+    fn run(self: Box<Self>, events: mpsc::Sender<Event>) -> BoxFuture<'static, Result<()>> {
+        let span = info_span!("scan", adapter = %self.adapter_info);
+        Box::pin(
+            async move {
+                ingest_events(
+                    self.adapter,
+                    self.adapter_info,
+                    self.events,
+                    self.state,
+                    events,
+                )
+                .await;
+                Ok(())
             }
+            .instrument(span),
+        )
+    }
+}
+
+/// Whether the measurement should be read over GATT after `consecutive_failures` advertisements in
+/// a row that failed to decode, e.g. because the firmware truncated them. Claims the single GATT
+/// connection if so, which [`gatt_fallback`] releases.
+fn claim_gatt_fallback(
+    state: &State,
+    device_type: &DeviceType,
+    consecutive_failures: u32,
+    config: &Config,
+) -> bool {
+    let Some(threshold) = config.gatt_fallback_after else {
+        return false;
+    };
+    if !supports_gatt_read(device_type) || !consecutive_failures.is_multiple_of(threshold) {
+        return false;
+    }
+    if state.gatt_reading.swap(true, Ordering::Relaxed) {
+        debug!("skipped GATT read, another one is in progress");
+        return false;
+    }
+
+    true
+}
+
+/// Reads the measurement over GATT and releases the connection claimed by
+/// [`claim_gatt_fallback`].
+async fn gatt_fallback(state: &State, peripheral: &Peripheral) -> Option<DecodedMeasurement> {
+    let result = gatt::read_measurement(peripheral).await;
+    state.gatt_reading.store(false, Ordering::Relaxed);
+
+    match result {
+        Ok(m) => {
+            telemetry::record_gatt_read(true);
+            info!("read measurement over GATT");
+            Some(m)
+        }
+        Err(err) => {
+            telemetry::record_gatt_read(false);
+            warn!(
+                error = format!("{err:#}"),
+                "failed to read measurement over GATT"
+            );
+            None
+        }
+    }
+}
+
+/// Publishes the Home Assistant discovery configs of every device whenever the MQTT sink asks for
+/// them. Devices added or changed later are announced by `refresh_devices`.
+async fn announce_devices(state: Arc<State>) {
+    let Some(mqtt) = &state.mqtt else {
+        return;
+    };
+
+    loop {
+        mqtt.announcement_needed().await;
+
+        let devices = state.devices.read().await;
+        for device in devices.values() {
+            mqtt.announce(device).await;
+        }
+        debug!(
+            devices = devices.len(),
+            "announced devices to Home Assistant"
+        );
+    }
+}
+
+/// Writes the captured advertisements to the capture file whenever SIGUSR1 is received.
+#[cfg(unix)]
+async fn dump_capture_on_signal(state: Arc<State>) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut sigusr1 = match signal(SignalKind::user_defined1()) {
+        Ok(sigusr1) => sigusr1,
+        Err(err) => {
+            warn!(error = format!("{err:#}"), "failed to listen for SIGUSR1");
+            return;
+        }
+    };
 
-            let mac_address: MacAddr6 = peripheral.address().into_inner().into();
-            let Some(device) = devices.get(&mac_address) else {
+    while sigusr1.recv().await.is_some() {
+        let path = &state.config().capture_path;
+        match state.capture.lock().await.dump(path).await {
+            Ok(count) => info!(count, ?path, "dumped captured advertisements"),
+            Err(err) => error!(
+                error = format!("{err:#}"),
+                "failed to dump captured advertisements"
+            ),
+        }
+    }
+}
+
+/// Reloads the config file whenever SIGHUP is received. Settings that need a restart keep their
+/// current value; the scans and the buffered measurements are left untouched.
+#[cfg(unix)]
+async fn reload_on_signal(state: Arc<State>, args: Args, log_handle: LogHandle) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut sighup = match signal(SignalKind::hangup()) {
+        Ok(sighup) => sighup,
+        Err(err) => {
+            warn!(error = format!("{err:#}"), "failed to listen for SIGHUP");
+            return;
+        }
+    };
+
+    while sighup.recv().await.is_some() {
+        let new = match Config::load(args.clone()) {
+            Ok(new) => new,
+            Err(err) => {
+                error!(
+                    error = format!("{err:#}"),
+                    "failed to reload config, keeping the current one"
+                );
                 continue;
-            };
-
-            let maybe_properties = match peripheral.properties().await {
-                Ok(p) => p,
-                Err(err) => {
-                    eprintln!(
-                        "failed to get BLE peripheral properties: {peripheral_id} ({mac_address}): {err:#}"
-                    );
-                    continue;
-                }
-            };
+            }
+        };
 
-            let Some(properties) = maybe_properties else {
-                eprintln!(
-                    "BLE peripheral properties not available: {peripheral_id} ({mac_address})"
+        let current = state.config();
+        let (new, ignored) = match current.reload(new) {
+            Ok(reloaded) => reloaded,
+            Err(err) => {
+                error!(
+                    error = format!("{err:#}"),
+                    "failed to reload config, keeping the current one"
                 );
                 continue;
-            };
+            }
+        };
+        if !ignored.is_empty() {
+            warn!(
+                ?ignored,
+                "some changed settings only take effect after a restart"
+            );
+        }
+        if new.log_level != current.log_level
+            && let Err(err) = log_handle.set_level(new.log_level.as_deref())
+        {
+            error!(error = format!("{err:#}"), "failed to change log level");
+        }
 
-            let decoded = match decode_ble_data(
-                &properties.manufacturer_data,
-                &properties.service_data,
-            )
-            .inspect_err(|_e| {
-                // eprintln!("failed to decode BLE service data, falling back to manufacturer data: {peripheral_id} ({mac_address}) {err:#}");
-            })
-            .or_else(|_| decode_manufacturer_data(&device.r#type, &properties.manufacturer_data))
-            {
-                Ok(m) => m,
-                Err(err) => {
-                    eprintln!(
-                        "failed to decode manufacturer data: {peripheral_id} ({mac_address}): {err:#}"
-                    );
-                    continue;
-                }
-            };
+        state.settings.send_replace(new.settings());
+        state.config.send_replace(Arc::new(new));
+        info!("reloaded config");
+    }
+}
+
+/// Waits for SIGINT or, on Unix, SIGTERM.
+async fn shutdown_signal() -> Result<()> {
+    #[cfg(unix)]
+    {
+        let mut sigterm =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result?,
+            _ = sigterm.recv() => {}
+        }
+    }
 
-            let mut db = db_for_ingester.lock().await;
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
+
+    Ok(())
+}
+
+/// Restricts the scan to the service UUIDs of the given devices' types. Without any devices nothing
+/// is filtered.
+fn scan_filter<'a>(devices: impl IntoIterator<Item = &'a Device>) -> ScanFilter {
+    let mut services = Vec::new();
+    for device in devices {
+        let uuid = service_uuid(&device.r#type);
+        if !services.contains(&uuid) {
+            services.push(uuid);
+        }
+    }
 
-            let Some(measurements) = db.get_mut(&mac_address) else {
-                eprintln!("unknown device: {mac_address}");
+    ScanFilter { services }
+}
+
+async fn load_devices(pool: &PgPool, filter: &DeviceFilter) -> Result<IndexMap<MacAddr6, Device>> {
+    Ok(get_switchbot_devices(pool)
+        .await
+        .context("failed to get SwitchBot devices")?
+        .into_iter()
+        .filter(|d| d.enabled && filter.matches(d))
+        .map(|d| (d.id, d))
+        .collect())
+}
+
+/// Reloads the enabled devices periodically so that devices can be added, disabled or removed
+/// without restarting. Measurements already buffered for a removed device are still inserted.
+async fn refresh_devices(state: Arc<State>, pool: PgPool) {
+    let mut config_rx = state.config.subscribe();
+    let mut interval = tokio::time::interval(state.config().device_refresh_interval);
+    interval.tick().await;
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            // A reloaded config may have a different filter or refresh interval.
+            Ok(()) = config_rx.changed() => {
+                interval = tokio::time::interval(config_rx.borrow_and_update().device_refresh_interval);
+                interval.tick().await;
+            }
+        }
+
+        let devices = match load_devices(&pool, &state.config().device_filter).await {
+            Ok(devices) => devices,
+            Err(err) => {
+                warn!(error = format!("{err:#}"), "failed to refresh devices");
                 continue;
-            };
+            }
+        };
+
+        if let Some(mqtt) = &state.mqtt {
+            // Published before taking the write lock, which would stall the scanning tasks while
+            // the broker is slow.
+            let current = state.devices.read().await;
+            for (id, device) in &devices {
+                if current
+                    .get(id)
+                    .is_none_or(|old| old.name != device.name || old.r#type != device.r#type)
+                {
+                    mqtt.announce(device).await;
+                }
+            }
+            for (id, device) in current.iter() {
+                if !devices.contains_key(id) {
+                    mqtt.withdraw(device).await;
+                }
+            }
+        }
+
+        let mut current = state.devices.write().await;
+        for (id, device) in &devices {
+            if !current.contains_key(id) {
+                info!(mac_address = %id, name = device.name, "added device");
+            }
+        }
+        for (id, device) in current.iter() {
+            if !devices.contains_key(id) {
+                info!(mac_address = %id, name = device.name, "removed device");
+            }
+        }
+
+        *current = devices;
+    }
+}
 
-            if let Some((existing_measured_at, _)) = measurements.get(&rounded_measured_at) {
-                let existing_diff = (*existing_measured_at - rounded_measured_at)
-                    .num_milliseconds()
-                    .abs();
+/// Warns once about every device that has not been heard for `stale_after`, e.g. because its battery
+/// ran out, and again when it comes back.
+async fn check_staleness(state: Arc<State>) {
+    let started_at = Utc::now();
+    let mut stale = HashSet::new();
 
-                if diff >= existing_diff {
-                    continue;
+    let mut interval = tokio::time::interval(STALENESS_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        let config = state.config();
+        let now = Utc::now();
+        let devices = state.devices.read().await;
+        let last_seen = state.last_seen.lock().await;
+        for (id, device) in devices.iter() {
+            let seen_at = last_seen.get(id).copied();
+            if now - seen_at.unwrap_or(started_at) < config.stale_after {
+                if stale.remove(id) {
+                    info!(mac_address = %id, name = device.name, "device is reporting again");
+                }
+            } else if stale.insert(*id) {
+                match seen_at {
+                    Some(seen_at) => warn!(
+                        mac_address = %id,
+                        name = device.name,
+                        last_seen_at = %seen_at.with_timezone(&config.timezone),
+                        "device has not been heard for a while"
+                    ),
+                    None => warn!(
+                        mac_address = %id,
+                        name = device.name,
+                        "device has not been heard since startup"
+                    ),
                 }
             }
+        }
+        telemetry::set_stale_devices(stale.len());
+    }
+}
+
+/// Pings the systemd watchdog, only while BLE events keep arriving when `scanning`, so that systemd
+/// restarts the service when the event stream stalls.
+async fn watchdog(state: Arc<State>, timeout: Duration, scanning: bool) {
+    let mut interval = tokio::time::interval(timeout / 2);
+    loop {
+        interval.tick().await;
 
-            measurements.insert(rounded_measured_at, (measured_at, decoded));
+        let since_last_event = state.last_event_at.lock().await.elapsed();
+        if !scanning || since_last_event < timeout {
+            systemd::notify_watchdog();
+        } else {
+            warn!(
+                ?since_last_event,
+                "no BLE events received, skipping watchdog ping"
+            );
         }
-    });
+    }
+}
 
-    let db_for_printer = db.clone();
-    let printer_handle = tokio::spawn(async move {
-        let mut interval = tokio::time::interval(Duration::from_mins(1));
-        loop {
-            interval.tick().await;
-            let mut db = db_for_printer.lock().await;
-
-            let now = Utc::now().with_timezone(&args.timezone);
-
-            let keys_to_insert: Vec<(MacAddr6, DateTime<Tz>)> = db
-                .iter()
-                .flat_map(|(&device_id, measurements)| {
-                    measurements
-                        .iter()
-                        .filter(|&(&measured_at, _)| {
-                            (now - measured_at).num_milliseconds()
-                                > TimeDelta::seconds(40).num_milliseconds()
-                        })
-                        .map(move |(&measured_at, _)| (device_id, measured_at))
-                })
-                .collect();
-
-            let measurments: Vec<Measurement> = keys_to_insert
-                .iter()
-                .filter_map(|(device_id, measured_at)| {
-                    db.get(device_id)
-                        .and_then(|m| m.get(measured_at))
-                        .map(|(_, m)| Measurement {
-                            device_id: *device_id,
-                            measured_at: *measured_at,
-                            temperature_celsius: m.temperature_celsius,
-                            humidity_percent: m.humidity_percent,
-                            co2_ppm: m.co2_ppm,
-                            light_level: m.light_level,
-                        })
-                })
-                .collect();
-
-            println!("Inserting {} measurements...", measurments.len());
-            if let Err(e) = bulk_insert_switchbot_measurements(&pool, &measurments).await {
-                eprintln!("failed to bulk insert measurements: {e:#}");
+async fn ingest_events(
+    adapter: Adapter,
+    adapter_info: String,
+    mut events: Pin<Box<dyn Stream<Item = CentralEvent> + Send>>,
+    state: Arc<State>,
+    events_tx: mpsc::Sender<Event>,
+) {
+    let mut payloads = PayloadCache::default();
+    loop {
+        let scan_timeout = state.config().scan_timeout;
+        let event = match tokio::time::timeout(scan_timeout, events.next()).await {
+            Ok(Some(event)) => event,
+            Ok(None) => break,
+            Err(_) => {
+                warn!(
+                    timeout = ?scan_timeout,
+                    "no BLE events received, restarting scan"
+                );
+                telemetry::record_scan_restart(&adapter_info);
+                match restart_scan(&adapter, &state.scan_filter).await {
+                    Ok(new_events) => events = new_events,
+                    Err(err) => error!(error = format!("{err:#}"), "failed to restart BLE scan"),
+                }
                 continue;
             }
-            println!("Inserted {} measurements.", measurments.len());
+        };
+        *state.last_event_at.lock().await = Instant::now();
 
-            for (device_id, measured_at) in keys_to_insert {
-                if let Some(measurements) = db.get_mut(&device_id) {
-                    measurements.remove(&measured_at);
-                }
+        let peripheral_id = match &event {
+            CentralEvent::DeviceDiscovered(id) | CentralEvent::DeviceUpdated(id) => id,
+            CentralEvent::ManufacturerDataAdvertisement {
+                id,
+                manufacturer_data,
+            } => {
+                payloads.update_manufacturer_data(id, manufacturer_data);
+                continue;
             }
+            CentralEvent::ServiceDataAdvertisement { id, service_data } => {
+                payloads.update_service_data(id, service_data);
+                continue;
+            }
+            _ => continue,
+        };
+        telemetry::record_advertisement(&adapter_info);
+
+        let Some(now) = read_clock(&state, &events_tx).await else {
+            continue;
+        };
+        let Some(bucket) = state.settings.borrow().bucket(now) else {
+            continue;
+        };
+        // An identical payload can still come with a stronger signal.
+        let strongest = state.settings.borrow().aggregation.prefer == Preference::Strongest;
+        if !strongest && !payloads.should_process(peripheral_id, bucket) {
+            telemetry::record_skipped_advertisement(&adapter_info);
+            continue;
         }
-    });
 
-    let _ = tokio::join!(ingester_handle, printer_handle);
+        let handled =
+            handle_advertisement(&adapter, peripheral_id, now, bucket, &state, &events_tx)
+                .instrument(debug_span!(
+                    "advertisement",
+                    %peripheral_id,
+                    mac_address = field::Empty
+                ))
+                .await;
+        if handled && !strongest {
+            payloads.mark_processed(peripheral_id, bucket);
+        }
+    }
+}
 
-    Ok(())
+/// Re-issues the scan, which revives adapters that silently stopped delivering events.
+async fn restart_scan(
+    adapter: &Adapter,
+    scan_filter: &ScanFilter,
+) -> Result<Pin<Box<dyn Stream<Item = CentralEvent> + Send>>> {
+    if let Err(err) = adapter.stop_scan().await {
+        debug!(error = format!("{err:#}"), "failed to stop BLE scan");
+    }
+
+    adapter
+        .start_scan(scan_filter.clone())
+        .await
+        .context("failed to start BLE scan")?;
+    info!("restarted BLE scan");
+
+    adapter
+        .events()
+        .await
+        .context("failed to get Bluetooth adapter events")
+}
+
+/// Returns the current time, or `None` while the system clock has not been set. When the clock was
+/// set since the last advertisement, the aggregator is told to correct what it has buffered before
+/// anything timestamped by the new clock reaches it.
+async fn read_clock(state: &State, events_tx: &mpsc::Sender<Event>) -> Option<DateTime<Utc>> {
+    let mut clock = state.clock.lock().await;
+    let reading = clock.read();
+
+    if let Some(jump) = reading.jump {
+        warn!(%jump, "system clock was set, correcting buffered measurements");
+        telemetry::record_clock_jump();
+        let _ = events_tx.send(Event::ClockJump(jump)).await;
+    }
+
+    let sane = reading.now.is_some();
+    if state.clock_sane.swap(sane, Ordering::Relaxed) != sane {
+        if sane {
+            info!("system clock has been set, accepting advertisements");
+        } else {
+            warn!(now = %Utc::now(), "system clock has not been set, ignoring advertisements");
+        }
+    }
+
+    reading.now
+}
+
+/// The MAC address of `peripheral`. CoreBluetooth on macOS hides it and btleplug reports
+/// 00:00:00:00:00:00, so there it is read from the SwitchBot manufacturer data instead.
+async fn resolve_address(peripheral: &Peripheral) -> Option<MacAddr6> {
+    let address: MacAddr6 = peripheral.address().into_inner().into();
+    if !address.is_nil() {
+        return Some(address);
+    }
+
+    let properties = peripheral.properties().await.ok().flatten()?;
+    mac_address_from_manufacturer_data(&properties.manufacturer_data)
+}
+
+/// Returns `false` when the peripheral could not be looked up, so that the next copy of the same
+/// payload is handled again.
+async fn handle_advertisement(
+    adapter: &Adapter,
+    peripheral_id: &PeripheralId,
+    measured_at: DateTime<Utc>,
+    bucket: DateTime<Utc>,
+    state: &Arc<State>,
+    events_tx: &mpsc::Sender<Event>,
+) -> bool {
+    let config = state.config();
+
+    let peripheral = match adapter.peripheral(peripheral_id).await {
+        Ok(p) => p,
+        Err(err) => {
+            warn!(error = format!("{err:#}"), "failed to get peripheral");
+            return false;
+        }
+    };
+
+    let Some(mac_address) = resolve_address(&peripheral).await else {
+        return true;
+    };
+    let Some((device_type, device_name, temperature_offset, humidity_offset)) =
+        state.devices.read().await.get(&mac_address).map(|d| {
+            (
+                d.r#type,
+                d.name.clone(),
+                d.temperature_offset_celsius,
+                d.humidity_offset_percent,
+            )
+        })
+    else {
+        return true;
+    };
+    Span::current().record("mac_address", field::display(mac_address));
+
+    let maybe_properties = match peripheral.properties().await {
+        Ok(p) => p,
+        Err(err) => {
+            warn!(
+                error = format!("{err:#}"),
+                "failed to get BLE peripheral properties"
+            );
+            return false;
+        }
+    };
+
+    let Some(properties) = maybe_properties else {
+        warn!("BLE peripheral properties not available");
+        return false;
+    };
+
+    let raw_advertisement = RawAdvertisement {
+        device_id: mac_address,
+        received_at: measured_at,
+        rssi_dbm: properties.rssi,
+        manufacturer_data: properties.manufacturer_data.clone(),
+        service_data: properties.service_data.clone(),
+    };
+    if let Some(recorder) = &state.recorder
+        && let Err(err) = recorder.lock().await.record(&raw_advertisement).await
+    {
+        warn!(error = format!("{err:#}"), "failed to record advertisement");
+    }
+    state.capture.lock().await.push(raw_advertisement.clone());
+
+    if config.store_raw_advertisements
+        && !config.dry_run
+        && events_tx
+            .send(Event::RawAdvertisement(raw_advertisement))
+            .await
+            .is_err()
+    {
+        return true;
+    }
+
+    let decoded = {
+        let _span = debug_span!("decode", device_type = device_type.as_str()).entered();
+
+        decode_advertisement(
+            &device_type,
+            config.decoder_overrides.get(&mac_address),
+            &properties.manufacturer_data,
+            &properties.service_data,
+        )
+    };
+    let mut decoded = match decoded {
+        Ok(m) => {
+            state
+                .decode_failures
+                .lock()
+                .await
+                .record_success(mac_address);
+            m
+        }
+        Err(err) => {
+            telemetry::record_decode_failure(
+                &mac_address.to_string(),
+                &device_name,
+                device_type.as_str(),
+            );
+            let recorded = state.decode_failures.lock().await.record(
+                mac_address,
+                &device_name,
+                device_type,
+                &err,
+                measured_at,
+            );
+            if let Some(suppressed) = recorded.log {
+                warn!(
+                    error = format!("{err:#}"),
+                    consecutive = recorded.consecutive,
+                    suppressed,
+                    "failed to decode manufacturer data"
+                );
+            }
+
+            // The read connects for up to 10s, so it runs beside the scan rather than stalling it.
+            if claim_gatt_fallback(state, &device_type, recorded.consecutive, &config) {
+                let state = state.clone();
+                let events_tx = events_tx.clone();
+                let rssi_dbm = properties.rssi;
+                tokio::spawn(
+                    async move {
+                        let Some(mut decoded) = gatt_fallback(&state, &peripheral).await else {
+                            return;
+                        };
+                        decoded.calibrate(temperature_offset, humidity_offset);
+                        accept_measurement(
+                            &state,
+                            &events_tx,
+                            &device_name,
+                            AcceptedMeasurement {
+                                device_id: mac_address,
+                                bucket,
+                                received_at: measured_at,
+                                rssi_dbm,
+                                decoded,
+                            },
+                        )
+                        .await;
+                    }
+                    .instrument(Span::current()),
+                );
+            }
+            return true;
+        }
+    };
+
+    decoded.calibrate(temperature_offset, humidity_offset);
+
+    accept_measurement(
+        state,
+        events_tx,
+        &device_name,
+        AcceptedMeasurement {
+            device_id: mac_address,
+            bucket,
+            received_at: measured_at,
+            rssi_dbm: properties.rssi,
+            decoded,
+        },
+    )
+    .await;
+
+    true
+}
+
+/// Passes a calibrated measurement of a registered device through the outlier filter and hands it
+/// to the engine, MQTT and the live stream, or prints it in dry-run mode.
+async fn accept_measurement(
+    state: &State,
+    events_tx: &mpsc::Sender<Event>,
+    device_name: &str,
+    measurement: AcceptedMeasurement,
+) {
+    let config = state.config();
+    let AcceptedMeasurement {
+        device_id: mac_address,
+        bucket,
+        received_at: measured_at,
+        rssi_dbm,
+        ref decoded,
+    } = measurement;
+
+    state
+        .last_seen
+        .lock()
+        .await
+        .insert(mac_address, measured_at);
+    telemetry::record_device_seen(
+        &mac_address.to_string(),
+        device_name,
+        measured_at.timestamp(),
+    );
+
+    if let Some(outlier) =
+        state
+            .outliers
+            .lock()
+            .await
+            .check(mac_address, decoded, &config.max_deltas)
+    {
+        telemetry::record_rejected_outlier(outlier.metric);
+        warn!(
+            metric = outlier.metric,
+            value = outlier.value,
+            median = outlier.median,
+            "rejected outlier"
+        );
+        return;
+    }
+
+    if config.dry_run {
+        println!(
+            "{} {device_name} ({mac_address}) {:.1}°C {}% CO2 {} RSSI {}",
+            measured_at.with_timezone(&config.timezone),
+            decoded.temperature_celsius,
+            decoded.humidity_percent,
+            decoded
+                .co2_ppm
+                .map_or_else(|| "-".to_string(), |co2| format!("{co2}ppm")),
+            rssi_dbm.map_or_else(|| "-".to_string(), |rssi| format!("{rssi}dBm")),
+        );
+        return;
+    }
+
+    if let Some(mqtt) = &state.mqtt {
+        mqtt.publish(mac_address, device_name, decoded);
+    }
+    let _ = state.live.send(LiveMeasurement {
+        device_id: mac_address.to_string(),
+        device_name: device_name.to_string(),
+        received_at: measured_at.with_timezone(&config.timezone),
+        bucket: bucket.with_timezone(&config.timezone),
+        temperature_celsius: decoded.temperature_celsius,
+        humidity_percent: decoded.humidity_percent,
+        co2_ppm: decoded.co2_ppm,
+        light_level: decoded.light_level,
+        rssi_dbm,
+    });
+
+    // Fails only during shutdown, when the aggregator has already stopped.
+    let _ = events_tx.send(Event::Measurement(measurement)).await;
 }
@@ -0,0 +1,256 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{Context as _, Result};
+use home_environments::{
+    ingest::DecodedMeasurement,
+    mqtt::mqtt_options,
+    switchbot::{Device, DeviceType},
+};
+use macaddr::MacAddr6;
+use rumqttc::{AsyncClient, Event, Packet, QoS};
+use serde_json::json;
+use tokio::sync::Notify;
+use tracing::{debug, info, warn};
+
+use crate::telemetry;
+
+const DEFAULT_CLIENT_ID: &str = "ble-ingester";
+
+const REQUEST_CHANNEL_CAPACITY: usize = 256;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Home Assistant publishes `online` here when it starts, and then expects the discovery configs
+/// to be published again.
+const HOME_ASSISTANT_STATUS_TOPIC: &str = "status";
+
+struct Metric {
+    name: &'static str,
+    /// <https://www.home-assistant.io/integrations/sensor/#device-class>
+    device_class: Option<&'static str>,
+    unit: Option<&'static str>,
+}
+
+const TEMPERATURE: Metric = Metric {
+    name: "temperature",
+    device_class: Some("temperature"),
+    unit: Some("°C"),
+};
+
+const HUMIDITY: Metric = Metric {
+    name: "humidity",
+    device_class: Some("humidity"),
+    unit: Some("%"),
+};
+
+const CO2: Metric = Metric {
+    name: "co2",
+    device_class: Some("carbon_dioxide"),
+    unit: Some("ppm"),
+};
+
+/// SwitchBot's light level is a 1-20 scale rather than lux, so it has no device class.
+const LIGHT_LEVEL: Metric = Metric {
+    name: "light_level",
+    device_class: None,
+    unit: None,
+};
+
+/// Publishes every accepted measurement as soon as it is decoded, one retained message per value.
+#[derive(Debug)]
+pub struct MqttSink {
+    client: AsyncClient,
+    topic: String,
+    discovery_prefix: String,
+    /// Notified when the discovery configs need to be (re-)published.
+    announce: Arc<Notify>,
+}
+
+impl MqttSink {
+    /// Connects to `url` (e.g. `mqtt://localhost:1883`) in the background. `topic` may contain the
+    /// `{device_id}`, `{device_name}` and `{metric}` placeholders. Home Assistant discovery configs
+    /// are published under `discovery_prefix`.
+    pub fn connect(url: &str, topic: &str, discovery_prefix: &str) -> Result<Self> {
+        let options = mqtt_options(url, DEFAULT_CLIENT_ID)
+            .with_context(|| format!("invalid MQTT URL: {url}"))?;
+
+        let (client, mut event_loop) = AsyncClient::new(options, REQUEST_CHANNEL_CAPACITY);
+        let announce = Arc::new(Notify::new());
+        let status_topic = format!("{discovery_prefix}/{HOME_ASSISTANT_STATUS_TOPIC}");
+
+        tokio::spawn({
+            let client = client.clone();
+            let announce = announce.clone();
+            async move {
+                loop {
+                    match event_loop.poll().await {
+                        Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                            info!("connected to MQTT broker");
+                            // Subscriptions do not survive a reconnect with a clean session.
+                            if let Err(err) = client.try_subscribe(&status_topic, QoS::AtMostOnce) {
+                                warn!(
+                                    error = format!("{err:#}"),
+                                    "failed to subscribe to Home Assistant status"
+                                );
+                            }
+                            announce.notify_one();
+                        }
+                        Ok(Event::Incoming(Packet::Publish(publish)))
+                            if publish.topic == status_topic
+                                && publish.payload.as_ref() == b"online" =>
+                        {
+                            debug!("Home Assistant came online");
+                            announce.notify_one();
+                        }
+                        Ok(event) => debug!(?event, "MQTT event"),
+                        Err(err) => {
+                            warn!(
+                                error = format!("{err:#}"),
+                                "MQTT connection failed, reconnecting"
+                            );
+                            tokio::time::sleep(RECONNECT_DELAY).await;
+                        }
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            client,
+            topic: topic.to_string(),
+            discovery_prefix: discovery_prefix.to_string(),
+            announce,
+        })
+    }
+
+    /// Waits until the discovery configs need to be published, i.e. after every (re)connect and
+    /// whenever Home Assistant restarts.
+    pub async fn announcement_needed(&self) {
+        self.announce.notified().await;
+    }
+
+    /// Publishes a retained Home Assistant discovery config for every value `device` reports, so
+    /// that Home Assistant creates its sensors.
+    pub async fn announce(&self, device: &Device) {
+        let node_id = node_id(device.id);
+        for metric in metrics(device.r#type) {
+            let mut config = json!({
+                "name": metric.name.replace('_', " "),
+                "unique_id": format!("{node_id}_{}", metric.name),
+                "state_topic": self.topic(device.id, &device.name, metric.name),
+                "state_class": "measurement",
+                "device": {
+                    "identifiers": [node_id],
+                    "connections": [["mac", device.id.to_string()]],
+                    "name": device.name,
+                    "manufacturer": "SwitchBot",
+                    "model": device.r#type.as_str(),
+                },
+            });
+            if let Some(device_class) = metric.device_class {
+                config["device_class"] = device_class.into();
+            }
+            if let Some(unit) = metric.unit {
+                config["unit_of_measurement"] = unit.into();
+            }
+
+            self.publish_discovery(device.id, metric, config.to_string())
+                .await;
+        }
+    }
+
+    /// Clears the discovery configs of `device`, which makes Home Assistant remove its sensors.
+    pub async fn withdraw(&self, device: &Device) {
+        for metric in metrics(device.r#type) {
+            self.publish_discovery(device.id, metric, String::new())
+                .await;
+        }
+    }
+
+    async fn publish_discovery(&self, device_id: MacAddr6, metric: &Metric, payload: String) {
+        let topic = format!(
+            "{}/sensor/{}/{}/config",
+            self.discovery_prefix,
+            node_id(device_id),
+            metric.name
+        );
+        if let Err(err) = self
+            .client
+            .publish(&topic, QoS::AtLeastOnce, true, payload)
+            .await
+        {
+            warn!(
+                error = format!("{err:#}"),
+                topic, "failed to publish Home Assistant discovery config"
+            );
+        }
+    }
+
+    /// Queues the values of `measurement` without waiting. Values are dropped while the request
+    /// queue is full, e.g. during a long disconnect.
+    pub fn publish(
+        &self,
+        device_id: MacAddr6,
+        device_name: &str,
+        measurement: &DecodedMeasurement,
+    ) {
+        let values = [
+            (
+                TEMPERATURE,
+                Some(measurement.temperature_celsius.to_string()),
+            ),
+            (HUMIDITY, Some(measurement.humidity_percent.to_string())),
+            (CO2, measurement.co2_ppm.map(|v| v.to_string())),
+            (LIGHT_LEVEL, measurement.light_level.map(|v| v.to_string())),
+        ];
+
+        for (metric, value) in values {
+            let Some(value) = value else {
+                continue;
+            };
+
+            let topic = self.topic(device_id, device_name, metric.name);
+            if let Err(err) = self
+                .client
+                .try_publish(&topic, QoS::AtMostOnce, true, value)
+            {
+                telemetry::record_mqtt_dropped_message();
+                debug!(
+                    error = format!("{err:#}"),
+                    topic, "failed to queue MQTT message"
+                );
+            }
+        }
+    }
+
+    fn topic(&self, device_id: MacAddr6, device_name: &str, metric: &str) -> String {
+        self.topic
+            .replace("{device_id}", &topic_segment(&device_id.to_string()))
+            .replace("{device_name}", &topic_segment(device_name))
+            .replace("{metric}", metric)
+    }
+}
+
+/// The values a device of `device_type` reports.
+fn metrics(device_type: DeviceType) -> &'static [Metric] {
+    match device_type {
+        DeviceType::Hub2 => &[TEMPERATURE, HUMIDITY, LIGHT_LEVEL],
+        DeviceType::MeterProCO2 => &[TEMPERATURE, HUMIDITY, CO2],
+        DeviceType::Hub
+        | DeviceType::HubMini
+        | DeviceType::Hub3
+        | DeviceType::Meter
+        | DeviceType::MeterPlus
+        | DeviceType::WoIOSensor
+        | DeviceType::MeterPro => &[TEMPERATURE, HUMIDITY],
+    }
+}
+
+fn node_id(device_id: MacAddr6) -> String {
+    device_id.to_string().replace(':', "").to_lowercase()
+}
+
+/// Replaces the characters that have a meaning in MQTT topics.
+fn topic_segment(s: &str) -> String {
+    s.replace(['/', '+', '#'], "_")
+}
@@ -0,0 +1,95 @@
+use std::collections::{HashMap, VecDeque};
+
+use home_environments::ingest::DecodedMeasurement;
+use macaddr::MacAddr6;
+
+/// How many recent samples per device the median is taken from.
+const HISTORY_SIZE: usize = 9;
+
+/// Below this many samples a device is not filtered, since a spike could be the median itself.
+const MIN_HISTORY_SIZE: usize = 3;
+
+/// How far each value may deviate from the recent median of its device. `None` disables the check
+/// of that value.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MaxDeltas {
+    pub temperature_celsius: Option<f32>,
+    pub humidity_percent: Option<f32>,
+    pub co2_ppm: Option<f32>,
+}
+
+/// A value that was rejected, for logging.
+#[derive(Debug)]
+pub struct Outlier {
+    pub metric: &'static str,
+    pub value: f32,
+    pub median: f32,
+}
+
+/// Rejects decoded values that jump away from what the device reported recently, such as a 0.0°C
+/// spike from a corrupted advertisement that still decoded.
+#[derive(Debug, Default)]
+pub struct OutlierFilter {
+    history: HashMap<MacAddr6, VecDeque<[Option<f32>; 3]>>,
+}
+
+impl OutlierFilter {
+    /// Returns the first value of `measurement` that is further than its max delta from the median
+    /// of the device's recent samples. Rejected samples are remembered too, so that a lasting change,
+    /// e.g. after the device was moved, is accepted once it makes up half of the history.
+    pub fn check(
+        &mut self,
+        device_id: MacAddr6,
+        measurement: &DecodedMeasurement,
+        max_deltas: &MaxDeltas,
+    ) -> Option<Outlier> {
+        let sample = [
+            Some(measurement.temperature_celsius),
+            Some(f32::from(measurement.humidity_percent)),
+            measurement.co2_ppm.map(f32::from),
+        ];
+        let checks = [
+            ("temperature", max_deltas.temperature_celsius),
+            ("humidity", max_deltas.humidity_percent),
+            ("co2", max_deltas.co2_ppm),
+        ];
+
+        let history = self.history.entry(device_id).or_default();
+
+        let outlier = checks
+            .iter()
+            .enumerate()
+            .find_map(|(i, (metric, max_delta))| {
+                let max_delta = (*max_delta)?;
+                let value = sample[i]?;
+                let median = median(history.iter().filter_map(|s| s[i]))?;
+                ((value - median).abs() > max_delta).then_some(Outlier {
+                    metric,
+                    value,
+                    median,
+                })
+            });
+
+        if history.len() == HISTORY_SIZE {
+            history.pop_front();
+        }
+        history.push_back(sample);
+
+        outlier
+    }
+}
+
+fn median(values: impl Iterator<Item = f32>) -> Option<f32> {
+    let mut values: Vec<f32> = values.collect();
+    if values.len() < MIN_HISTORY_SIZE {
+        return None;
+    }
+
+    values.sort_by(f32::total_cmp);
+    let mid = values.len() / 2;
+    Some(if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    })
+}
@@ -0,0 +1,85 @@
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+};
+
+use btleplug::platform::PeripheralId;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+/// Remembers the advertisement payload of each peripheral, as delivered with the advertisement
+/// events, and which payload was last processed for which bucket. Re-processing an identical
+/// payload within the same bucket cannot change the outcome, so it can skip the D-Bus round trips
+/// of `peripheral()` and `properties()`. Peripherals are forgotten when a new bucket starts, so
+/// that rotating random addresses do not accumulate.
+#[derive(Debug, Default)]
+pub struct PayloadCache {
+    bucket: Option<DateTime<Utc>>,
+    peripherals: HashMap<PeripheralId, Entry>,
+}
+
+#[derive(Debug, Default)]
+struct Entry {
+    manufacturer_data: Option<u64>,
+    service_data: Option<u64>,
+    processed: Option<(DateTime<Utc>, Option<u64>, Option<u64>)>,
+}
+
+impl PayloadCache {
+    pub fn update_manufacturer_data(
+        &mut self,
+        id: &PeripheralId,
+        manufacturer_data: &HashMap<u16, Vec<u8>>,
+    ) {
+        self.peripherals
+            .entry(id.clone())
+            .or_default()
+            .manufacturer_data = Some(hash_map(manufacturer_data));
+    }
+
+    pub fn update_service_data(
+        &mut self,
+        id: &PeripheralId,
+        service_data: &HashMap<Uuid, Vec<u8>>,
+    ) {
+        self.peripherals.entry(id.clone()).or_default().service_data = Some(hash_map(service_data));
+    }
+
+    /// Returns `false` if the current payload of `id` has already been processed for `bucket`.
+    /// Without a known payload it always returns `true`.
+    pub fn should_process(&mut self, id: &PeripheralId, bucket: DateTime<Utc>) -> bool {
+        if self.bucket != Some(bucket) {
+            self.bucket = Some(bucket);
+            self.peripherals.retain(|_, entry| {
+                entry
+                    .processed
+                    .is_some_and(|(processed, _, _)| processed >= bucket)
+            });
+        }
+
+        let Some(entry) = self.peripherals.get(id) else {
+            return true;
+        };
+        if entry.manufacturer_data.is_none() && entry.service_data.is_none() {
+            return true;
+        }
+
+        entry.processed != Some((bucket, entry.manufacturer_data, entry.service_data))
+    }
+
+    /// Records the current payload of `id` as processed for `bucket`, once it has been handled.
+    pub fn mark_processed(&mut self, id: &PeripheralId, bucket: DateTime<Utc>) {
+        let entry = self.peripherals.entry(id.clone()).or_default();
+        entry.processed = Some((bucket, entry.manufacturer_data, entry.service_data));
+    }
+}
+
+/// Hashes the entries in key order, since the iteration order of a `HashMap` is unspecified.
+fn hash_map<K: Hash + Ord>(map: &HashMap<K, Vec<u8>>) -> u64 {
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+
+    let mut hasher = DefaultHasher::new();
+    entries.hash(&mut hasher);
+    hasher.finish()
+}
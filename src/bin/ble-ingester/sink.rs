@@ -0,0 +1,158 @@
+use std::{
+    io::{self, Write as _},
+    sync::Arc,
+    time::Instant,
+};
+
+use anyhow::{Context as _, Result};
+use chrono::DateTime;
+use chrono_tz::Tz;
+use clap::ValueEnum;
+use home_environments::{
+    db::WriteBatch,
+    ingest::{self, BoxFuture, Settings},
+    switchbot::{Device, Measurement},
+};
+use indexmap::IndexMap;
+use macaddr::MacAddr6;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tokio::sync::{RwLock, watch};
+use tracing::{debug, info, warn};
+
+use crate::{insert, spool::Spool, telemetry};
+
+/// Where accepted measurements are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sink {
+    Postgres,
+    /// One JSON object per measurement on stdout.
+    Ndjson,
+}
+
+/// Inserts batches with retries, keeping them in the spool while the database is unreachable.
+#[derive(Debug)]
+pub struct PostgresSink {
+    pub pool: PgPool,
+    pub spool: Option<Spool>,
+}
+
+impl ingest::Sink for PostgresSink {
+    fn write<'a>(&'a self, batch: &'a WriteBatch) -> BoxFuture<'a, Result<()>> {
+        Box::pin(self.write_batch(batch))
+    }
+}
+
+impl PostgresSink {
+    /// Inserts previously spooled batches and then `batch`, writing it to the spool if that fails.
+    async fn write_batch(&self, batch: &WriteBatch) -> Result<()> {
+        if let Some(spool) = &self.spool {
+            match spool.drain(&self.pool).await {
+                Ok(0) => {}
+                Ok(rows) => info!(rows, "inserted spooled rows"),
+                Err(err) => {
+                    telemetry::record_db_error();
+                    warn!(error = format!("{err:#}"), "failed to insert spooled rows");
+                }
+            }
+        }
+
+        debug!(
+            measurements = batch.switchbot_measurements().len(),
+            raw_advertisements = batch.raw_advertisements().len(),
+            "inserting buffered rows"
+        );
+        let started_at = Instant::now();
+        match insert::commit(&self.pool, batch).await {
+            Ok(dropped) => {
+                telemetry::record_insert(batch.len(), started_at.elapsed());
+                info!(
+                    measurements = batch.switchbot_measurements().len(),
+                    raw_advertisements = batch.raw_advertisements().len(),
+                    dropped,
+                    "inserted buffered rows"
+                );
+            }
+            Err(err) => {
+                telemetry::record_db_error();
+
+                let Some(spool) = &self.spool else {
+                    return Err(err).context("failed to bulk insert measurements");
+                };
+                warn!(
+                    error = format!("{err:#}"),
+                    spool = ?spool.path(),
+                    "failed to bulk insert measurements, writing them to the spool"
+                );
+                spool
+                    .append(batch)
+                    .await
+                    .context("failed to write measurements to the spool")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes the measurements of every batch to stdout, with the device names and the times in the
+/// configured timezone.
+#[derive(Debug)]
+pub struct NdjsonSink {
+    pub devices: Arc<RwLock<IndexMap<MacAddr6, Device>>>,
+    pub settings: watch::Receiver<Settings>,
+}
+
+impl ingest::Sink for NdjsonSink {
+    fn write<'a>(&'a self, batch: &'a WriteBatch) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let timezone = self.settings.borrow().timezone;
+            write_ndjson(
+                batch.switchbot_measurements(),
+                &*self.devices.read().await,
+                timezone,
+            )
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct NdjsonMeasurement<'a> {
+    device_id: String,
+    device_name: Option<&'a str>,
+    measured_at: DateTime<Tz>,
+    temperature_celsius: f32,
+    humidity_percent: u8,
+    co2_ppm: Option<u16>,
+    light_level: Option<u8>,
+    rssi_dbm: Option<i16>,
+    source: Option<&'a str>,
+}
+
+/// Writes `measurements` to stdout with their times in `timezone`.
+fn write_ndjson(
+    measurements: &[Measurement],
+    devices: &IndexMap<MacAddr6, Device>,
+    timezone: Tz,
+) -> Result<()> {
+    let mut stdout = io::stdout().lock();
+
+    for m in measurements {
+        let line = NdjsonMeasurement {
+            device_id: m.device_id.to_string(),
+            device_name: devices.get(&m.device_id).map(|d| d.name.as_str()),
+            measured_at: m.measured_at.with_timezone(&timezone),
+            temperature_celsius: m.temperature_celsius,
+            humidity_percent: m.humidity_percent,
+            co2_ppm: m.co2_ppm,
+            light_level: m.light_level,
+            rssi_dbm: m.rssi_dbm,
+            source: m.source.as_deref(),
+        };
+        serde_json::to_writer(&mut stdout, &line).context("failed to serialize measurement")?;
+        writeln!(stdout).context("failed to write to stdout")?;
+    }
+
+    stdout.flush().context("failed to flush stdout")
+}
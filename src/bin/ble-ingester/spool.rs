@@ -0,0 +1,245 @@
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context as _, Result};
+use chrono::{DateTime, FixedOffset, Utc};
+use home_environments::{
+    db::WriteBatch, raw_advertisement::RawAdvertisement, switchbot::Measurement,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tokio::{fs, io::AsyncWriteExt as _};
+use tracing::error;
+use uuid::Uuid;
+
+use crate::insert;
+
+/// Append-only file holding batches that could not be inserted, one JSON object per line, so
+/// that they survive a restart while the database is unreachable.
+#[derive(Debug)]
+pub struct Spool {
+    path: PathBuf,
+}
+
+impl Spool {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub async fn append(&self, batch: &WriteBatch) -> Result<()> {
+        let mut line =
+            serde_json::to_string(&SpoolBatch::from(batch)).context("failed to serialize batch")?;
+        line.push('\n');
+
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .with_context(|| format!("failed to open spool file: {:?}", self.path))?;
+        file.write_all(line.as_bytes())
+            .await
+            .with_context(|| format!("failed to write spool file: {:?}", self.path))?;
+        file.sync_data()
+            .await
+            .with_context(|| format!("failed to sync spool file: {:?}", self.path))?;
+
+        Ok(())
+    }
+
+    /// Inserts the spooled batches in order and returns the number of rows inserted.
+    ///
+    /// Batches inserted before a failure are removed from the file, the rest are kept for the
+    /// next attempt. Lines that cannot be parsed, e.g. one truncated by a power loss, are moved to
+    /// the corrupt file so that they do not hold up the others.
+    pub async fn drain(&self, pool: &PgPool) -> Result<usize> {
+        let content = match fs::read_to_string(&self.path).await {
+            Ok(content) => content,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(0),
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("failed to read spool file: {:?}", self.path));
+            }
+        };
+
+        let lines: Vec<&str> = content.lines().filter(|l| !l.trim().is_empty()).collect();
+        let mut inserted = 0;
+        for (i, line) in lines.iter().enumerate() {
+            let batch = match serde_json::from_str::<SpoolBatch>(line)
+                .context("failed to parse batch")
+                .and_then(SpoolBatch::into_write_batch)
+            {
+                Ok(batch) => batch,
+                Err(err) => {
+                    error!(
+                        error = format!("{err:#}"),
+                        line = i + 1,
+                        corrupt = ?self.corrupt_path(),
+                        "moving an unreadable spooled batch to the corrupt file"
+                    );
+                    self.append_corrupt(line).await?;
+                    continue;
+                }
+            };
+
+            match insert::commit(pool, &batch).await {
+                Ok(_) => inserted += batch.len(),
+                Err(err) => {
+                    self.rewrite(&lines[i..]).await?;
+                    return Err(err);
+                }
+            }
+        }
+
+        match fs::remove_file(&self.path).await {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("failed to remove spool file: {:?}", self.path));
+            }
+        }
+
+        Ok(inserted)
+    }
+
+    /// The spool path with `.corrupt` appended.
+    fn corrupt_path(&self) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(".corrupt");
+        path.into()
+    }
+
+    async fn append_corrupt(&self, line: &str) -> Result<()> {
+        let path = self.corrupt_path();
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+            .with_context(|| format!("failed to open corrupt spool file: {path:?}"))?;
+        file.write_all(format!("{line}\n").as_bytes())
+            .await
+            .with_context(|| format!("failed to write corrupt spool file: {path:?}"))?;
+        file.sync_data()
+            .await
+            .with_context(|| format!("failed to sync corrupt spool file: {path:?}"))
+    }
+
+    async fn rewrite(&self, lines: &[&str]) -> Result<()> {
+        let tmp_path = self.path.with_extension("tmp");
+
+        let mut content = lines.join("\n");
+        content.push('\n');
+        fs::write(&tmp_path, content)
+            .await
+            .with_context(|| format!("failed to write spool file: {tmp_path:?}"))?;
+        fs::rename(&tmp_path, &self.path)
+            .await
+            .with_context(|| format!("failed to replace spool file: {:?}", self.path))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SpoolBatch {
+    switchbot_measurements: Vec<SpoolMeasurement>,
+    raw_advertisements: Vec<SpoolRawAdvertisement>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SpoolMeasurement {
+    device_id: String,
+    measured_at: DateTime<FixedOffset>,
+    temperature_celsius: f32,
+    humidity_percent: u8,
+    co2_ppm: Option<u16>,
+    light_level: Option<u8>,
+    rssi_dbm: Option<i16>,
+    #[serde(default)]
+    source: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SpoolRawAdvertisement {
+    device_id: String,
+    received_at: DateTime<FixedOffset>,
+    rssi_dbm: Option<i16>,
+    manufacturer_data: HashMap<u16, Vec<u8>>,
+    service_data: HashMap<Uuid, Vec<u8>>,
+}
+
+impl From<&WriteBatch> for SpoolBatch {
+    fn from(batch: &WriteBatch) -> Self {
+        Self {
+            switchbot_measurements: batch
+                .switchbot_measurements()
+                .iter()
+                .map(|m| SpoolMeasurement {
+                    device_id: m.device_id.to_string(),
+                    measured_at: m.measured_at.fixed_offset(),
+                    temperature_celsius: m.temperature_celsius,
+                    humidity_percent: m.humidity_percent,
+                    co2_ppm: m.co2_ppm,
+                    light_level: m.light_level,
+                    rssi_dbm: m.rssi_dbm,
+                    source: m.source.clone(),
+                })
+                .collect(),
+            raw_advertisements: batch
+                .raw_advertisements()
+                .iter()
+                .map(|a| SpoolRawAdvertisement {
+                    device_id: a.device_id.to_string(),
+                    received_at: a.received_at.fixed_offset(),
+                    rssi_dbm: a.rssi_dbm,
+                    manufacturer_data: a.manufacturer_data.clone(),
+                    service_data: a.service_data.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl SpoolBatch {
+    fn into_write_batch(self) -> Result<WriteBatch> {
+        let mut batch = WriteBatch::new();
+
+        for m in self.switchbot_measurements {
+            batch.push_switchbot_measurement(Measurement {
+                device_id: m
+                    .device_id
+                    .parse()
+                    .with_context(|| format!("invalid device ID: {}", m.device_id))?,
+                measured_at: m.measured_at.with_timezone(&Utc),
+                temperature_celsius: m.temperature_celsius,
+                humidity_percent: m.humidity_percent,
+                co2_ppm: m.co2_ppm,
+                light_level: m.light_level,
+                rssi_dbm: m.rssi_dbm,
+                source: m.source,
+            });
+        }
+
+        for a in self.raw_advertisements {
+            batch.push_raw_advertisement(RawAdvertisement {
+                device_id: a
+                    .device_id
+                    .parse()
+                    .with_context(|| format!("invalid device ID: {}", a.device_id))?,
+                received_at: a.received_at.with_timezone(&Utc),
+                rssi_dbm: a.rssi_dbm,
+                manufacturer_data: a.manufacturer_data,
+                service_data: a.service_data,
+            });
+        }
+
+        Ok(batch)
+    }
+}
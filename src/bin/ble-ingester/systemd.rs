@@ -0,0 +1,40 @@
+//! `Type=notify` support. Every function is a no-op when not started by systemd or on platforms
+//! other than Linux.
+
+use std::time::Duration;
+
+/// Tells systemd that startup has finished.
+pub fn notify_ready() {
+    #[cfg(target_os = "linux")]
+    notify(&[sd_notify::NotifyState::Ready]);
+}
+
+pub fn notify_stopping() {
+    #[cfg(target_os = "linux")]
+    notify(&[sd_notify::NotifyState::Stopping]);
+}
+
+pub fn notify_watchdog() {
+    #[cfg(target_os = "linux")]
+    notify(&[sd_notify::NotifyState::Watchdog]);
+}
+
+/// Returns `WatchdogSec=` of the unit if the watchdog is enabled for this process.
+pub fn watchdog_timeout() -> Option<Duration> {
+    #[cfg(target_os = "linux")]
+    {
+        let mut usec = 0;
+        if sd_notify::watchdog_enabled(false, &mut usec) {
+            return Some(Duration::from_micros(usec));
+        }
+    }
+
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn notify(state: &[sd_notify::NotifyState]) {
+    if let Err(err) = sd_notify::notify(false, state) {
+        tracing::debug!(error = format!("{err:#}"), "failed to notify systemd");
+    }
+}
@@ -0,0 +1,127 @@
+use std::{
+    net::SocketAddr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context as _, Result};
+use metrics::{counter, gauge, histogram};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder};
+
+const ADVERTISEMENTS_TOTAL: &str = "ble_ingester_advertisements_total";
+const SKIPPED_ADVERTISEMENTS_TOTAL: &str = "ble_ingester_skipped_advertisements_total";
+const LAST_ADVERTISEMENT_TIMESTAMP: &str = "ble_ingester_last_advertisement_timestamp_seconds";
+const SCAN_RESTARTS_TOTAL: &str = "ble_ingester_scan_restarts_total";
+const DEVICE_LAST_SEEN_TIMESTAMP: &str = "ble_ingester_device_last_seen_timestamp_seconds";
+const STALE_DEVICES: &str = "ble_ingester_stale_devices";
+const DECODE_FAILURES_TOTAL: &str = "ble_ingester_decode_failures_total";
+const INSERT_BATCH_SIZE: &str = "ble_ingester_insert_batch_size";
+const INSERT_DURATION_SECONDS: &str = "ble_ingester_insert_duration_seconds";
+const DB_ERRORS_TOTAL: &str = "ble_ingester_db_errors_total";
+const REJECTED_ROWS_TOTAL: &str = "ble_ingester_rejected_rows_total";
+const REJECTED_OUTLIERS_TOTAL: &str = "ble_ingester_rejected_outliers_total";
+const CLOCK_JUMPS_TOTAL: &str = "ble_ingester_clock_jumps_total";
+const GATT_READS_TOTAL: &str = "ble_ingester_gatt_reads_total";
+const CLOUD_POLLS_TOTAL: &str = "ble_ingester_cloud_polls_total";
+const MQTT_DROPPED_MESSAGES_TOTAL: &str = "ble_ingester_mqtt_dropped_messages_total";
+
+const INSERT_BATCH_SIZE_BUCKETS: &[f64] = &[1.0, 10.0, 50.0, 100.0, 500.0, 1000.0, 5000.0];
+
+const INSERT_DURATION_SECONDS_BUCKETS: &[f64] = &[0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Starts an HTTP listener serving the Prometheus exposition format on `addr`.
+///
+/// Without it the recording functions below are no-ops.
+pub fn install(addr: SocketAddr) -> Result<()> {
+    PrometheusBuilder::new()
+        .with_http_listener(addr)
+        .set_buckets_for_metric(
+            Matcher::Full(INSERT_BATCH_SIZE.to_string()),
+            INSERT_BATCH_SIZE_BUCKETS,
+        )?
+        .set_buckets_for_metric(
+            Matcher::Full(INSERT_DURATION_SECONDS.to_string()),
+            INSERT_DURATION_SECONDS_BUCKETS,
+        )?
+        .install()
+        .with_context(|| format!("failed to start metrics listener on {addr}"))
+}
+
+/// Counts a BLE event from any peripheral, before it is filtered by time window or device.
+pub fn record_advertisement(adapter: &str) {
+    counter!(ADVERTISEMENTS_TOTAL, "adapter" => adapter.to_string()).increment(1);
+    gauge!(LAST_ADVERTISEMENT_TIMESTAMP, "adapter" => adapter.to_string()).set(unix_now());
+}
+
+/// Counts an advertisement skipped because its payload was already processed for the bucket.
+pub fn record_skipped_advertisement(adapter: &str) {
+    counter!(SKIPPED_ADVERTISEMENTS_TOTAL, "adapter" => adapter.to_string()).increment(1);
+}
+
+pub fn record_scan_restart(adapter: &str) {
+    counter!(SCAN_RESTARTS_TOTAL, "adapter" => adapter.to_string()).increment(1);
+}
+
+pub fn record_device_seen(device_id: &str, device_name: &str, timestamp: i64) {
+    gauge!(
+        DEVICE_LAST_SEEN_TIMESTAMP,
+        "device_id" => device_id.to_string(),
+        "device_name" => device_name.to_string()
+    )
+    .set(timestamp as f64);
+}
+
+pub fn set_stale_devices(count: usize) {
+    gauge!(STALE_DEVICES).set(count as f64);
+}
+
+pub fn record_decode_failure(device_id: &str, device_name: &str, device_type: &str) {
+    counter!(
+        DECODE_FAILURES_TOTAL,
+        "device_id" => device_id.to_string(),
+        "device_name" => device_name.to_string(),
+        "device_type" => device_type.to_string()
+    )
+    .increment(1);
+}
+
+pub fn record_insert(batch_size: usize, duration: Duration) {
+    histogram!(INSERT_BATCH_SIZE).record(batch_size as f64);
+    histogram!(INSERT_DURATION_SECONDS).record(duration.as_secs_f64());
+}
+
+pub fn record_db_error() {
+    counter!(DB_ERRORS_TOTAL).increment(1);
+}
+
+pub fn record_rejected_rows(count: usize) {
+    counter!(REJECTED_ROWS_TOTAL).increment(count as u64);
+}
+
+pub fn record_rejected_outlier(metric: &'static str) {
+    counter!(REJECTED_OUTLIERS_TOTAL, "metric" => metric).increment(1);
+}
+
+pub fn record_clock_jump() {
+    counter!(CLOCK_JUMPS_TOTAL).increment(1);
+}
+
+pub fn record_gatt_read(success: bool) {
+    let result = if success { "success" } else { "failure" };
+    counter!(GATT_READS_TOTAL, "result" => result).increment(1);
+}
+
+pub fn record_cloud_poll(success: bool) {
+    let result = if success { "success" } else { "failure" };
+    counter!(CLOUD_POLLS_TOTAL, "result" => result).increment(1);
+}
+
+pub fn record_mqtt_dropped_message() {
+    counter!(MQTT_DROPPED_MESSAGES_TOTAL).increment(1);
+}
+
+fn unix_now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
@@ -0,0 +1,36 @@
+use std::net::SocketAddr;
+
+use chrono_tz::Tz;
+use clap::Parser;
+use home_environments::logging::LogFormat;
+
+#[derive(Debug, Parser)]
+pub struct Args {
+    /// Address to serve the API on, e.g. `0.0.0.0:8080` to reach it from other hosts.
+    #[arg(long, env = "HTTP_ADDR", default_value = "127.0.0.1:8080")]
+    pub http_addr: SocketAddr,
+
+    /// Address to also serve the gRPC service of `proto/home_environments.proto` on, e.g.
+    /// `0.0.0.0:50051`.
+    #[arg(long, env = "GRPC_ADDR")]
+    pub grpc_addr: Option<SocketAddr>,
+
+    /// Timezone the times are returned in and the buckets of `resolution` are aligned to.
+    #[arg(long, env = "TZ")]
+    pub timezone: Tz,
+
+    /// Most measurements returned at the raw resolution, beyond which a coarser one must be asked
+    /// for.
+    #[arg(long, env = "MAX_ROWS", default_value_t = 100_000)]
+    pub max_rows: usize,
+
+    #[arg(long, env = "DATABASE_URL")]
+    pub database_url: String,
+
+    /// Log filter such as `debug` or `info,sqlx=warn`. Overrides `RUST_LOG`.
+    #[arg(long, env = "LOG_LEVEL")]
+    pub log_level: Option<String>,
+
+    #[arg(long, env = "LOG_FORMAT", value_enum)]
+    pub log_format: Option<LogFormat>,
+}
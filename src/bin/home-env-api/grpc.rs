@@ -0,0 +1,197 @@
+use std::{collections::HashMap, time::Duration};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use home_environments::{
+    db::{get_latest_switchbot_measurements, get_switchbot_devices, stream_switchbot_measurements},
+    switchbot::{Device, Measurement},
+};
+use macaddr::MacAddr6;
+use prost_types::Timestamp;
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+use tokio_stream::{StreamExt as _, wrappers::ReceiverStream};
+use tonic::{Request, Response, Status};
+use tracing::error;
+
+use self::proto::{DeviceFilter, QueryRequest, measurements_server::Measurements};
+
+pub mod proto {
+    tonic::include_proto!("home_environments.v1");
+}
+
+pub use self::proto::measurements_server::MeasurementsServer;
+
+/// How often the database is checked for the new measurements of the subscribers.
+const SUBSCRIBE_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Measurements that may wait for a slow client before reading from the database pauses.
+const CHANNEL_CAPACITY: usize = 64;
+
+type MeasurementStream = ReceiverStream<Result<proto::Measurement, Status>>;
+
+/// The `Measurements` service of `proto/home_environments.proto`.
+#[derive(Debug, Clone)]
+pub struct MeasurementsService {
+    pub pool: PgPool,
+}
+
+#[tonic::async_trait]
+impl Measurements for MeasurementsService {
+    type SubscribeStream = MeasurementStream;
+    type QueryStream = MeasurementStream;
+
+    async fn subscribe(
+        &self,
+        request: Request<DeviceFilter>,
+    ) -> Result<Response<MeasurementStream>, Status> {
+        let filter = request.into_inner();
+        let device_ids = filter
+            .device_ids
+            .iter()
+            .map(|id| parse_device_id(id))
+            .collect::<Result<Vec<_>, _>>()?;
+        let matches = move |device: &Device| {
+            (device_ids.is_empty() || device_ids.contains(&device.id))
+                && (filter.rooms.is_empty()
+                    || device
+                        .room
+                        .as_ref()
+                        .is_some_and(|room| filter.rooms.contains(&room.name)))
+        };
+
+        // Each device resumes after the latest measurement it had at the time of the call.
+        let mut cursors: HashMap<MacAddr6, DateTime<Utc>> =
+            get_latest_switchbot_measurements(&self.pool)
+                .await
+                .map_err(internal)?
+                .into_iter()
+                .map(|latest| (latest.device.id, latest.measurement.measured_at))
+                .collect();
+
+        let pool = self.pool.clone();
+        let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(SUBSCRIBE_POLL_INTERVAL);
+            while !tx.is_closed() {
+                ticker.tick().await;
+
+                let devices = match get_switchbot_devices(&pool).await {
+                    Ok(devices) => devices,
+                    Err(err) => {
+                        let _ = tx.send(Err(internal(err))).await;
+                        return;
+                    }
+                };
+                for device in devices.iter().filter(|device| matches(device)) {
+                    let from = cursors.entry(device.id).or_insert(DateTime::UNIX_EPOCH);
+                    let start = *from + TimeDelta::microseconds(1);
+                    let range = start.with_timezone(&chrono_tz::UTC)
+                        ..Utc::now().with_timezone(&chrono_tz::UTC);
+                    let mut measurements = stream_switchbot_measurements(&pool, device.id, range);
+                    while let Some(measurement) = measurements.next().await {
+                        let message = measurement
+                            .map(|measurement| {
+                                *from = measurement.measured_at;
+                                message(device, measurement)
+                            })
+                            .map_err(internal);
+                        let failed = message.is_err();
+                        if tx.send(message).await.is_err() || failed {
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn query(
+        &self,
+        request: Request<QueryRequest>,
+    ) -> Result<Response<MeasurementStream>, Status> {
+        let request = request.into_inner();
+        let device_id = parse_device_id(&request.device_id)?;
+        let device = get_switchbot_devices(&self.pool)
+            .await
+            .map_err(internal)?
+            .into_iter()
+            .find(|device| device.id == device_id)
+            .ok_or_else(|| Status::not_found(format!("unknown device: {device_id}")))?;
+
+        let to = request
+            .to
+            .map(date_time)
+            .transpose()?
+            .unwrap_or_else(Utc::now);
+        let from = request
+            .from
+            .map(date_time)
+            .transpose()?
+            .unwrap_or(to - TimeDelta::days(1));
+        if from >= to {
+            return Err(Status::invalid_argument("from must be before to"));
+        }
+
+        let pool = self.pool.clone();
+        let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+        tokio::spawn(async move {
+            let mut measurements = stream_switchbot_measurements(
+                &pool,
+                device.id,
+                from.with_timezone(&chrono_tz::UTC)..to.with_timezone(&chrono_tz::UTC),
+            );
+            while let Some(measurement) = measurements.next().await {
+                let message = measurement
+                    .map(|measurement| message(&device, measurement))
+                    .map_err(internal);
+                let failed = message.is_err();
+                if tx.send(message).await.is_err() || failed {
+                    return;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+fn parse_device_id(id: &str) -> Result<MacAddr6, Status> {
+    id.parse()
+        .map_err(|_| Status::invalid_argument(format!("invalid device ID: {id}")))
+}
+
+fn date_time(timestamp: Timestamp) -> Result<DateTime<Utc>, Status> {
+    u32::try_from(timestamp.nanos)
+        .ok()
+        .and_then(|nanos| DateTime::from_timestamp(timestamp.seconds, nanos))
+        .ok_or_else(|| Status::invalid_argument(format!("invalid timestamp: {timestamp}")))
+}
+
+fn timestamp(date_time: DateTime<Utc>) -> Timestamp {
+    Timestamp {
+        seconds: date_time.timestamp(),
+        nanos: date_time.timestamp_subsec_nanos() as i32,
+    }
+}
+
+fn message(device: &Device, measurement: Measurement) -> proto::Measurement {
+    proto::Measurement {
+        device_id: device.id.to_string(),
+        device_name: device.name.clone(),
+        room: device.room.as_ref().map(|room| room.name.clone()),
+        measured_at: Some(timestamp(measurement.measured_at)),
+        temperature_celsius: measurement.temperature_celsius,
+        humidity_percent: measurement.humidity_percent.into(),
+        co2_ppm: measurement.co2_ppm.map(Into::into),
+        light_level: measurement.light_level.map(Into::into),
+        rssi_dbm: measurement.rssi_dbm.map(Into::into),
+    }
+}
+
+/// A failure of the database, which is logged rather than returned, like in the REST API.
+fn internal(err: anyhow::Error) -> Status {
+    error!(error = format!("{err:#}"), "gRPC request failed");
+    Status::internal("internal server error")
+}
@@ -0,0 +1,102 @@
+mod args;
+mod error;
+mod grpc;
+mod routes;
+
+use std::process::ExitCode;
+
+use anyhow::{Context as _, Result};
+use args::Args;
+use clap::Parser as _;
+use home_environments::{db::new_pool, logging};
+use tokio::net::TcpListener;
+use tracing::{error, info};
+
+use crate::{
+    grpc::{MeasurementsServer, MeasurementsService},
+    routes::ApiState,
+};
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = Args::parse();
+
+    if let Err(e) = logging::init(
+        args.log_level.as_deref(),
+        args.log_format.unwrap_or_default(),
+    ) {
+        eprintln!("failed to initialize logging: {e:#}");
+        return ExitCode::from(1);
+    }
+
+    if let Err(e) = run(args).await {
+        error!("{e:#}");
+        return ExitCode::from(1);
+    }
+
+    ExitCode::from(0)
+}
+
+async fn run(args: Args) -> Result<()> {
+    let pool = new_pool(&args.database_url)
+        .await
+        .context("failed to connect to database")?;
+
+    let listener = TcpListener::bind(args.http_addr)
+        .await
+        .with_context(|| format!("failed to listen on {}", args.http_addr))?;
+    let router = routes::router(ApiState {
+        pool: pool.clone(),
+        timezone: args.timezone,
+        max_rows: args.max_rows,
+    });
+
+    if let Some(grpc_addr) = args.grpc_addr {
+        let service = MeasurementsServer::new(MeasurementsService { pool });
+        info!(addr = %grpc_addr, "serving the gRPC service");
+        tokio::spawn(async move {
+            if let Err(err) = tonic::transport::Server::builder()
+                .add_service(service)
+                .serve_with_shutdown(grpc_addr, shutdown())
+                .await
+            {
+                error!(error = format!("{err:#}"), "gRPC server stopped");
+            }
+        });
+    }
+
+    info!(addr = %args.http_addr, "serving the API");
+    axum::serve(listener, router)
+        .with_graceful_shutdown(shutdown())
+        .await
+        .context("HTTP server stopped")?;
+    info!("shut down");
+
+    Ok(())
+}
+
+async fn shutdown() {
+    if let Err(err) = shutdown_signal().await {
+        error!(
+            error = format!("{err:#}"),
+            "failed to listen for shutdown signals"
+        );
+    }
+}
+
+async fn shutdown_signal() -> Result<()> {
+    #[cfg(unix)]
+    {
+        let mut sigterm =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result?,
+            _ = sigterm.recv() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
+
+    Ok(())
+}
@@ -0,0 +1,290 @@
+use axum::{
+    Router,
+    extract::{Path, Query, State},
+    response::Json,
+    routing::get,
+};
+use chrono::{DateTime, FixedOffset, TimeDelta, Utc};
+use chrono_tz::Tz;
+use home_environments::{
+    db::{
+        get_latest_switchbot_measurements, get_switchbot_devices,
+        get_switchbot_measurement_buckets, stream_switchbot_measurements,
+    },
+    forecast::{MAX_HOURS, forecast_rooms},
+    switchbot::{BucketSize, Device, Measurement, MeasurementBucket},
+};
+use macaddr::MacAddr6;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tokio_stream::StreamExt as _;
+
+use crate::error::ApiError;
+
+#[derive(Debug, Clone)]
+pub struct ApiState {
+    pub pool: PgPool,
+    pub timezone: Tz,
+    pub max_rows: usize,
+}
+
+pub fn router(state: ApiState) -> Router {
+    Router::new()
+        .route("/devices", get(devices))
+        .route("/devices/{id}/measurements", get(measurements))
+        .route("/latest", get(latest))
+        .route("/forecast", get(forecast))
+        .with_state(state)
+}
+
+#[derive(Debug, Serialize)]
+struct DeviceJson {
+    id: String,
+    r#type: &'static str,
+    name: String,
+    enabled: bool,
+    room: Option<String>,
+    notes: Option<String>,
+}
+
+impl From<Device> for DeviceJson {
+    fn from(device: Device) -> Self {
+        Self {
+            id: device.id.to_string(),
+            r#type: device.r#type.as_str(),
+            name: device.name,
+            enabled: device.enabled,
+            room: device.room.map(|room| room.name),
+            notes: device.notes,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct MeasurementJson {
+    measured_at: DateTime<Tz>,
+    temperature_celsius: f32,
+    humidity_percent: u8,
+    co2_ppm: Option<u16>,
+    light_level: Option<u8>,
+    rssi_dbm: Option<i16>,
+}
+
+impl MeasurementJson {
+    fn new(measurement: Measurement, timezone: Tz) -> Self {
+        Self {
+            measured_at: measurement.measured_at.with_timezone(&timezone),
+            temperature_celsius: measurement.temperature_celsius,
+            humidity_percent: measurement.humidity_percent,
+            co2_ppm: measurement.co2_ppm,
+            light_level: measurement.light_level,
+            rssi_dbm: measurement.rssi_dbm,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct BucketJson {
+    bucket_start: DateTime<Tz>,
+    sample_count: u32,
+    temperature_celsius_min: f32,
+    temperature_celsius_avg: f64,
+    temperature_celsius_max: f32,
+    humidity_percent_min: u8,
+    humidity_percent_avg: f64,
+    humidity_percent_max: u8,
+    co2_ppm_min: Option<u16>,
+    co2_ppm_avg: Option<f64>,
+    co2_ppm_max: Option<u16>,
+    light_level_min: Option<u8>,
+    light_level_avg: Option<f64>,
+    light_level_max: Option<u8>,
+}
+
+impl From<MeasurementBucket> for BucketJson {
+    fn from(bucket: MeasurementBucket) -> Self {
+        Self {
+            bucket_start: bucket.bucket_start,
+            sample_count: bucket.sample_count,
+            temperature_celsius_min: bucket.temperature_celsius_min,
+            temperature_celsius_avg: bucket.temperature_celsius_avg,
+            temperature_celsius_max: bucket.temperature_celsius_max,
+            humidity_percent_min: bucket.humidity_percent_min,
+            humidity_percent_avg: bucket.humidity_percent_avg,
+            humidity_percent_max: bucket.humidity_percent_max,
+            co2_ppm_min: bucket.co2_ppm_min,
+            co2_ppm_avg: bucket.co2_ppm_avg,
+            co2_ppm_max: bucket.co2_ppm_max,
+            light_level_min: bucket.light_level_min,
+            light_level_avg: bucket.light_level_avg,
+            light_level_max: bucket.light_level_max,
+        }
+    }
+}
+
+/// Every measurement at the raw resolution, or an aggregate per bucket.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum MeasurementsJson {
+    Raw(Vec<MeasurementJson>),
+    Buckets(Vec<BucketJson>),
+}
+
+#[derive(Debug, Serialize)]
+struct LatestJson {
+    device_id: String,
+    device_name: String,
+    #[serde(flatten)]
+    measurement: MeasurementJson,
+}
+
+#[derive(Debug, Serialize)]
+struct RoomForecastJson {
+    room: String,
+    hours: Vec<HourForecastJson>,
+}
+
+#[derive(Debug, Serialize)]
+struct HourForecastJson {
+    at: DateTime<Tz>,
+    temperature_celsius: Option<f64>,
+    co2_ppm: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Resolution {
+    #[default]
+    Raw,
+    Hour,
+    Day,
+}
+
+#[derive(Debug, Deserialize)]
+struct MeasurementsQuery {
+    /// Defaults to a day before `to`.
+    from: Option<DateTime<FixedOffset>>,
+    /// Excluded. Defaults to now.
+    to: Option<DateTime<FixedOffset>>,
+    #[serde(default)]
+    resolution: Resolution,
+}
+
+#[derive(Debug, Deserialize)]
+struct ForecastQuery {
+    /// Defaults to the longest forecast.
+    hours: Option<u32>,
+}
+
+/// `GET /devices`: the registered devices, in their sort order.
+async fn devices(State(state): State<ApiState>) -> Result<Json<Vec<DeviceJson>>, ApiError> {
+    let devices = get_switchbot_devices(&state.pool).await?;
+
+    Ok(Json(devices.into_iter().map(DeviceJson::from).collect()))
+}
+
+/// `GET /devices/{id}/measurements?from=&to=&resolution=`: the measurements of a device from
+/// `from` up to `to`, both RFC 3339, oldest first. `resolution` is `raw`, `hour` or `day`.
+async fn measurements(
+    State(state): State<ApiState>,
+    Path(id): Path<String>,
+    Query(query): Query<MeasurementsQuery>,
+) -> Result<Json<MeasurementsJson>, ApiError> {
+    let device_id: MacAddr6 = id
+        .parse()
+        .map_err(|_| ApiError::BadRequest(format!("invalid device ID: {id}")))?;
+    if !get_switchbot_devices(&state.pool)
+        .await?
+        .iter()
+        .any(|device| device.id == device_id)
+    {
+        return Err(ApiError::NotFound(format!("unknown device: {device_id}")));
+    }
+
+    let to = query
+        .to
+        .map_or_else(Utc::now, |to| to.with_timezone(&Utc))
+        .with_timezone(&state.timezone);
+    let from = query.from.map_or_else(
+        || to - TimeDelta::days(1),
+        |from| from.with_timezone(&state.timezone),
+    );
+    if from >= to {
+        return Err(ApiError::BadRequest("from must be before to".to_string()));
+    }
+    let range = from..to;
+
+    let size = match query.resolution {
+        Resolution::Raw => {
+            let mut measurements = Vec::new();
+            let mut stream = stream_switchbot_measurements(&state.pool, device_id, range);
+            while let Some(measurement) = stream.next().await {
+                if measurements.len() == state.max_rows {
+                    return Err(ApiError::BadRequest(format!(
+                        "more than {} measurements, ask for a shorter range or resolution=hour",
+                        state.max_rows
+                    )));
+                }
+                measurements.push(MeasurementJson::new(measurement?, state.timezone));
+            }
+
+            return Ok(Json(MeasurementsJson::Raw(measurements)));
+        }
+        Resolution::Hour => BucketSize::Hour,
+        Resolution::Day => BucketSize::Day,
+    };
+    let buckets = get_switchbot_measurement_buckets(&state.pool, device_id, range, size).await?;
+
+    Ok(Json(MeasurementsJson::Buckets(
+        buckets.into_iter().map(BucketJson::from).collect(),
+    )))
+}
+
+/// `GET /latest`: the most recent measurement of every device, in their sort order.
+async fn latest(State(state): State<ApiState>) -> Result<Json<Vec<LatestJson>>, ApiError> {
+    let latest = get_latest_switchbot_measurements(&state.pool).await?;
+
+    Ok(Json(
+        latest
+            .into_iter()
+            .map(|latest| LatestJson {
+                device_id: latest.device.id.to_string(),
+                device_name: latest.device.name,
+                measurement: MeasurementJson::new(latest.measurement, state.timezone),
+            })
+            .collect(),
+    ))
+}
+
+/// `GET /forecast?hours=`: the predicted temperature and CO2 concentration of every room for each
+/// of the next `hours` hours, at most 3.
+async fn forecast(
+    State(state): State<ApiState>,
+    Query(query): Query<ForecastQuery>,
+) -> Result<Json<Vec<RoomForecastJson>>, ApiError> {
+    let hours = query.hours.unwrap_or(MAX_HOURS);
+    if !(1..=MAX_HOURS).contains(&hours) {
+        return Err(ApiError::BadRequest(format!(
+            "hours must be between 1 and {MAX_HOURS}"
+        )));
+    }
+    let forecasts = forecast_rooms(&state.pool, Utc::now(), hours).await?;
+
+    Ok(Json(
+        forecasts
+            .into_iter()
+            .map(|forecast| RoomForecastJson {
+                room: forecast.room,
+                hours: forecast
+                    .hours
+                    .into_iter()
+                    .map(|hour| HourForecastJson {
+                        at: hour.at.with_timezone(&state.timezone),
+                        temperature_celsius: hour.temperature_celsius,
+                        co2_ppm: hour.co2_ppm,
+                    })
+                    .collect(),
+            })
+            .collect(),
+    ))
+}
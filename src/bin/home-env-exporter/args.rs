@@ -0,0 +1,21 @@
+use std::net::SocketAddr;
+
+use clap::Parser;
+use home_environments::logging::LogFormat;
+
+#[derive(Debug, Parser)]
+pub struct Args {
+    /// Address to serve `/metrics` on, e.g. `0.0.0.0:9101` for a Prometheus on another host.
+    #[arg(long, env = "METRICS_ADDR", default_value = "127.0.0.1:9101")]
+    pub metrics_addr: SocketAddr,
+
+    #[arg(long, env = "DATABASE_URL")]
+    pub database_url: String,
+
+    /// Log filter such as `debug` or `info,sqlx=warn`. Overrides `RUST_LOG`.
+    #[arg(long, env = "LOG_LEVEL")]
+    pub log_level: Option<String>,
+
+    #[arg(long, env = "LOG_FORMAT", value_enum)]
+    pub log_format: Option<LogFormat>,
+}
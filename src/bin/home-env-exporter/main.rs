@@ -0,0 +1,78 @@
+mod args;
+mod scrape;
+
+use std::process::ExitCode;
+
+use anyhow::{Context as _, Result};
+use args::Args;
+use axum::{Router, routing::get};
+use clap::Parser as _;
+use home_environments::{db::new_pool, logging};
+use tokio::net::TcpListener;
+use tracing::{error, info};
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = Args::parse();
+
+    if let Err(e) = logging::init(
+        args.log_level.as_deref(),
+        args.log_format.unwrap_or_default(),
+    ) {
+        eprintln!("failed to initialize logging: {e:#}");
+        return ExitCode::from(1);
+    }
+
+    if let Err(e) = run(args).await {
+        error!("{e:#}");
+        return ExitCode::from(1);
+    }
+
+    ExitCode::from(0)
+}
+
+async fn run(args: Args) -> Result<()> {
+    let pool = new_pool(&args.database_url)
+        .await
+        .context("failed to connect to database")?;
+
+    let listener = TcpListener::bind(args.metrics_addr)
+        .await
+        .with_context(|| format!("failed to listen on {}", args.metrics_addr))?;
+    let router = Router::new()
+        .route("/metrics", get(scrape::metrics))
+        .with_state(pool);
+
+    info!(addr = %args.metrics_addr, "serving metrics");
+    axum::serve(listener, router)
+        .with_graceful_shutdown(async {
+            if let Err(err) = shutdown_signal().await {
+                error!(
+                    error = format!("{err:#}"),
+                    "failed to listen for shutdown signals"
+                );
+            }
+        })
+        .await
+        .context("HTTP server stopped")?;
+    info!("shut down");
+
+    Ok(())
+}
+
+async fn shutdown_signal() -> Result<()> {
+    #[cfg(unix)]
+    {
+        let mut sigterm =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result?,
+            _ = sigterm.recv() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
+
+    Ok(())
+}
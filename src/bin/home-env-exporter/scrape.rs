@@ -0,0 +1,78 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse};
+use chrono::Utc;
+use home_environments::{db::get_latest_switchbot_measurements, switchbot::LatestMeasurement};
+use metrics::{describe_gauge, gauge, with_local_recorder};
+use metrics_exporter_prometheus::PrometheusBuilder;
+use sqlx::PgPool;
+use tracing::error;
+
+const TEMPERATURE_CELSIUS: &str = "home_temperature_celsius";
+const HUMIDITY_PERCENT: &str = "home_humidity_percent";
+const CO2_PPM: &str = "home_co2_ppm";
+const LIGHT_LEVEL: &str = "home_light_level";
+const MEASUREMENT_AGE_SECONDS: &str = "home_measurement_age_seconds";
+
+/// `GET /metrics`: the latest measurement of every device, read from the database on each scrape.
+///
+/// The gauges are recorded into a fresh recorder every time, so that a removed device disappears
+/// rather than repeating its last value.
+pub async fn metrics(State(pool): State<PgPool>) -> impl IntoResponse {
+    let latest = match get_latest_switchbot_measurements(&pool).await {
+        Ok(latest) => latest,
+        Err(err) => {
+            error!(error = format!("{err:#}"), "failed to scrape");
+            return (StatusCode::INTERNAL_SERVER_ERROR, String::new());
+        }
+    };
+
+    let recorder = PrometheusBuilder::new().build_recorder();
+    with_local_recorder(&recorder, || {
+        describe();
+        for latest in &latest {
+            record(latest);
+        }
+    });
+
+    (StatusCode::OK, recorder.handle().render())
+}
+
+fn describe() {
+    describe_gauge!(TEMPERATURE_CELSIUS, "Latest temperature of the device.");
+    describe_gauge!(HUMIDITY_PERCENT, "Latest relative humidity of the device.");
+    describe_gauge!(CO2_PPM, "Latest CO2 concentration of the device.");
+    describe_gauge!(LIGHT_LEVEL, "Latest light level of the device.");
+    describe_gauge!(
+        MEASUREMENT_AGE_SECONDS,
+        "Seconds since the latest measurement of the device."
+    );
+}
+
+fn record(latest: &LatestMeasurement) {
+    let LatestMeasurement {
+        device,
+        measurement,
+    } = latest;
+    let labels = [
+        ("device", device.name.clone()),
+        ("device_id", device.id.to_string()),
+        (
+            "room",
+            device
+                .room
+                .as_ref()
+                .map(|room| room.name.clone())
+                .unwrap_or_default(),
+        ),
+    ];
+
+    gauge!(TEMPERATURE_CELSIUS, &labels).set(measurement.temperature_celsius);
+    gauge!(HUMIDITY_PERCENT, &labels).set(measurement.humidity_percent);
+    if let Some(co2_ppm) = measurement.co2_ppm {
+        gauge!(CO2_PPM, &labels).set(co2_ppm);
+    }
+    if let Some(light_level) = measurement.light_level {
+        gauge!(LIGHT_LEVEL, &labels).set(light_level);
+    }
+    let age = Utc::now() - measurement.measured_at;
+    gauge!(MEASUREMENT_AGE_SECONDS, &labels).set(age.as_seconds_f64());
+}
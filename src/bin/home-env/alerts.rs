@@ -0,0 +1,344 @@
+use std::{fmt::Write as _, fs, mem, path::Path, time::Duration};
+
+use anyhow::{Context as _, Result, bail};
+use chrono::{TimeDelta, Utc};
+use chrono_tz::Tz;
+use home_environments::{
+    db::{get_latest_switchbot_measurements, get_switchbot_devices, stream_switchbot_measurements},
+    switchbot::{Device, Measurement},
+};
+use serde::Deserialize;
+use sqlx::PgPool;
+use tokio_stream::StreamExt as _;
+use tracing::{info, warn};
+
+use crate::{
+    args::AlertsArgs,
+    notify::{Channel, Message, Notifier, sparkline},
+    select_device,
+};
+
+const DEFAULT_INTERVAL: Duration = Duration::from_mins(1);
+
+/// How far back the sparkline of a message goes.
+const SPARKLINE_PERIOD: TimeDelta = TimeDelta::hours(1);
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(default, with = "humantime_serde")]
+    interval: Option<Duration>,
+    /// Notified for the rules without channels of their own, and of reports.
+    #[serde(default)]
+    channels: Vec<Channel>,
+    #[serde(default)]
+    rules: Vec<RuleConfigFile>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleConfigFile {
+    /// Name or MAC address.
+    device: String,
+    metric: Metric,
+    above: Option<f64>,
+    below: Option<f64>,
+    #[serde(default)]
+    channels: Vec<Channel>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Metric {
+    Temperature,
+    Humidity,
+    Co2,
+    LightLevel,
+}
+
+impl Metric {
+    fn value(&self, measurement: &Measurement) -> Option<f64> {
+        match self {
+            Metric::Temperature => Some(measurement.temperature_celsius.into()),
+            Metric::Humidity => Some(measurement.humidity_percent.into()),
+            Metric::Co2 => measurement.co2_ppm.map(f64::from),
+            Metric::LightLevel => measurement.light_level.map(f64::from),
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Metric::Temperature => "Temperature",
+            Metric::Humidity => "Humidity",
+            Metric::Co2 => "CO2",
+            Metric::LightLevel => "Light level",
+        }
+    }
+
+    /// `value` with its unit.
+    fn format(&self, value: f64) -> String {
+        match self {
+            Metric::Temperature => format!("{value:.1} °C"),
+            Metric::Humidity => format!("{value:.0} %"),
+            Metric::Co2 => format!("{value:.0} ppm"),
+            Metric::LightLevel => format!("{value:.0}"),
+        }
+    }
+}
+
+/// Which threshold a value crossed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Crossing {
+    Above(f64),
+    Below(f64),
+}
+
+struct Rule {
+    device: Device,
+    metric: Metric,
+    above: Option<f64>,
+    below: Option<f64>,
+    channels: Vec<Channel>,
+    /// The threshold that the latest value is beyond.
+    firing: Option<Crossing>,
+    /// The messages that are yet to be delivered, by index of their channel and in order.
+    pending: Vec<(usize, Message)>,
+}
+
+impl Rule {
+    fn crossing(&self, value: f64) -> Option<Crossing> {
+        if let Some(above) = self.above.filter(|above| value > *above) {
+            return Some(Crossing::Above(above));
+        }
+        self.below
+            .filter(|below| value < *below)
+            .map(Crossing::Below)
+    }
+}
+
+/// Checks every rule against the latest measurements every `interval`, and notifies when a value
+/// crosses a threshold and when it is back within it. A notification that fails is retried at the
+/// next check, on the channels it failed on only.
+pub async fn run(pool: &PgPool, args: AlertsArgs) -> Result<()> {
+    let config = read_config_file(&args.config)?;
+    if config.rules.is_empty() {
+        bail!("no rules in {}", args.config.display());
+    }
+    let interval = config.interval.unwrap_or(DEFAULT_INTERVAL);
+    if interval.is_zero() {
+        bail!(
+            "interval must be greater than zero in {}",
+            args.config.display()
+        );
+    }
+    let devices = get_switchbot_devices(pool)
+        .await
+        .context("failed to get SwitchBot devices")?;
+    let mut rules = config
+        .rules
+        .into_iter()
+        .map(|rule| {
+            if rule.above.is_none() && rule.below.is_none() {
+                bail!("the rule for {:?} has neither above nor below", rule.device);
+            }
+            let channels = match rule.channels.is_empty() {
+                true => config.channels.clone(),
+                false => rule.channels,
+            };
+            if channels.is_empty() {
+                bail!("the rule for {:?} has no channels", rule.device);
+            }
+
+            Ok(Rule {
+                device: select_device(&devices, &rule.device)?.clone(),
+                metric: rule.metric,
+                above: rule.above,
+                below: rule.below,
+                channels,
+                firing: None,
+                pending: Vec::new(),
+            })
+        })
+        .collect::<Result<Vec<_>>>()
+        .with_context(|| format!("invalid rules in {}", args.config.display()))?;
+
+    let notifier = Notifier::new()?;
+    info!(rules = rules.len(), interval = %humantime::format_duration(interval), "watching");
+
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        if let Err(err) = check(pool, &notifier, &mut rules, args.timezone).await {
+            warn!(error = format!("{err:#}"), "failed to check rules");
+        }
+    }
+}
+
+async fn check(pool: &PgPool, notifier: &Notifier, rules: &mut [Rule], timezone: Tz) -> Result<()> {
+    let latest = get_latest_switchbot_measurements(pool).await?;
+
+    for rule in rules {
+        let measurement = latest
+            .iter()
+            .find(|latest| latest.device.id == rule.device.id)
+            .map(|latest| &latest.measurement);
+        if let Some((measurement, value)) =
+            measurement.and_then(|m| Some((m, rule.metric.value(m)?)))
+        {
+            let crossing = rule.crossing(value);
+            let message = match (crossing, rule.firing) {
+                (crossing, firing) if crossing == firing => None,
+                (Some(crossing), _) => {
+                    let recent = recent_values(pool, &rule.device, rule.metric).await?;
+                    Some(alert_message(
+                        rule,
+                        crossing,
+                        value,
+                        measurement,
+                        &recent,
+                        timezone,
+                    ))
+                }
+                (None, Some(firing)) => Some(resolved_message(rule, firing, value)),
+                (None, None) => None,
+            };
+            if let Some(message) = message {
+                rule.firing = crossing;
+                rule.pending
+                    .extend((0..rule.channels.len()).map(|channel| (channel, message.clone())));
+            }
+        }
+
+        deliver(notifier, rule).await;
+    }
+
+    Ok(())
+}
+
+/// Sends the pending messages of `rule`, keeping those of a channel from its first failure on.
+async fn deliver(notifier: &Notifier, rule: &mut Rule) {
+    let mut failed = Vec::new();
+    for (channel, message) in mem::take(&mut rule.pending) {
+        if !failed.contains(&channel) {
+            match notifier.send(&rule.channels[channel], &message).await {
+                Ok(()) => {
+                    info!(device = rule.device.name, title = message.title, "notified");
+                    continue;
+                }
+                Err(err) => {
+                    warn!(error = format!("{err:#}"), "failed to notify");
+                    failed.push(channel);
+                }
+            }
+        }
+        rule.pending.push((channel, message));
+    }
+}
+
+/// The values of `metric` over the last [`SPARKLINE_PERIOD`], oldest first.
+async fn recent_values(pool: &PgPool, device: &Device, metric: Metric) -> Result<Vec<f64>> {
+    let now = Utc::now().with_timezone(&chrono_tz::UTC);
+    let mut measurements =
+        stream_switchbot_measurements(pool, device.id, now - SPARKLINE_PERIOD..now);
+    let mut values = Vec::new();
+    while let Some(measurement) = measurements.next().await {
+        values.extend(metric.value(&measurement?));
+    }
+
+    Ok(values)
+}
+
+fn alert_message(
+    rule: &Rule,
+    crossing: Crossing,
+    value: f64,
+    measurement: &Measurement,
+    recent: &[f64],
+    timezone: Tz,
+) -> Message {
+    let metric = rule.metric;
+    let (direction, threshold) = match crossing {
+        Crossing::Above(threshold) => ("above", threshold),
+        Crossing::Below(threshold) => ("below", threshold),
+    };
+
+    let mut body = format!(
+        "{} is {} (threshold {}) at {}",
+        metric.label(),
+        metric.format(value),
+        metric.format(threshold),
+        measurement
+            .measured_at
+            .with_timezone(&timezone)
+            .format("%Y-%m-%d %H:%M"),
+    );
+    if let (Some(min), Some(max)) = (
+        recent.iter().copied().reduce(f64::min),
+        recent.iter().copied().reduce(f64::max),
+    ) {
+        let _ = write!(
+            body,
+            "\nLast hour: `{}` ({} – {})",
+            sparkline(recent),
+            metric.format(min),
+            metric.format(max),
+        );
+    }
+
+    Message {
+        title: format!(
+            "{}: {} {direction} {}",
+            rule.device.name,
+            metric.label(),
+            metric.format(threshold)
+        ),
+        body,
+        fields: fields(rule, value, threshold),
+    }
+}
+
+fn resolved_message(rule: &Rule, crossing: Crossing, value: f64) -> Message {
+    let metric = rule.metric;
+    let (direction, threshold) = match crossing {
+        Crossing::Above(threshold) => ("below", threshold),
+        Crossing::Below(threshold) => ("above", threshold),
+    };
+
+    Message {
+        title: format!(
+            "{}: {} back {direction} {}",
+            rule.device.name,
+            metric.label(),
+            metric.format(threshold)
+        ),
+        body: format!("{} is {}", metric.label(), metric.format(value)),
+        fields: fields(rule, value, threshold),
+    }
+}
+
+/// The placeholders of the email templates.
+fn fields(rule: &Rule, value: f64, threshold: f64) -> Vec<(&'static str, String)> {
+    vec![
+        ("device", rule.device.name.clone()),
+        ("metric", rule.metric.label().to_string()),
+        ("value", rule.metric.format(value)),
+        ("threshold", rule.metric.format(threshold)),
+    ]
+}
+
+/// The top-level channels of the config file at `path`.
+pub fn read_channels(path: &Path) -> Result<Vec<Channel>> {
+    let channels = read_config_file(path)?.channels;
+    if channels.is_empty() {
+        bail!("no channels in {}", path.display());
+    }
+
+    Ok(channels)
+}
+
+fn read_config_file(path: &Path) -> Result<ConfigFile> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("failed to read config file: {path:?}"))?;
+
+    toml::from_str(&content).with_context(|| format!("failed to parse config file: {path:?}"))
+}
@@ -0,0 +1,243 @@
+use std::path::PathBuf;
+
+use chrono::NaiveDate;
+use chrono_tz::Tz;
+use clap::{Parser, Subcommand, ValueEnum};
+use home_environments::{forecast, logging::LogFormat, switchbot::DeviceType};
+use macaddr::MacAddr6;
+
+#[derive(Debug, Parser)]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Command,
+
+    #[arg(long, env = "DATABASE_URL")]
+    pub database_url: String,
+
+    /// Log filter such as `debug` or `info,sqlx=warn`. Overrides `RUST_LOG`.
+    #[arg(long, env = "LOG_LEVEL")]
+    pub log_level: Option<String>,
+
+    #[arg(long, env = "LOG_FORMAT", value_enum)]
+    pub log_format: Option<LogFormat>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Prints the stored measurements of a device.
+    Query(QueryArgs),
+
+    /// Lists, registers and edits devices.
+    Devices {
+        #[command(subcommand)]
+        command: DevicesCommand,
+    },
+
+    /// Watches the latest measurements and notifies the channels in `--config` when they cross
+    /// the thresholds of its rules.
+    Alerts(AlertsArgs),
+
+    /// Summarizes the previous day or week per room, and writes it or sends it to the channels in
+    /// `--config`.
+    Report(ReportArgs),
+
+    /// Predicts the temperature and the CO2 concentration of every room over the next hours.
+    Forecast(ForecastArgs),
+
+    /// Dumps the devices and their measurements to a directory of compressed chunks, resuming a
+    /// backup already in it.
+    Backup(BackupArgs),
+
+    /// Loads a backup, skipping the devices and measurements that already exist.
+    Restore(RestoreArgs),
+
+    /// Copies the devices and their measurements to another database, then compares the row
+    /// counts of every device.
+    MigrateData(MigrateDataArgs),
+}
+
+#[derive(Debug, clap::Args)]
+pub struct ForecastArgs {
+    /// How many hours ahead to predict, one row per hour.
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u32).range(1..=i64::from(forecast::MAX_HOURS)))]
+    pub hours: u32,
+
+    #[arg(long, value_enum, default_value_t = Format::Table)]
+    pub format: Format,
+
+    /// Timezone the printed times are in.
+    #[arg(long, env = "TZ")]
+    pub timezone: Tz,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct BackupArgs {
+    /// Directory to write the backup to, created if it does not exist.
+    #[arg(long)]
+    pub out: PathBuf,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct MigrateDataArgs {
+    /// Database to copy to, whose schema is already migrated.
+    #[arg(long, env = "TARGET_DATABASE_URL")]
+    pub to: String,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct RestoreArgs {
+    /// Directory written by `backup`.
+    #[arg(long)]
+    pub from: PathBuf,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct ReportArgs {
+    /// Yesterday, or last week from Monday to Sunday.
+    #[arg(long, value_enum, default_value_t = Period::Day)]
+    pub period: Period,
+
+    /// Hours whose average CO2 concentration is above this are counted, in ppm.
+    #[arg(long, default_value_t = 1000)]
+    pub co2_threshold: u16,
+
+    /// Lower bound of the comfortable relative humidity, in percent.
+    #[arg(long, default_value_t = 40)]
+    pub humidity_min: u8,
+
+    /// Upper bound of the comfortable relative humidity, in percent.
+    #[arg(long, default_value_t = 60)]
+    pub humidity_max: u8,
+
+    #[arg(long, value_enum, default_value_t = ReportFormat::Markdown)]
+    pub format: ReportFormat,
+
+    /// File to write the report to, instead of stdout. Overwritten if it exists.
+    #[arg(long)]
+    pub output: Option<PathBuf>,
+
+    /// Send the report to the top-level channels of this alerts config instead of writing it.
+    #[arg(long, conflicts_with = "output")]
+    pub config: Option<PathBuf>,
+
+    /// Timezone the days are in.
+    #[arg(long, env = "TZ")]
+    pub timezone: Tz,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Period {
+    Day,
+    Week,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    Markdown,
+    Html,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct AlertsArgs {
+    /// TOML file with the rules and the channels to notify.
+    #[arg(long)]
+    pub config: PathBuf,
+
+    /// Timezone the times in the messages are in.
+    #[arg(long, env = "TZ")]
+    pub timezone: Tz,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct QueryArgs {
+    /// Name or MAC address of the device.
+    #[arg(long)]
+    pub device: String,
+
+    /// First day to print, in `--timezone`.
+    #[arg(long)]
+    pub from: NaiveDate,
+
+    /// Day to stop at, excluded, in `--timezone`. Defaults to now.
+    #[arg(long)]
+    pub to: Option<NaiveDate>,
+
+    /// Every measurement, or the min, average and max of each hour or day.
+    #[arg(long, value_enum, default_value_t = Resolution::Raw)]
+    pub resolution: Resolution,
+
+    #[arg(long, value_enum, default_value_t = Format::Table)]
+    pub format: Format,
+
+    /// Timezone the days and the printed times are in, and the buckets are aligned to.
+    #[arg(long, env = "TZ")]
+    pub timezone: Tz,
+}
+
+/// Devices are given by name or MAC address, except to `add`.
+#[derive(Debug, Subcommand)]
+pub enum DevicesCommand {
+    /// Prints the registered devices in their sort order.
+    List {
+        #[arg(long, value_enum, default_value_t = Format::Table)]
+        format: Format,
+    },
+
+    /// Registers a device, after the last one unless `--sort-order` is given.
+    Add {
+        id: MacAddr6,
+
+        /// As in switchbot_devices.type, e.g. `Meter` or `"MeterPro(CO2)"`.
+        #[arg(long = "type")]
+        r#type: DeviceType,
+
+        #[arg(long)]
+        name: String,
+
+        #[arg(long)]
+        sort_order: Option<u8>,
+    },
+
+    Rename {
+        device: String,
+        name: String,
+    },
+
+    /// Moves a device to a free position in the sort order.
+    SetOrder {
+        device: String,
+        sort_order: u8,
+    },
+
+    SetType {
+        device: String,
+        r#type: DeviceType,
+    },
+
+    /// Unregisters a device that has no measurements.
+    Remove {
+        device: String,
+
+        /// Delete the measurements, rollups, raw advertisements and locations of the device too.
+        #[arg(long)]
+        purge: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Resolution {
+    Raw,
+    #[value(name = "1h")]
+    Hour,
+    #[value(name = "1d")]
+    Day,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Aligned columns, for reading in a terminal.
+    Table,
+    Csv,
+    /// An array of objects.
+    Json,
+}
@@ -0,0 +1,567 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{BufReader, BufWriter, Write as _},
+    ops::Range,
+    path::Path,
+};
+
+use anyhow::{Context as _, Result, bail, ensure};
+use chrono::{DateTime, Datelike as _, Months, NaiveDate, NaiveTime, Utc};
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use home_environments::{
+    db::{
+        get_device_statistics, get_homes, get_rooms, get_switchbot_device_locations,
+        get_switchbot_devices, insert_switchbot_measurements, restore_home, restore_room,
+        restore_switchbot_device, restore_switchbot_device_location, stream_switchbot_measurements,
+        update_switchbot_measurements_derived_metrics,
+    },
+    home::Home,
+    room::Room,
+    switchbot::{Device, DeviceLocation, Measurement},
+};
+use macaddr::MacAddr6;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use sqlx::PgPool;
+use tokio_stream::StreamExt as _;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::args::{BackupArgs, RestoreArgs};
+
+/// Bumped when a backup can no longer be restored by an older version.
+const FORMAT: u32 = 1;
+
+const MANIFEST_FILE: &str = "manifest.json";
+
+const HOMES_FILE: &str = "homes.json";
+
+const ROOMS_FILE: &str = "rooms.json";
+
+const DEVICES_FILE: &str = "devices.json";
+
+const LOCATIONS_FILE: &str = "locations.json";
+
+const MEASUREMENTS_DIR: &str = "measurements";
+
+/// How many measurements are inserted per transaction.
+const WRITE_BATCH_SIZE: usize = 10_000;
+
+/// The chunks written so far, updated after each one so that an interrupted backup resumes.
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    format: u32,
+    /// Keyed by the path of the chunk, relative to the backup.
+    chunks: BTreeMap<String, Chunk>,
+}
+
+/// The measurements of a device in a month, in UTC.
+#[derive(Debug, Serialize, Deserialize)]
+struct Chunk {
+    device_id: String,
+    rows: u64,
+    /// Whether the month had ended when the chunk was written, so that it is not written again.
+    complete: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DeviceRecord {
+    id: String,
+    r#type: String,
+    name: String,
+    sort_order: u8,
+    enabled: bool,
+    notes: Option<String>,
+    temperature_offset_celsius: f32,
+    humidity_offset_percent: i8,
+}
+
+impl From<Device> for DeviceRecord {
+    fn from(device: Device) -> Self {
+        Self {
+            id: device.id.to_string(),
+            r#type: device.r#type.as_str().to_string(),
+            name: device.name,
+            sort_order: device.sort_order,
+            enabled: device.enabled,
+            notes: device.notes,
+            temperature_offset_celsius: device.temperature_offset_celsius,
+            humidity_offset_percent: device.humidity_offset_percent,
+        }
+    }
+}
+
+impl TryFrom<DeviceRecord> for Device {
+    type Error = anyhow::Error;
+
+    fn try_from(record: DeviceRecord) -> Result<Self> {
+        Ok(Self {
+            id: record.id.parse()?,
+            r#type: record.r#type.parse()?,
+            name: record.name,
+            sort_order: record.sort_order,
+            enabled: record.enabled,
+            // Restored with the locations.
+            room: None,
+            notes: record.notes,
+            temperature_offset_celsius: record.temperature_offset_celsius,
+            humidity_offset_percent: record.humidity_offset_percent,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct HomeRecord {
+    id: Uuid,
+    name: String,
+    sort_order: i64,
+}
+
+impl From<Home> for HomeRecord {
+    fn from(home: Home) -> Self {
+        Self {
+            id: home.id,
+            name: home.name,
+            sort_order: home.sort_order,
+        }
+    }
+}
+
+impl From<HomeRecord> for Home {
+    fn from(record: HomeRecord) -> Self {
+        Self {
+            id: record.id,
+            name: record.name,
+            sort_order: record.sort_order,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RoomRecord {
+    id: Uuid,
+    home_id: Uuid,
+    name: String,
+    sort_order: i64,
+}
+
+impl From<Room> for RoomRecord {
+    fn from(room: Room) -> Self {
+        Self {
+            id: room.id,
+            home_id: room.home_id,
+            name: room.name,
+            sort_order: room.sort_order,
+        }
+    }
+}
+
+impl From<RoomRecord> for Room {
+    fn from(record: RoomRecord) -> Self {
+        Self {
+            id: record.id,
+            home_id: record.home_id,
+            name: record.name,
+            sort_order: record.sort_order,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LocationRecord {
+    device_id: String,
+    room_id: Uuid,
+    placed_at: DateTime<Utc>,
+    removed_at: Option<DateTime<Utc>>,
+}
+
+impl From<DeviceLocation> for LocationRecord {
+    fn from(location: DeviceLocation) -> Self {
+        Self {
+            device_id: location.device_id.to_string(),
+            room_id: location.room_id,
+            placed_at: location.placed_at,
+            removed_at: location.removed_at,
+        }
+    }
+}
+
+impl TryFrom<LocationRecord> for DeviceLocation {
+    type Error = anyhow::Error;
+
+    fn try_from(record: LocationRecord) -> Result<Self> {
+        Ok(Self {
+            device_id: record.device_id.parse()?,
+            room_id: record.room_id,
+            placed_at: record.placed_at,
+            removed_at: record.removed_at,
+        })
+    }
+}
+
+/// The homes, rooms and devices, and where the devices were placed.
+#[derive(Debug)]
+pub struct Layout {
+    pub homes: Vec<Home>,
+    pub rooms: Vec<Room>,
+    pub devices: Vec<Device>,
+    pub locations: Vec<DeviceLocation>,
+}
+
+impl Layout {
+    pub async fn read(pool: &PgPool) -> Result<Self> {
+        Ok(Self {
+            homes: get_homes(pool).await?,
+            rooms: get_rooms(pool).await?,
+            devices: get_switchbot_devices(pool)
+                .await
+                .context("failed to get SwitchBot devices")?,
+            locations: get_switchbot_device_locations(pool).await?,
+        })
+    }
+
+    /// Inserts the homes, rooms, devices and locations that do not exist yet, in this order, and
+    /// returns how many devices were registered.
+    pub async fn restore(&self, pool: &PgPool) -> Result<usize> {
+        let mut homes = 0;
+        for home in &self.homes {
+            if restore_home(pool, home).await? {
+                homes += 1;
+            }
+        }
+        let mut rooms = 0;
+        for room in &self.rooms {
+            if restore_room(pool, room).await? {
+                rooms += 1;
+            }
+        }
+        let mut devices = 0;
+        for device in &self.devices {
+            if restore_switchbot_device(pool, device).await? {
+                info!(device = device.name, id = %device.id, "registered device");
+                devices += 1;
+            }
+        }
+        let mut locations = 0;
+        for location in &self.locations {
+            if restore_switchbot_device_location(pool, location).await? {
+                locations += 1;
+            }
+        }
+        info!(
+            homes,
+            rooms, locations, "restored homes, rooms and locations"
+        );
+
+        Ok(devices)
+    }
+
+    fn write(self, dir: &Path) -> Result<()> {
+        write_records::<_, HomeRecord>(&dir.join(HOMES_FILE), self.homes)?;
+        write_records::<_, RoomRecord>(&dir.join(ROOMS_FILE), self.rooms)?;
+        write_records::<_, DeviceRecord>(&dir.join(DEVICES_FILE), self.devices)?;
+        write_records::<_, LocationRecord>(&dir.join(LOCATIONS_FILE), self.locations)
+    }
+
+    fn load(dir: &Path) -> Result<Self> {
+        let homes: Vec<HomeRecord> = read_json(&dir.join(HOMES_FILE))?;
+        let rooms: Vec<RoomRecord> = read_json(&dir.join(ROOMS_FILE))?;
+        let devices: Vec<DeviceRecord> = read_json(&dir.join(DEVICES_FILE))?;
+        let locations: Vec<LocationRecord> = read_json(&dir.join(LOCATIONS_FILE))?;
+
+        Ok(Self {
+            homes: homes.into_iter().map(Home::from).collect(),
+            rooms: rooms.into_iter().map(Room::from).collect(),
+            devices: devices
+                .into_iter()
+                .map(Device::try_from)
+                .collect::<Result<_>>()
+                .context("invalid device in backup")?,
+            locations: locations
+                .into_iter()
+                .map(DeviceLocation::try_from)
+                .collect::<Result<_>>()
+                .context("invalid location in backup")?,
+        })
+    }
+}
+
+/// A row of a chunk. Derived metrics are left out and recomputed on restore.
+#[derive(Debug, Serialize, Deserialize)]
+struct MeasurementRecord {
+    measured_at: DateTime<Utc>,
+    temperature_celsius: f32,
+    humidity_percent: u8,
+    co2_ppm: Option<u16>,
+    light_level: Option<u8>,
+    rssi_dbm: Option<i16>,
+    source: Option<String>,
+}
+
+impl From<Measurement> for MeasurementRecord {
+    fn from(measurement: Measurement) -> Self {
+        Self {
+            measured_at: measurement.measured_at,
+            temperature_celsius: measurement.temperature_celsius,
+            humidity_percent: measurement.humidity_percent,
+            co2_ppm: measurement.co2_ppm,
+            light_level: measurement.light_level,
+            rssi_dbm: measurement.rssi_dbm,
+            source: measurement.source,
+        }
+    }
+}
+
+impl MeasurementRecord {
+    fn into_measurement(self, device_id: MacAddr6) -> Measurement {
+        Measurement {
+            device_id,
+            measured_at: self.measured_at,
+            temperature_celsius: self.temperature_celsius,
+            humidity_percent: self.humidity_percent,
+            co2_ppm: self.co2_ppm,
+            light_level: self.light_level,
+            rssi_dbm: self.rssi_dbm,
+            source: self.source,
+        }
+    }
+}
+
+/// Writes the homes, rooms, devices and locations, then the measurements of every device as a gzipped CSV file per month.
+/// Chunks of months that had ended are kept from a previous run, the others are written again.
+pub async fn backup(pool: &PgPool, args: BackupArgs) -> Result<()> {
+    let started_at = Utc::now();
+    fs::create_dir_all(&args.out)
+        .with_context(|| format!("failed to create {}", args.out.display()))?;
+    let mut manifest = match read_manifest(&args.out)? {
+        Some(manifest) => manifest,
+        None => Manifest {
+            format: FORMAT,
+            chunks: BTreeMap::new(),
+        },
+    };
+
+    let layout = Layout::read(pool).await?;
+    let device_count = layout.devices.len();
+    layout.write(&args.out)?;
+
+    let (mut written, mut kept, mut rows) = (0, 0, 0);
+    for statistics in get_device_statistics(pool, chrono_tz::UTC).await? {
+        let (Some(first), Some(last)) = (statistics.first_measured_at, statistics.last_measured_at)
+        else {
+            continue;
+        };
+
+        let mut month = first_of_month(first.date_naive());
+        while month <= last.date_naive() {
+            let next = month + Months::new(1);
+            let path = format!(
+                "{MEASUREMENTS_DIR}/{}/{}.csv.gz",
+                statistics.device_id.to_string().replace(':', ""),
+                month.format("%Y-%m")
+            );
+            if manifest
+                .chunks
+                .get(&path)
+                .is_some_and(|chunk| chunk.complete)
+            {
+                kept += 1;
+                month = next;
+                continue;
+            }
+
+            let range = start_of_utc_day(month)..start_of_utc_day(next);
+            let chunk_rows =
+                write_chunk(pool, &args.out.join(&path), statistics.device_id, range).await?;
+            manifest.chunks.insert(
+                path.clone(),
+                Chunk {
+                    device_id: statistics.device_id.to_string(),
+                    rows: chunk_rows,
+                    complete: start_of_utc_day(next) <= started_at,
+                },
+            );
+            write_json(&args.out.join(MANIFEST_FILE), &manifest)?;
+            info!(chunk = path, rows = chunk_rows, "wrote chunk");
+
+            written += 1;
+            rows += chunk_rows;
+            month = next;
+        }
+    }
+    write_json(&args.out.join(MANIFEST_FILE), &manifest)?;
+
+    info!(
+        devices = device_count,
+        chunks = written,
+        kept,
+        rows,
+        "backed up to {}",
+        args.out.display()
+    );
+
+    Ok(())
+}
+
+/// Registers the homes, rooms, devices and locations that do not exist yet, then inserts the
+/// measurements of every chunk.
+/// Existing measurements are kept unless the backup has a stronger signal, so an interrupted restore
+/// can simply be run again.
+pub async fn restore(pool: &PgPool, args: RestoreArgs) -> Result<()> {
+    let Some(manifest) = read_manifest(&args.from)? else {
+        bail!("no {MANIFEST_FILE} in {}", args.from.display());
+    };
+    let layout = Layout::load(&args.from)?;
+    let device_count = layout.devices.len();
+    let registered = layout.restore(pool).await?;
+
+    let (mut rows, mut written) = (0, 0);
+    let chunk_count = manifest.chunks.len();
+    for (i, (path, chunk)) in manifest.chunks.iter().enumerate() {
+        let device_id = chunk
+            .device_id
+            .parse()
+            .with_context(|| format!("invalid device ID of {path}"))?;
+        let measurements = read_chunk(&args.from.join(path), device_id)?;
+        ensure!(
+            measurements.len() as u64 == chunk.rows,
+            "{path} has {} rows, expected {}",
+            measurements.len(),
+            chunk.rows
+        );
+
+        let chunk_written = write_measurements(pool, &measurements).await?;
+        info!(
+            chunk = path,
+            rows = chunk.rows,
+            written = chunk_written,
+            "restored chunk {}/{chunk_count}",
+            i + 1
+        );
+
+        rows += chunk.rows;
+        written += chunk_written;
+    }
+
+    info!(
+        devices = device_count,
+        registered,
+        rows,
+        written,
+        "restored from {}",
+        args.from.display()
+    );
+
+    Ok(())
+}
+
+/// Inserts `measurements` with their derived metrics and returns how many were written. Existing
+/// rows are only replaced by ones with a stronger signal.
+pub async fn write_measurements(pool: &PgPool, measurements: &[Measurement]) -> Result<u64> {
+    let mut written = 0;
+    for batch in measurements.chunks(WRITE_BATCH_SIZE) {
+        let mut tx = pool.begin().await.context("failed to begin transaction")?;
+        written += insert_switchbot_measurements(&mut tx, batch).await?;
+        update_switchbot_measurements_derived_metrics(&mut tx, batch).await?;
+        tx.commit().await.context("failed to commit transaction")?;
+    }
+
+    Ok(written)
+}
+
+/// Streams the measurements of `device_id` in `range` to `path`, through a temporary file so that
+/// an interrupted chunk is never mistaken for a complete one.
+async fn write_chunk(
+    pool: &PgPool,
+    path: &Path,
+    device_id: MacAddr6,
+    range: Range<DateTime<Utc>>,
+) -> Result<u64> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    }
+    let partial = path.with_extension("gz.partial");
+    let file = File::create(&partial)
+        .with_context(|| format!("failed to create {}", partial.display()))?;
+    let mut writer =
+        csv::Writer::from_writer(GzEncoder::new(BufWriter::new(file), Compression::default()));
+
+    let mut rows = 0;
+    let range =
+        range.start.with_timezone(&chrono_tz::UTC)..range.end.with_timezone(&chrono_tz::UTC);
+    let mut measurements = stream_switchbot_measurements(pool, device_id, range);
+    while let Some(measurement) = measurements.next().await {
+        writer.serialize(MeasurementRecord::from(measurement?))?;
+        rows += 1;
+    }
+
+    writer
+        .into_inner()
+        .context("failed to write chunk")?
+        .finish()
+        .and_then(|mut writer| writer.flush())
+        .with_context(|| format!("failed to write {}", partial.display()))?;
+    fs::rename(&partial, path)
+        .with_context(|| format!("failed to rename {}", partial.display()))?;
+
+    Ok(rows)
+}
+
+fn read_chunk(path: &Path, device_id: MacAddr6) -> Result<Vec<Measurement>> {
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    csv::Reader::from_reader(GzDecoder::new(BufReader::new(file)))
+        .deserialize()
+        .map(|record| {
+            record
+                .map(|record: MeasurementRecord| record.into_measurement(device_id))
+                .with_context(|| format!("failed to read {}", path.display()))
+        })
+        .collect()
+}
+
+fn read_manifest(dir: &Path) -> Result<Option<Manifest>> {
+    let path = dir.join(MANIFEST_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let manifest: Manifest = read_json(&path)?;
+    ensure!(
+        manifest.format == FORMAT,
+        "{} is of format {}, expected {FORMAT}",
+        path.display(),
+        manifest.format
+    );
+
+    Ok(Some(manifest))
+}
+
+fn read_json<T: DeserializeOwned>(path: &Path) -> Result<T> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+
+    serde_json::from_str(&content).with_context(|| format!("failed to parse {}", path.display()))
+}
+
+fn write_records<T, R: From<T> + Serialize>(path: &Path, values: Vec<T>) -> Result<()> {
+    write_json(path, &values.into_iter().map(R::from).collect::<Vec<_>>())
+}
+
+/// Writes through a temporary file, so that `path` is never left half-written.
+fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    let partial = path.with_extension("json.partial");
+    let mut content = serde_json::to_string_pretty(value)?;
+    content.push('\n');
+    fs::write(&partial, content)
+        .with_context(|| format!("failed to write {}", partial.display()))?;
+
+    fs::rename(&partial, path).with_context(|| format!("failed to rename {}", partial.display()))
+}
+
+fn first_of_month(date: NaiveDate) -> NaiveDate {
+    date.with_day(1).unwrap_or(date)
+}
+
+fn start_of_utc_day(date: NaiveDate) -> DateTime<Utc> {
+    date.and_time(NaiveTime::MIN).and_utc()
+}
@@ -0,0 +1,155 @@
+use std::io::{self, BufWriter};
+
+use anyhow::{Result, bail};
+use home_environments::{
+    db::{
+        delete_switchbot_device, get_device_statistics, get_switchbot_devices,
+        insert_switchbot_device, update_switchbot_device_name, update_switchbot_device_sort_order,
+        update_switchbot_device_type,
+    },
+    switchbot::Device,
+};
+use serde::Serialize;
+use sqlx::PgPool;
+use tracing::info;
+
+use crate::{
+    args::DevicesCommand,
+    find_device,
+    output::{self, Record},
+};
+
+#[derive(Debug, Serialize)]
+struct DeviceRecord {
+    id: String,
+    r#type: &'static str,
+    name: String,
+    sort_order: u8,
+    enabled: bool,
+    room: Option<String>,
+    notes: Option<String>,
+}
+
+impl From<Device> for DeviceRecord {
+    fn from(device: Device) -> Self {
+        Self {
+            id: device.id.to_string(),
+            r#type: device.r#type.as_str(),
+            name: device.name,
+            sort_order: device.sort_order,
+            enabled: device.enabled,
+            room: device.room.map(|room| room.name),
+            notes: device.notes,
+        }
+    }
+}
+
+impl Record for DeviceRecord {
+    const HEADER: &'static [&'static str] = &[
+        "id",
+        "type",
+        "name",
+        "sort_order",
+        "enabled",
+        "room",
+        "notes",
+    ];
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.id.clone(),
+            self.r#type.to_string(),
+            self.name.clone(),
+            self.sort_order.to_string(),
+            self.enabled.to_string(),
+            self.room.clone().unwrap_or_default(),
+            self.notes.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+pub async fn run(pool: &PgPool, command: DevicesCommand) -> Result<()> {
+    match command {
+        DevicesCommand::List { format } => {
+            let records = get_switchbot_devices(pool)
+                .await?
+                .into_iter()
+                .map(DeviceRecord::from)
+                .collect::<Vec<_>>();
+            output::write(&records, format, BufWriter::new(io::stdout().lock()))?;
+        }
+        DevicesCommand::Add {
+            id,
+            r#type,
+            name,
+            sort_order,
+        } => {
+            let devices = get_switchbot_devices(pool).await?;
+            if let Some(device) = devices.iter().find(|device| device.id == id) {
+                bail!("{id} is already registered as {:?}", device.name);
+            }
+            if let Some(sort_order) = sort_order {
+                check_sort_order_free(&devices, sort_order)?;
+            }
+            insert_switchbot_device(pool, id, r#type, &name, sort_order).await?;
+            info!(device_id = %id, name, r#type = r#type.as_str(), "added device");
+        }
+        DevicesCommand::Rename { device, name } => {
+            let device = find_device(pool, &device).await?;
+            update_switchbot_device_name(pool, device.id, &name).await?;
+            info!(device_id = %device.id, from = device.name, to = name, "renamed device");
+        }
+        DevicesCommand::SetOrder { device, sort_order } => {
+            let device = find_device(pool, &device).await?;
+            let others = get_switchbot_devices(pool)
+                .await?
+                .into_iter()
+                .filter(|other| other.id != device.id)
+                .collect::<Vec<_>>();
+            check_sort_order_free(&others, sort_order)?;
+            update_switchbot_device_sort_order(pool, device.id, sort_order).await?;
+            info!(device_id = %device.id, name = device.name, sort_order, "moved device");
+        }
+        DevicesCommand::SetType { device, r#type } => {
+            let device = find_device(pool, &device).await?;
+            update_switchbot_device_type(pool, device.id, r#type).await?;
+            info!(
+                device_id = %device.id,
+                name = device.name,
+                from = device.r#type.as_str(),
+                to = r#type.as_str(),
+                "changed device type"
+            );
+        }
+        DevicesCommand::Remove { device, purge } => {
+            let device = find_device(pool, &device).await?;
+            let record_count = get_device_statistics(pool, chrono_tz::UTC)
+                .await?
+                .into_iter()
+                .find(|statistics| statistics.device_id == device.id)
+                .map_or(0, |statistics| statistics.record_count);
+            if record_count > 0 && !purge {
+                bail!(
+                    "{:?} has {record_count} measurements, pass --purge to delete them too",
+                    device.name
+                );
+            }
+            delete_switchbot_device(pool, device.id, purge).await?;
+            info!(device_id = %device.id, name = device.name, record_count, "removed device");
+        }
+    }
+
+    Ok(())
+}
+
+/// Fails if one of `devices` is at `sort_order`, which is unique.
+fn check_sort_order_free(devices: &[Device], sort_order: u8) -> Result<()> {
+    if let Some(device) = devices
+        .iter()
+        .find(|device| device.sort_order == sort_order)
+    {
+        bail!("sort order {sort_order} is taken by {:?}", device.name);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,53 @@
+use std::io::{self, BufWriter};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use home_environments::forecast::forecast_rooms;
+use serde::Serialize;
+use sqlx::PgPool;
+
+use crate::{
+    args::ForecastArgs,
+    output::{self, Record},
+    query::{TIME_FORMAT, optional},
+};
+
+#[derive(Debug, Serialize)]
+struct ForecastRecord {
+    room: String,
+    at: DateTime<Tz>,
+    temperature_celsius: Option<f64>,
+    co2_ppm: Option<f64>,
+}
+
+impl Record for ForecastRecord {
+    const HEADER: &'static [&'static str] = &["room", "at", "temperature_celsius", "co2_ppm"];
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.room.clone(),
+            self.at.format(TIME_FORMAT).to_string(),
+            optional(self.temperature_celsius),
+            optional(self.co2_ppm),
+        ]
+    }
+}
+
+/// Prints a row per room and hour ahead.
+pub async fn run(pool: &PgPool, args: ForecastArgs) -> Result<()> {
+    let records = forecast_rooms(pool, Utc::now(), args.hours)
+        .await?
+        .into_iter()
+        .flat_map(|forecast| {
+            forecast.hours.into_iter().map(move |hour| ForecastRecord {
+                room: forecast.room.clone(),
+                at: hour.at.with_timezone(&args.timezone),
+                temperature_celsius: hour.temperature_celsius,
+                co2_ppm: hour.co2_ppm,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    output::write(&records, args.format, BufWriter::new(io::stdout().lock()))
+}
@@ -0,0 +1,102 @@
+mod alerts;
+mod args;
+mod backup;
+mod devices;
+mod forecast;
+mod migrate;
+mod notify;
+mod output;
+mod query;
+mod report;
+
+use std::process::ExitCode;
+
+use anyhow::{Context as _, Result, bail};
+use args::{Args, Command};
+use chrono::{DateTime, NaiveDate};
+use chrono_tz::Tz;
+use clap::Parser as _;
+use home_environments::{
+    db::{get_switchbot_devices, new_pool},
+    logging,
+    switchbot::Device,
+};
+use macaddr::MacAddr6;
+use sqlx::PgPool;
+use tracing::error;
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = Args::parse();
+
+    if let Err(e) = logging::init(
+        args.log_level.as_deref(),
+        args.log_format.unwrap_or_default(),
+    ) {
+        eprintln!("failed to initialize logging: {e:#}");
+        return ExitCode::from(1);
+    }
+
+    if let Err(e) = run(args).await {
+        error!("{e:#}");
+        return ExitCode::from(1);
+    }
+
+    ExitCode::from(0)
+}
+
+async fn run(args: Args) -> Result<()> {
+    let pool = new_pool(&args.database_url)
+        .await
+        .context("failed to connect to database")?;
+
+    match args.command {
+        Command::Query(args) => query::run(&pool, args).await,
+        Command::Devices { command } => devices::run(&pool, command).await,
+        Command::Alerts(args) => alerts::run(&pool, args).await,
+        Command::Report(args) => report::run(&pool, args).await,
+        Command::Forecast(args) => forecast::run(&pool, args).await,
+        Command::Backup(args) => backup::backup(&pool, args).await,
+        Command::Restore(args) => backup::restore(&pool, args).await,
+        Command::MigrateData(args) => migrate::run(&pool, args).await,
+    }
+}
+
+/// The device whose MAC address or name, ignoring case, is `device`.
+async fn find_device(pool: &PgPool, device: &str) -> Result<Device> {
+    let devices = get_switchbot_devices(pool)
+        .await
+        .context("failed to get SwitchBot devices")?;
+
+    select_device(&devices, device).cloned()
+}
+
+/// Like [`find_device`], among `devices`.
+fn select_device<'a>(devices: &'a [Device], device: &str) -> Result<&'a Device> {
+    if let Ok(id) = device.parse::<MacAddr6>() {
+        return devices
+            .iter()
+            .find(|device| device.id == id)
+            .with_context(|| format!("unknown device: {id}"));
+    }
+
+    let mut matches = devices
+        .iter()
+        .filter(|d| d.name.trim().eq_ignore_ascii_case(device.trim()));
+    match (matches.next(), matches.next()) {
+        (Some(device), None) => Ok(device),
+        (Some(_), Some(_)) => bail!("several devices are named {device:?}, use the MAC address"),
+        (None, _) => bail!("no device is named {device:?}"),
+    }
+}
+
+/// The first instant of `date`, which is not midnight if the clocks are turned forward at midnight.
+fn start_of_day(date: NaiveDate, timezone: Tz) -> Result<DateTime<Tz>> {
+    (0..24)
+        .find_map(|hour| {
+            date.and_hms_opt(hour, 0, 0)?
+                .and_local_timezone(timezone)
+                .earliest()
+        })
+        .with_context(|| format!("{date} has no start in {timezone}"))
+}
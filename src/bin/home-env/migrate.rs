@@ -0,0 +1,97 @@
+use anyhow::{Context as _, Result, ensure};
+use chrono::TimeDelta;
+use home_environments::db::{get_device_statistics, new_pool, stream_switchbot_measurements};
+use indicatif::{ProgressBar, ProgressStyle};
+use sqlx::PgPool;
+use tokio_stream::StreamExt as _;
+use tracing::{info, warn};
+
+use crate::{
+    args::MigrateDataArgs,
+    backup::{Layout, write_measurements},
+};
+
+/// How many measurements are read from the source before they are written to the target.
+const PAGE_SIZE: usize = 10_000;
+
+const TEMPLATE: &str = "[{bar:30}] {percent:>3}% {pos}/{len} rows, {msg}, ETA {eta}";
+
+/// Copies the homes, rooms, devices and locations like `restore`, then streams the measurements of every device to the target
+/// and checks that it has at least as many rows of each device as the source.
+pub async fn run(pool: &PgPool, args: MigrateDataArgs) -> Result<()> {
+    let target = new_pool(&args.to)
+        .await
+        .context("failed to connect to target database")?;
+
+    let layout = Layout::read(pool).await?;
+    let registered = layout.restore(&target).await?;
+    info!(devices = layout.devices.len(), registered, "copied devices");
+
+    let statistics = get_device_statistics(pool, chrono_tz::UTC).await?;
+    let rows = statistics.iter().map(|s| s.record_count).sum();
+    let bar = ProgressBar::new(rows);
+    bar.set_style(
+        ProgressStyle::with_template(TEMPLATE)
+            .context("invalid progress bar template")?
+            .progress_chars("=> "),
+    );
+
+    let mut written = 0;
+    for statistics in &statistics {
+        let (Some(first), Some(last)) = (statistics.first_measured_at, statistics.last_measured_at)
+        else {
+            continue;
+        };
+        bar.set_message(statistics.device_name.clone());
+
+        let range = first..last + TimeDelta::microseconds(1);
+        let mut measurements = stream_switchbot_measurements(pool, statistics.device_id, range);
+        let mut page = Vec::with_capacity(PAGE_SIZE);
+        loop {
+            let measurement = measurements.next().await.transpose()?;
+            let done = measurement.is_none();
+            page.extend(measurement);
+            if page.len() == PAGE_SIZE || done {
+                written += write_measurements(&target, &page).await?;
+                bar.inc(page.len() as u64);
+                page.clear();
+            }
+            if done {
+                break;
+            }
+        }
+    }
+    bar.finish_and_clear();
+
+    let copied = get_device_statistics(&target, chrono_tz::UTC).await?;
+    let mut incomplete = 0;
+    for source in &statistics {
+        let target_rows = copied
+            .iter()
+            .find(|copied| copied.device_id == source.device_id)
+            .map_or(0, |copied| copied.record_count);
+        if target_rows < source.record_count {
+            warn!(
+                device = source.device_name,
+                source_rows = source.record_count,
+                target_rows,
+                "rows are missing in the target"
+            );
+            incomplete += 1;
+        } else {
+            info!(
+                device = source.device_name,
+                source_rows = source.record_count,
+                target_rows,
+                "verified"
+            );
+        }
+    }
+    ensure!(
+        incomplete == 0,
+        "{incomplete} devices have fewer rows in the target than in the source"
+    );
+    info!(devices = layout.devices.len(), rows, written, "migrated");
+
+    Ok(())
+}
@@ -0,0 +1,74 @@
+use std::io::Write;
+
+use anyhow::{Context as _, Result};
+use serde::Serialize;
+
+use crate::args::Format;
+
+/// A row of the output, with a cell per column of `HEADER`.
+pub trait Record: Serialize {
+    const HEADER: &'static [&'static str];
+
+    /// The values of the row, empty where there is none.
+    fn cells(&self) -> Vec<String>;
+}
+
+/// Writes `records` to `writer` in `format`.
+pub fn write<R: Record, W: Write>(records: &[R], format: Format, mut writer: W) -> Result<()> {
+    match format {
+        Format::Table => write_table(records, &mut writer)?,
+        Format::Csv => {
+            let mut csv_writer = csv::Writer::from_writer(&mut writer);
+            csv_writer.write_record(R::HEADER)?;
+            for record in records {
+                csv_writer.write_record(record.cells())?;
+            }
+            csv_writer.flush()?;
+        }
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut writer, records)?;
+            writeln!(writer)?;
+        }
+    }
+
+    writer.flush().context("failed to write output")
+}
+
+/// Writes the header and the rows in columns as wide as their widest cell, columns of numbers
+/// aligned to the right.
+fn write_table<R: Record, W: Write>(records: &[R], writer: &mut W) -> Result<()> {
+    let rows = records.iter().map(Record::cells).collect::<Vec<_>>();
+    let mut widths = R::HEADER
+        .iter()
+        .map(|name| name.chars().count())
+        .collect::<Vec<_>>();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let numeric = (0..widths.len())
+        .map(|i| {
+            rows.iter()
+                .all(|row| row[i].is_empty() || row[i].parse::<f64>().is_ok())
+        })
+        .collect::<Vec<_>>();
+
+    let header = R::HEADER.iter().map(|name| name.to_string()).collect();
+    let separator = widths.iter().map(|width| "-".repeat(*width)).collect();
+    for row in [header, separator].iter().chain(&rows) {
+        let line = row
+            .iter()
+            .zip(widths.iter().zip(&numeric))
+            .map(|(cell, (width, numeric))| match numeric {
+                true => format!("{cell:>width$}"),
+                false => format!("{cell:<width$}"),
+            })
+            .collect::<Vec<_>>()
+            .join("  ");
+        writeln!(writer, "{}", line.trim_end())?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,184 @@
+use std::io::{self, BufWriter};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use home_environments::{
+    db::{get_switchbot_measurement_buckets, stream_switchbot_measurements},
+    switchbot::{BucketSize, Measurement, MeasurementBucket},
+};
+use serde::Serialize;
+use sqlx::PgPool;
+use tokio_stream::StreamExt as _;
+use tracing::info;
+
+use crate::{
+    args::{QueryArgs, Resolution},
+    find_device,
+    output::{self, Record},
+    start_of_day,
+};
+
+pub const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S%:z";
+
+#[derive(Debug, Serialize)]
+struct MeasurementRecord {
+    measured_at: DateTime<Tz>,
+    temperature_celsius: f32,
+    humidity_percent: u8,
+    co2_ppm: Option<u16>,
+    light_level: Option<u8>,
+    rssi_dbm: Option<i16>,
+}
+
+impl MeasurementRecord {
+    fn new(measurement: Measurement, timezone: Tz) -> Self {
+        Self {
+            measured_at: measurement.measured_at.with_timezone(&timezone),
+            temperature_celsius: measurement.temperature_celsius,
+            humidity_percent: measurement.humidity_percent,
+            co2_ppm: measurement.co2_ppm,
+            light_level: measurement.light_level,
+            rssi_dbm: measurement.rssi_dbm,
+        }
+    }
+}
+
+impl Record for MeasurementRecord {
+    const HEADER: &'static [&'static str] = &[
+        "measured_at",
+        "temperature_celsius",
+        "humidity_percent",
+        "co2_ppm",
+        "light_level",
+        "rssi_dbm",
+    ];
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.measured_at.format(TIME_FORMAT).to_string(),
+            self.temperature_celsius.to_string(),
+            self.humidity_percent.to_string(),
+            optional(self.co2_ppm),
+            optional(self.light_level),
+            optional(self.rssi_dbm),
+        ]
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct BucketRecord {
+    bucket_start: DateTime<Tz>,
+    sample_count: u32,
+    temperature_celsius_min: f32,
+    temperature_celsius_avg: f64,
+    temperature_celsius_max: f32,
+    humidity_percent_min: u8,
+    humidity_percent_avg: f64,
+    humidity_percent_max: u8,
+    co2_ppm_min: Option<u16>,
+    co2_ppm_avg: Option<f64>,
+    co2_ppm_max: Option<u16>,
+    light_level_min: Option<u8>,
+    light_level_avg: Option<f64>,
+    light_level_max: Option<u8>,
+}
+
+impl From<MeasurementBucket> for BucketRecord {
+    fn from(bucket: MeasurementBucket) -> Self {
+        Self {
+            bucket_start: bucket.bucket_start,
+            sample_count: bucket.sample_count,
+            temperature_celsius_min: bucket.temperature_celsius_min,
+            temperature_celsius_avg: bucket.temperature_celsius_avg,
+            temperature_celsius_max: bucket.temperature_celsius_max,
+            humidity_percent_min: bucket.humidity_percent_min,
+            humidity_percent_avg: bucket.humidity_percent_avg,
+            humidity_percent_max: bucket.humidity_percent_max,
+            co2_ppm_min: bucket.co2_ppm_min,
+            co2_ppm_avg: bucket.co2_ppm_avg,
+            co2_ppm_max: bucket.co2_ppm_max,
+            light_level_min: bucket.light_level_min,
+            light_level_avg: bucket.light_level_avg,
+            light_level_max: bucket.light_level_max,
+        }
+    }
+}
+
+impl Record for BucketRecord {
+    const HEADER: &'static [&'static str] = &[
+        "bucket_start",
+        "samples",
+        "temperature_min",
+        "temperature_avg",
+        "temperature_max",
+        "humidity_min",
+        "humidity_avg",
+        "humidity_max",
+        "co2_min",
+        "co2_avg",
+        "co2_max",
+        "light_min",
+        "light_avg",
+        "light_max",
+    ];
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.bucket_start.format(TIME_FORMAT).to_string(),
+            self.sample_count.to_string(),
+            self.temperature_celsius_min.to_string(),
+            format!("{:.2}", self.temperature_celsius_avg),
+            self.temperature_celsius_max.to_string(),
+            self.humidity_percent_min.to_string(),
+            format!("{:.2}", self.humidity_percent_avg),
+            self.humidity_percent_max.to_string(),
+            optional(self.co2_ppm_min),
+            optional(self.co2_ppm_avg.map(|avg| format!("{avg:.2}"))),
+            optional(self.co2_ppm_max),
+            optional(self.light_level_min),
+            optional(self.light_level_avg.map(|avg| format!("{avg:.2}"))),
+            optional(self.light_level_max),
+        ]
+    }
+}
+
+pub fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+/// Prints the measurements of a device from the start of `--from` to that of `--to`, or now.
+pub async fn run(pool: &PgPool, args: QueryArgs) -> Result<()> {
+    let device = find_device(pool, &args.device).await?;
+
+    let from = start_of_day(args.from, args.timezone)?;
+    let to = match args.to {
+        Some(to) => start_of_day(to, args.timezone)?,
+        None => Utc::now().with_timezone(&args.timezone),
+    };
+    let range = from..to;
+
+    let stdout = BufWriter::new(io::stdout().lock());
+    let size = match args.resolution {
+        Resolution::Raw => {
+            let mut records = Vec::new();
+            let mut measurements = stream_switchbot_measurements(pool, device.id, range);
+            while let Some(measurement) = measurements.next().await {
+                records.push(MeasurementRecord::new(measurement?, args.timezone));
+            }
+            info!(rows = records.len(), device_id = %device.id, name = device.name, "queried measurements");
+
+            return output::write(&records, args.format, stdout);
+        }
+        Resolution::Hour => BucketSize::Hour,
+        Resolution::Day => BucketSize::Day,
+    };
+    let records = get_switchbot_measurement_buckets(pool, device.id, range, size)
+        .await?
+        .into_iter()
+        .map(BucketRecord::from)
+        .collect::<Vec<_>>();
+    info!(rows = records.len(), device_id = %device.id, name = device.name, "queried buckets");
+
+    output::write(&records, args.format, stdout)
+}
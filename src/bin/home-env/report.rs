@@ -0,0 +1,284 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs,
+    io::{self, Write as _},
+};
+
+use anyhow::{Context as _, Result, ensure};
+use chrono::{Datelike as _, Days, NaiveDateTime, Timelike as _, Utc};
+use home_environments::{
+    db::{get_switchbot_devices, stream_switchbot_measurements},
+    switchbot::Measurement,
+};
+use sqlx::PgPool;
+use tokio_stream::StreamExt as _;
+use tracing::{info, warn};
+
+use crate::{
+    alerts::read_channels,
+    args::{Period, ReportArgs, ReportFormat},
+    notify::{Message, Notifier},
+    start_of_day,
+};
+
+/// The measurements of the devices in a room over the period of a report.
+#[derive(Debug, Default)]
+struct RoomSummary {
+    name: String,
+    temperature_min: Option<f32>,
+    temperature_max: Option<f32>,
+    temperature_sum: f64,
+    humidity_in_band: u64,
+    count: u64,
+    /// The sum and the number of the CO2 concentrations of each hour, in local time.
+    co2_hours: BTreeMap<NaiveDateTime, (f64, u64)>,
+}
+
+impl RoomSummary {
+    fn add(&mut self, measurement: &Measurement, args: &ReportArgs) {
+        let temperature = measurement.temperature_celsius;
+        self.temperature_min = Some(
+            self.temperature_min
+                .map_or(temperature, |t| t.min(temperature)),
+        );
+        self.temperature_max = Some(
+            self.temperature_max
+                .map_or(temperature, |t| t.max(temperature)),
+        );
+        self.temperature_sum += f64::from(temperature);
+        if (args.humidity_min..=args.humidity_max).contains(&measurement.humidity_percent) {
+            self.humidity_in_band += 1;
+        }
+        self.count += 1;
+
+        if let Some(co2_ppm) = measurement.co2_ppm {
+            let local = measurement
+                .measured_at
+                .with_timezone(&args.timezone)
+                .naive_local();
+            let hour = local
+                .date()
+                .and_hms_opt(local.hour(), 0, 0)
+                .unwrap_or(local);
+            let (sum, count) = self.co2_hours.entry(hour).or_default();
+            *sum += f64::from(co2_ppm);
+            *count += 1;
+        }
+    }
+
+    fn temperature_avg(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.temperature_sum / self.count as f64)
+    }
+
+    /// The share of the measurements within the comfortable humidity, in percent.
+    fn humidity_coverage(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.humidity_in_band as f64 / self.count as f64 * 100.0)
+    }
+
+    /// The hours whose average CO2 concentration is above `threshold`, for rooms with a CO2 meter.
+    fn co2_hours_above(&self, threshold: u16) -> Option<usize> {
+        (!self.co2_hours.is_empty()).then(|| {
+            self.co2_hours
+                .values()
+                .filter(|(sum, count)| sum / *count as f64 > f64::from(threshold))
+                .count()
+        })
+    }
+}
+
+/// Summarizes the previous day or week per room, devices without a room standing for their own.
+pub async fn run(pool: &PgPool, args: ReportArgs) -> Result<()> {
+    let today = Utc::now().with_timezone(&args.timezone).date_naive();
+    let (from, to) = match args.period {
+        Period::Day => (today - Days::new(1), today),
+        Period::Week => {
+            let monday = today - Days::new(today.weekday().num_days_from_monday().into());
+            (monday - Days::new(7), monday)
+        }
+    };
+    let range = start_of_day(from, args.timezone)?..start_of_day(to, args.timezone)?;
+
+    let mut rooms: Vec<RoomSummary> = Vec::new();
+    for device in get_switchbot_devices(pool)
+        .await
+        .context("failed to get SwitchBot devices")?
+    {
+        let name = device.room.map_or(device.name, |room| room.name);
+        let index = match rooms.iter().position(|room| room.name == name) {
+            Some(index) => index,
+            None => {
+                rooms.push(RoomSummary {
+                    name,
+                    ..Default::default()
+                });
+                rooms.len() - 1
+            }
+        };
+
+        let mut measurements = stream_switchbot_measurements(pool, device.id, range.clone());
+        while let Some(measurement) = measurements.next().await {
+            rooms[index].add(&measurement?, &args);
+        }
+    }
+
+    let period = match args.period {
+        Period::Day => from.to_string(),
+        Period::Week => format!("{from} – {}", to - Days::new(1)),
+    };
+
+    if let Some(config) = &args.config {
+        let message = message(&rooms, &period, &args);
+        let notifier = Notifier::new()?;
+        let mut failed = 0;
+        for channel in read_channels(config)? {
+            if let Err(err) = notifier.send(&channel, &message).await {
+                warn!(error = format!("{err:#}"), "failed to send report");
+                failed += 1;
+            }
+        }
+        ensure!(
+            failed == 0,
+            "failed to send the report to {failed} channels"
+        );
+        info!(period, rooms = rooms.len(), "sent report");
+
+        return Ok(());
+    }
+
+    let report = match args.format {
+        ReportFormat::Markdown => markdown(&rooms, &period, &args),
+        ReportFormat::Html => html(&rooms, &period, &args),
+    };
+    match &args.output {
+        Some(path) => fs::write(path, report)
+            .with_context(|| format!("failed to write {}", path.display()))?,
+        None => io::stdout()
+            .write_all(report.as_bytes())
+            .context("failed to write report")?,
+    }
+
+    Ok(())
+}
+
+fn title(period: &str) -> String {
+    format!("Home report for {period}")
+}
+
+/// The header and the cells of the row of each room.
+fn table(rooms: &[RoomSummary], args: &ReportArgs) -> (Vec<String>, Vec<Vec<String>>) {
+    let header = vec![
+        "Room".to_string(),
+        "Min temperature".to_string(),
+        "Avg temperature".to_string(),
+        "Max temperature".to_string(),
+        format!("Humidity {}–{} %", args.humidity_min, args.humidity_max),
+        format!("CO2 above {} ppm", args.co2_threshold),
+    ];
+    let rows = rooms
+        .iter()
+        .map(|room| {
+            vec![
+                room.name.clone(),
+                optional(room.temperature_min.map(|t| format!("{t:.1} °C"))),
+                optional(room.temperature_avg().map(|t| format!("{t:.1} °C"))),
+                optional(room.temperature_max.map(|t| format!("{t:.1} °C"))),
+                optional(room.humidity_coverage().map(|p| format!("{p:.0} %"))),
+                optional(
+                    room.co2_hours_above(args.co2_threshold)
+                        .map(|h| format!("{h} h")),
+                ),
+            ]
+        })
+        .collect();
+
+    (header, rows)
+}
+
+fn optional(cell: Option<String>) -> String {
+    cell.unwrap_or_else(|| "-".to_string())
+}
+
+fn markdown(rooms: &[RoomSummary], period: &str, args: &ReportArgs) -> String {
+    let (header, rows) = table(rooms, args);
+    let mut markdown = format!("# {}\n\n", title(period));
+    let _ = writeln!(markdown, "| {} |", header.join(" | "));
+    let _ = writeln!(markdown, "| --- |{}", " ---: |".repeat(header.len() - 1));
+    for row in rows {
+        let cells = row
+            .iter()
+            .map(|cell| cell.replace('|', "\\|"))
+            .collect::<Vec<_>>();
+        let _ = writeln!(markdown, "| {} |", cells.join(" | "));
+    }
+
+    markdown
+}
+
+fn html(rooms: &[RoomSummary], period: &str, args: &ReportArgs) -> String {
+    let (header, rows) = table(rooms, args);
+    let title = escape_html(&title(period));
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n</head>\n<body>\n<h1>{title}</h1>\n<table>\n"
+    );
+    let _ = writeln!(
+        html,
+        "<tr>{}</tr>",
+        header
+            .iter()
+            .map(|cell| format!("<th>{}</th>", escape_html(cell)))
+            .collect::<String>()
+    );
+    for row in rows {
+        let _ = writeln!(
+            html,
+            "<tr>{}</tr>",
+            row.iter()
+                .map(|cell| format!("<td>{}</td>", escape_html(cell)))
+                .collect::<String>()
+        );
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+
+    html
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The report as a line per room, which chats render better than a table.
+fn message(rooms: &[RoomSummary], period: &str, args: &ReportArgs) -> Message {
+    let body = rooms
+        .iter()
+        .map(|room| {
+            let (Some(min), Some(max), Some(avg), Some(coverage)) = (
+                room.temperature_min,
+                room.temperature_max,
+                room.temperature_avg(),
+                room.humidity_coverage(),
+            ) else {
+                return format!("{}: no measurements", room.name);
+            };
+
+            let mut line = format!(
+                "{}: {min:.1}–{max:.1} °C (avg {avg:.1} °C), humidity {}–{} % for {coverage:.0} % of the time",
+                room.name, args.humidity_min, args.humidity_max,
+            );
+            if let Some(hours) = room.co2_hours_above(args.co2_threshold) {
+                let _ = write!(line, ", CO2 above {} ppm for {hours} h", args.co2_threshold);
+            }
+            line
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    Message {
+        title: title(period),
+        body,
+        fields: vec![("period", period.to_string())],
+    }
+}
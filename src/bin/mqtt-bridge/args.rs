@@ -0,0 +1,21 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+use home_environments::logging::LogFormat;
+
+#[derive(Debug, Parser)]
+pub struct Args {
+    /// TOML file with the broker, the sensors and their topics.
+    #[arg(long, env = "CONFIG")]
+    pub config: PathBuf,
+
+    #[arg(long, env = "DATABASE_URL")]
+    pub database_url: String,
+
+    /// Log filter such as `debug` or `info,sqlx=warn`. Overrides `RUST_LOG`.
+    #[arg(long, env = "LOG_LEVEL")]
+    pub log_level: Option<String>,
+
+    #[arg(long, env = "LOG_FORMAT", value_enum)]
+    pub log_format: Option<LogFormat>,
+}
@@ -0,0 +1,136 @@
+mod args;
+mod config;
+mod source;
+
+use std::process::ExitCode;
+
+use anyhow::{Context as _, Result, bail};
+use args::Args;
+use clap::Parser as _;
+use home_environments::{
+    db::{get_switchbot_devices, new_pool},
+    ingest::Engine,
+    logging,
+    switchbot::Device,
+};
+use macaddr::MacAddr6;
+use tokio::sync::watch;
+use tracing::{error, info, warn};
+
+use crate::{
+    config::{Config, SensorConfigFile},
+    source::{MqttSource, Sensor},
+};
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = Args::parse();
+
+    if let Err(e) = logging::init(
+        args.log_level.as_deref(),
+        args.log_format.unwrap_or_default(),
+    ) {
+        eprintln!("failed to initialize logging: {e:#}");
+        return ExitCode::from(1);
+    }
+
+    if let Err(e) = run(args).await {
+        error!("{e:#}");
+        return ExitCode::from(1);
+    }
+
+    ExitCode::from(0)
+}
+
+async fn run(args: Args) -> Result<()> {
+    let config = Config::read(&args.config)?;
+    let pool = new_pool(&args.database_url)
+        .await
+        .context("failed to connect to database")?;
+
+    let devices = get_switchbot_devices(&pool)
+        .await
+        .context("failed to get SwitchBot devices")?;
+    let sensors = config
+        .sensors
+        .into_iter()
+        .map(|sensor| sensor_for(&devices, sensor))
+        .collect::<Result<Vec<_>>>()
+        .with_context(|| format!("invalid sensors in {}", args.config.display()))?
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    if sensors.is_empty() {
+        bail!(
+            "every sensor in {} is of a disabled device",
+            args.config.display()
+        );
+    }
+    info!(sensors = sensors.len(), "bridging");
+
+    let (_settings_tx, settings) = watch::channel(config.settings);
+    let source = MqttSource::new(&config.broker, sensors, settings.clone())?;
+    Engine::new(settings)
+        .sink(pool)
+        .run(vec![Box::new(source)], shutdown_signal())
+        .await?;
+    info!("shut down");
+
+    Ok(())
+}
+
+/// Resolves the device of `sensor`, which has to be registered for its measurements to be stored.
+/// Returns `None` for a disabled device, which is skipped like the ingester does.
+fn sensor_for(devices: &[Device], sensor: SensorConfigFile) -> Result<Option<Sensor>> {
+    let device = match sensor.device.parse::<MacAddr6>() {
+        Ok(id) => devices.iter().find(|device| device.id == id),
+        Err(_) => devices.iter().find(|device| {
+            device
+                .name
+                .trim()
+                .eq_ignore_ascii_case(sensor.device.trim())
+        }),
+    };
+    let Some(device) = device else {
+        bail!(
+            "the device of {} is not in switchbot_devices: {}",
+            sensor.topic,
+            sensor.device
+        );
+    };
+
+    if !device.enabled {
+        warn!(
+            topic = sensor.topic,
+            device = device.name,
+            "skipping the sensor of a disabled device"
+        );
+        return Ok(None);
+    }
+
+    Ok(Some(Sensor {
+        topic: sensor.topic,
+        device_id: device.id,
+        device_name: device.name.clone(),
+        temperature: sensor.temperature,
+        humidity: sensor.humidity,
+        co2: sensor.co2,
+    }))
+}
+
+async fn shutdown_signal() -> Result<()> {
+    #[cfg(unix)]
+    {
+        let mut sigterm =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result?,
+            _ = sigterm.recv() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
+
+    Ok(())
+}
@@ -0,0 +1,40 @@
+use std::path::PathBuf;
+
+use chrono::NaiveDate;
+use chrono_tz::Tz;
+use clap::Parser;
+use home_environments::logging::LogFormat;
+use macaddr::MacAddr6;
+
+#[derive(Debug, Parser)]
+pub struct Args {
+    /// Device whose measurements are exported.
+    #[arg(long)]
+    pub device_id: MacAddr6,
+
+    /// First day to export, in `--timezone`. Defaults to the first measurement.
+    #[arg(long)]
+    pub from: Option<NaiveDate>,
+
+    /// Last day to export, included, in `--timezone`. Defaults to today.
+    #[arg(long)]
+    pub to: Option<NaiveDate>,
+
+    /// Timezone the days and the times in the file are in.
+    #[arg(long, env = "TZ")]
+    pub timezone: Tz,
+
+    /// File to write the export to, instead of stdout. Overwritten if it exists.
+    #[arg(long)]
+    pub output: Option<PathBuf>,
+
+    #[arg(long, env = "DATABASE_URL")]
+    pub database_url: String,
+
+    /// Log filter such as `debug` or `info,sqlx=warn`. Overrides `RUST_LOG`.
+    #[arg(long, env = "LOG_LEVEL")]
+    pub log_level: Option<String>,
+
+    #[arg(long, env = "LOG_FORMAT", value_enum)]
+    pub log_format: Option<LogFormat>,
+}
@@ -0,0 +1,98 @@
+mod args;
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    process::ExitCode,
+};
+
+use anyhow::{Context as _, Result};
+use args::Args;
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use chrono_tz::Tz;
+use clap::Parser as _;
+use home_environments::{
+    db::{get_switchbot_devices, new_pool},
+    export::export_switchbot_measurements_to_csv,
+    logging,
+    switchbot::DeviceType,
+};
+use tracing::{error, info};
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = Args::parse();
+
+    if let Err(e) = logging::init(
+        args.log_level.as_deref(),
+        args.log_format.unwrap_or_default(),
+    ) {
+        eprintln!("failed to initialize logging: {e:#}");
+        return ExitCode::from(1);
+    }
+
+    if let Err(e) = run(args).await {
+        error!("{e:#}");
+        return ExitCode::from(1);
+    }
+
+    ExitCode::from(0)
+}
+
+async fn run(args: Args) -> Result<()> {
+    let pool = new_pool(&args.database_url)
+        .await
+        .context("failed to connect to database")?;
+
+    let device = get_switchbot_devices(&pool)
+        .await
+        .context("failed to get SwitchBot devices")?
+        .into_iter()
+        .find(|device| device.id == args.device_id)
+        .with_context(|| format!("unknown device: {}", args.device_id))?;
+
+    let from = args
+        .from
+        .unwrap_or(NaiveDate::from_ymd_opt(1970, 1, 1).unwrap());
+    // Up to the end of the last day, or of today to include the latest measurements.
+    let to = args
+        .to
+        .unwrap_or_else(|| Utc::now().with_timezone(&args.timezone).date_naive())
+        .checked_add_days(Days::new(1))
+        .context("--to is too late")?;
+    let range = start_of_day(from, args.timezone)?..start_of_day(to, args.timezone)?;
+    let co2 = device.r#type == DeviceType::MeterProCO2;
+
+    let writer: Box<dyn Write> = match &args.output {
+        Some(path) => {
+            Box::new(BufWriter::new(File::create(path).with_context(|| {
+                format!("failed to create {}", path.display())
+            })?))
+        }
+        None => Box::new(io::stdout().lock()),
+    };
+    let rows = export_switchbot_measurements_to_csv(&pool, device.id, range, co2, writer)
+        .await
+        .context("failed to export measurements")?;
+    info!(
+        rows,
+        device_id = %device.id,
+        name = device.name,
+        from = %from,
+        to = %args.to.map_or_else(|| "now".to_string(), |to| to.to_string()),
+        "exported measurements"
+    );
+
+    Ok(())
+}
+
+/// The first instant of `date`, which is not midnight if the clocks are turned forward at midnight.
+fn start_of_day(date: NaiveDate, timezone: Tz) -> Result<DateTime<Tz>> {
+    (0..24)
+        .find_map(|hour| {
+            date.and_hms_opt(hour, 0, 0)?
+                .and_local_timezone(timezone)
+                .earliest()
+        })
+        .with_context(|| format!("{date} has no start in {timezone}"))
+}
@@ -1,20 +1,176 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
 use chrono_tz::Tz;
 use clap::Parser;
+use home_environments::{logging::LogFormat, switchbot::DeviceType};
 use macaddr::MacAddr6;
 
+use crate::{
+    csv::{Columns, TemperatureUnit},
+    dedup::Duplicates,
+    influx::Precision,
+    input::Format,
+    range::Bound,
+    rejects::OnError,
+    timezone::{AmbiguousTime, FileTimezone},
+};
+
 #[derive(Debug, Parser)]
 pub struct Args {
+    /// Device the measurements are attributed to. Required for CSV files; NDJSON records and line
+    /// protocol points can name their own.
     #[arg(long)]
-    pub device_id: MacAddr6,
+    pub device_id: Option<MacAddr6>,
 
-    #[arg(long)]
-    pub file: PathBuf,
+    /// Name of the device the measurements are attributed to, looked up in the database instead
+    /// of giving `--device-id`.
+    #[arg(long, conflicts_with = "device_id")]
+    pub device_name: Option<String>,
+
+    /// The registered type of the device, looked up once connected to the database, which the
+    /// exports are checked against.
+    #[arg(skip)]
+    pub device_type: Option<DeviceType>,
 
+    /// CSV export to import, a glob pattern such as `exports/Meter_*.csv`, or `-` for the standard
+    /// input. Can be repeated.
+    #[arg(long = "file", required = true)]
+    pub files: Vec<String>,
+
+    /// Timezone of the timestamps. Required for CSV files; NDJSON timestamps carry an offset.
     #[arg(long, env = "TZ")]
-    pub timezone: Tz,
+    pub timezone: Option<Tz>,
+
+    /// Timezone of the files matching a glob pattern, such as `exports/berlin/*.csv=Europe/Berlin`,
+    /// instead of `--timezone`. Can be repeated; the first match applies.
+    #[arg(long = "file-timezone", value_name = "PATTERN=TZ")]
+    pub file_timezones: Vec<FileTimezone>,
+
+    /// Which time to take for a local time that occurs twice, when the clocks are turned back.
+    #[arg(long, value_enum, default_value_t)]
+    pub ambiguous_time: AmbiguousTime,
+
+    /// Format of the files.
+    #[arg(long, value_enum, default_value_t)]
+    pub format: Format,
+
+    /// Tag of InfluxDB line protocol holding the MAC address of the device.
+    #[arg(long, default_value = "device_id")]
+    pub device_tag: String,
+
+    /// Measurement of InfluxDB line protocol to import, such as `switchbot`. All by default.
+    #[arg(long)]
+    pub measurement: Option<String>,
+
+    /// Unit of the timestamps of InfluxDB line protocol.
+    #[arg(long, value_enum, default_value_t)]
+    pub precision: Precision,
+
+    /// Unit of the temperatures in the files. Detected from the header by default.
+    #[arg(long, value_enum)]
+    pub temperature_unit: Option<TemperatureUnit>,
+
+    /// Column indexes, counted from 0, for files that are not in a known layout, such as
+    /// `measured_at=0,temperature=2,humidity=3,co2=5`. `light` can also be given.
+    #[arg(long)]
+    pub map: Option<Columns>,
+
+    /// Layout of the timestamps in `strftime` syntax, such as `%d.%m.%Y %H:%M`, in `--timezone`
+    /// unless it has an offset with `%z`.
+    #[arg(long)]
+    pub timestamp_format: Option<String>,
+
+    #[arg(long, env = "DATABASE_URL", required_unless_present = "dry_run")]
+    pub database_url: Option<String>,
+
+    /// Only import the measurements from this day, or this time, on. In `--timezone` unless it has
+    /// an offset, as in `2025-01-31T18:00:00+09:00`.
+    #[arg(long)]
+    pub from: Option<Bound>,
+
+    /// Only import the measurements up to this day, included, or up to this time, excluded.
+    #[arg(long)]
+    pub to: Option<Bound>,
+
+    /// Which values to keep of the rows of a file that have the same device and time.
+    #[arg(long, value_enum, default_value_t)]
+    pub duplicates: Duplicates,
+
+    /// What to do with a record that fails to parse.
+    #[arg(long, value_enum, default_value_t)]
+    pub on_error: OnError,
+
+    /// CSV file that records skipped with `--on-error skip` are written to, along with their error.
+    #[arg(long, default_value = "import-errors.csv")]
+    pub errors_file: PathBuf,
+
+    /// Largest change of temperature between consecutive rows of a device, in Celsius, before it
+    /// is reported as implausible.
+    #[arg(long, default_value_t = 5.0)]
+    pub max_temperature_jump: f32,
+
+    /// Treat a row whose time goes backwards or whose temperature jumps as an invalid record,
+    /// instead of importing it with a warning.
+    #[arg(long)]
+    pub strict: bool,
+
+    /// CSV file that implausible rows imported without `--strict` are written to, along with what
+    /// is implausible about them.
+    #[arg(long, default_value = "import-warnings.csv")]
+    pub warnings_file: PathBuf,
+
+    /// Also store the dew point and the absolute humidity of every row, computed from its
+    /// temperature and humidity.
+    #[arg(long)]
+    pub derived_metrics: bool,
+
+    /// Parse the files and report what would be imported, without connecting to the database.
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Count the rows that already exist in the database, which are skipped. Needs
+    /// `--database-url`, even with `--dry-run`.
+    #[arg(long)]
+    pub check_existing: bool,
+
+    /// File to record the progress of the import in, so that it can be resumed with `--resume`
+    /// if it is interrupted. It is removed once everything is imported.
+    #[arg(long, env = "CHECKPOINT", conflicts_with = "dry_run")]
+    pub checkpoint: Option<PathBuf>,
+
+    /// Skip the rows that the checkpoint records as committed.
+    #[arg(long, requires = "checkpoint")]
+    pub resume: bool,
+
+    /// Shortest stretch without measurements of a device that is reported as a gap.
+    #[arg(long, default_value = "10m", value_parser = humantime::parse_duration)]
+    pub min_gap: Duration,
+
+    /// Print a summary of the import as JSON on stdout.
+    #[arg(long)]
+    pub summary_json: bool,
+
+    /// Number of batches to insert at once, while the next ones are parsed.
+    #[arg(long, short, env = "JOBS", default_value_t = 1)]
+    pub jobs: usize,
+
+    /// Number of rows to insert at once.
+    #[arg(long, default_value_t = 1000)]
+    pub batch_size: usize,
+
+    /// Insert each file in a single transaction, so that nothing of it is imported if any of it
+    /// fails. The batches are then inserted one after another.
+    #[arg(long, conflicts_with = "jobs")]
+    pub atomic: bool,
+
+    /// Do not draw a progress bar.
+    #[arg(long, short)]
+    pub quiet: bool,
+
+    /// Log filter such as `debug` or `info,sqlx=warn`. Overrides `RUST_LOG`.
+    #[arg(long, env = "LOG_LEVEL")]
+    pub log_level: Option<String>,
 
-    #[arg(long, env = "DATABASE_URL")]
-    pub database_url: String,
+    #[arg(long, env = "LOG_FORMAT", value_enum)]
+    pub log_format: Option<LogFormat>,
 }
@@ -0,0 +1,149 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{ErrorKind, Read},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context as _, Result, bail};
+use ring::digest::{Context as DigestContext, SHA256};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tracing::info;
+
+/// How far an import got, rewritten after every committed batch so that `--resume` can skip the
+/// rows that are already in the database.
+#[derive(Debug)]
+pub struct Checkpoint {
+    path: PathBuf,
+    inputs: BTreeMap<String, InputState>,
+}
+
+/// How far the import of one input got, keyed by [`crate::input::Input::name`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InputState {
+    /// The SHA-256 of the file the input was read from, to tell whether it changed in between.
+    pub sha256: String,
+    /// The number of records committed, counted from the first one after the header.
+    pub rows: usize,
+    pub done: bool,
+}
+
+impl Checkpoint {
+    /// With `resume`, picks up the checkpoint left at `path` by an earlier run, if any. Otherwise,
+    /// starts from scratch and replaces it on the first save.
+    pub async fn load(path: PathBuf, resume: bool) -> Result<Self> {
+        if !resume {
+            return Ok(Self {
+                path,
+                inputs: BTreeMap::new(),
+            });
+        }
+
+        let inputs = match fs::read_to_string(&path).await {
+            Ok(content) => serde_json::from_str(&content)
+                .with_context(|| format!("failed to parse checkpoint file: {path:?}"))?,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                info!(
+                    ?path,
+                    "no checkpoint to resume from, starting from the beginning"
+                );
+                BTreeMap::new()
+            }
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("failed to read checkpoint file: {path:?}"));
+            }
+        };
+
+        Ok(Self { path, inputs })
+    }
+
+    /// Returns where to resume the input named `name`, or registers it as new. Fails if the file
+    /// has changed since it was checkpointed.
+    pub fn start(&mut self, name: &str, sha256: &str) -> Result<InputState> {
+        let state = self
+            .inputs
+            .entry(name.to_string())
+            .or_insert_with(|| InputState {
+                sha256: sha256.to_string(),
+                rows: 0,
+                done: false,
+            });
+        if state.sha256 != sha256 {
+            bail!(
+                "{name} has changed since it was checkpointed, remove the checkpoint to start over"
+            );
+        }
+
+        Ok(state.clone())
+    }
+
+    /// The number of records of `name` that are committed.
+    pub fn committed(&self, name: &str) -> usize {
+        self.inputs.get(name).map_or(0, |state| state.rows)
+    }
+
+    /// Records that the first `rows` records of `name` are committed.
+    pub async fn commit(&mut self, name: &str, rows: usize) -> Result<()> {
+        if let Some(state) = self.inputs.get_mut(name) {
+            state.rows = rows;
+        }
+        self.save().await
+    }
+
+    /// Records that every record of `name` is committed.
+    pub async fn finish(&mut self, name: &str) -> Result<()> {
+        if let Some(state) = self.inputs.get_mut(name) {
+            state.done = true;
+        }
+        self.save().await
+    }
+
+    /// Removes the checkpoint file once everything is imported.
+    pub async fn remove(self) -> Result<()> {
+        match fs::remove_file(&self.path).await {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err)
+                .with_context(|| format!("failed to remove checkpoint file: {:?}", self.path)),
+        }
+    }
+
+    async fn save(&self) -> Result<()> {
+        let tmp_path = self.path.with_extension("tmp");
+
+        let content =
+            serde_json::to_string_pretty(&self.inputs).context("failed to serialize checkpoint")?;
+        fs::write(&tmp_path, content)
+            .await
+            .with_context(|| format!("failed to write checkpoint file: {tmp_path:?}"))?;
+        fs::rename(&tmp_path, &self.path)
+            .await
+            .with_context(|| format!("failed to replace checkpoint file: {:?}", self.path))
+    }
+}
+
+/// The SHA-256 of the file at `path`, in lowercase hex.
+pub fn sha256(path: &Path) -> Result<String> {
+    let mut file = File::open(path).with_context(|| format!("failed to open file: {path:?}"))?;
+
+    let mut context = DigestContext::new(&SHA256);
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = file
+            .read(&mut buf)
+            .with_context(|| format!("failed to read file: {path:?}"))?;
+        if n == 0 {
+            break;
+        }
+        context.update(&buf[..n]);
+    }
+
+    Ok(context
+        .finish()
+        .as_ref()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect())
+}
@@ -1,105 +1,516 @@
-use std::fs::File;
-use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::{io::Read, str::FromStr};
 
-use anyhow::{Context as _, Result, bail};
-use chrono::{LocalResult, NaiveDateTime};
+use anyhow::{Context as _, Result, anyhow, bail};
+use chrono::{DateTime, DurationRound as _, FixedOffset, NaiveDateTime, TimeDelta, Utc};
 use chrono_tz::Tz;
-use csv::Reader;
-use home_environments::switchbot::Measurement;
+use clap::ValueEnum;
+use csv::{Reader, ReaderBuilder, StringRecord};
+use home_environments::switchbot::{DeviceType, Measurement};
 use macaddr::MacAddr6;
 
-const MEASURED_AT_INDEX: usize = 0;
-const TEMPERATURE_CELSIUS_INDEX: usize = 1;
-const HUMIDITY_PERCENT_INDEX: usize = 2;
-const CO2_PPM_INDEX: usize = 3;
-const LIGHT_LEVEL_INDEX: usize = 6;
+use crate::{
+    dedup::Duplicates,
+    device_type::{self, Inferred},
+    influx::InfluxOptions,
+    input::InvalidRecord,
+    plausibility::PlausibilityOptions,
+    range::TimeRange,
+    timezone::{AmbiguousTime, to_utc},
+};
 
 #[derive(Debug, Clone, Copy)]
 enum CsvFormat {
-    TemperatureHumidity,
-    TemperatureHumidityCo2,
-    TemperatureHumidityLightLevel,
+    SwitchBot,
+    /// Exported from the Govee app, with seconds in the timestamps and a fractional humidity.
+    Govee,
+    /// Exported from the Aranet Home app, with the day before the month unless the header says
+    /// otherwise.
+    Aranet4 {
+        month_first: bool,
+    },
 }
 
+impl CsvFormat {
+    /// The layouts of the timestamps, tried in order.
+    fn timestamp_formats(self) -> &'static [&'static str] {
+        match self {
+            CsvFormat::SwitchBot => &[
+                "%Y-%m-%d %H:%M",
+                "%Y-%m-%d %H:%M:%S%.f",
+                "%Y/%m/%d %H:%M",
+                "%Y/%m/%d %H:%M:%S%.f",
+            ],
+            CsvFormat::Govee => &["%Y-%m-%d %H:%M:%S"],
+            CsvFormat::Aranet4 { month_first: false } => &["%d/%m/%Y %H:%M:%S"],
+            CsvFormat::Aranet4 { month_first: true } => &["%m/%d/%Y %H:%M:%S"],
+        }
+    }
+}
+
+/// The indexes of the columns of an export, which can also be given as
+/// `measured_at=0,temperature=2,humidity=3,co2=5`.
+#[derive(Debug, Clone, Copy)]
+pub struct Columns {
+    measured_at: usize,
+    temperature_celsius: usize,
+    humidity_percent: usize,
+    co2_ppm: Option<usize>,
+    light_level: Option<usize>,
+}
+
+impl Columns {
+    /// The layout of a SwitchBot export, assumed when the file has no header.
+    const SWITCHBOT: Self = Self {
+        measured_at: 0,
+        temperature_celsius: 1,
+        humidity_percent: 2,
+        co2_ppm: None,
+        light_level: None,
+    };
+
+    /// Finds the columns by their names in the header, in English or Japanese and ignoring case,
+    /// units and the order of the columns, so that a reshuffled export still imports. Returns
+    /// `None` if the row names none of the required columns, which means the file has no header.
+    fn from_header(header: &StringRecord) -> Option<Result<Self>> {
+        let names: Vec<String> = header
+            .iter()
+            .map(|name| name.trim().to_lowercase())
+            .collect();
+        let find = |matches: fn(&str) -> bool| names.iter().position(|name| matches(name));
+
+        let measured_at = find(is_measured_at);
+        let temperature_celsius = find(is_temperature);
+        let humidity_percent = find(is_humidity);
+        let columns = match (measured_at, temperature_celsius, humidity_percent) {
+            (None, None, None) => return None,
+            (Some(measured_at), Some(temperature_celsius), Some(humidity_percent)) => Ok(Self {
+                measured_at,
+                temperature_celsius,
+                humidity_percent,
+                co2_ppm: find(is_co2),
+                light_level: find(is_light_level),
+            }),
+            _ => Err(anyhow!(
+                "CSV header lacks a timestamp, temperature or humidity column: {}",
+                names.join(",")
+            )),
+        };
+
+        Some(columns)
+    }
+}
+
+impl FromStr for Columns {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (mut measured_at, mut temperature_celsius, mut humidity_percent) = (None, None, None);
+        let (mut co2_ppm, mut light_level) = (None, None);
+        for pair in s.split(',') {
+            let (name, index) = pair
+                .split_once('=')
+                .with_context(|| format!("expected NAME=INDEX, got {pair:?}"))?;
+            let index = index
+                .trim()
+                .parse()
+                .with_context(|| format!("invalid column index: {index:?}"))?;
+            let column = match name.trim() {
+                "measured_at" | "time" | "timestamp" => &mut measured_at,
+                "temperature" => &mut temperature_celsius,
+                "humidity" => &mut humidity_percent,
+                "co2" => &mut co2_ppm,
+                "light" | "light_level" => &mut light_level,
+                name => bail!(
+                    "unknown column {name:?}, expected measured_at, temperature, humidity, co2 or light"
+                ),
+            };
+            *column = Some(index);
+        }
+
+        Ok(Self {
+            measured_at: measured_at.context("the column mapping lacks measured_at")?,
+            temperature_celsius: temperature_celsius
+                .context("the column mapping lacks temperature")?,
+            humidity_percent: humidity_percent.context("the column mapping lacks humidity")?,
+            co2_ppm,
+            light_level,
+        })
+    }
+}
+
+fn is_measured_at(name: &str) -> bool {
+    ["date", "time"].iter().any(|n| name.starts_with(n))
+        || ["日時", "日付", "時刻"].iter().any(|n| name.contains(n))
+}
+
+/// Skips the dew point, which SwitchBot exports next to the temperature.
+fn is_temperature(name: &str) -> bool {
+    (name.starts_with("temp") || name.contains("温度"))
+        && !["dew", "dpt", "露点"].iter().any(|n| name.contains(n))
+}
+
+/// Skips the absolute humidity, which SwitchBot exports next to the relative humidity.
+fn is_humidity(name: &str) -> bool {
+    ["humidity", "湿度"].iter().any(|n| name.contains(n))
+        && !["abs", "絶対"].iter().any(|n| name.contains(n))
+}
+
+fn is_co2(name: &str) -> bool {
+    ["co2", "carbon dioxide", "二酸化炭素"]
+        .iter()
+        .any(|n| name.contains(n))
+}
+
+fn is_light_level(name: &str) -> bool {
+    ["light", "照度", "明るさ"].iter().any(|n| name.contains(n))
+}
+
+/// The Mi Home data export, which has a row per reading rather than per measurement, such as
+/// `time,key,value` with `1710320400,temperature,22.4`. The readings are pivoted into one measurement
+/// per minute.
 #[derive(Debug)]
-pub struct CsvMeasurementIter {
-    reader: Reader<File>,
+struct Pivot {
+    time: usize,
+    key: usize,
+    value: usize,
+    minute: Option<Minute>,
+    last_temperature_celsius: Option<f32>,
+    last_humidity_percent: Option<f32>,
+}
+
+/// The readings of the minute being pivoted.
+#[derive(Debug)]
+struct Minute {
+    measured_at: DateTime<Utc>,
+    temperature_celsius: Option<f32>,
+    humidity_percent: Option<f32>,
+}
+
+impl Pivot {
+    fn detect(header: &StringRecord) -> Option<Self> {
+        let position = |names: &[&str]| {
+            header
+                .iter()
+                .position(|field| names.contains(&field.trim().to_lowercase().as_str()))
+        };
+
+        Some(Self {
+            time: position(&["time", "timestamp"])?,
+            key: position(&["key"])?,
+            value: position(&["value"])?,
+            minute: None,
+            last_temperature_celsius: None,
+            last_humidity_percent: None,
+        })
+    }
+
+    /// Completes the minute being pivoted. A reading missing from it is carried over from the last
+    /// minute that had one; until both have been read, minutes are skipped.
+    fn finish_minute(&mut self) -> Option<(DateTime<Utc>, f32, f32)> {
+        let minute = self.minute.take()?;
+        let temperature_celsius = minute.temperature_celsius.or(self.last_temperature_celsius);
+        let humidity_percent = minute.humidity_percent.or(self.last_humidity_percent);
+        self.last_temperature_celsius = temperature_celsius;
+        self.last_humidity_percent = humidity_percent;
+
+        Some((minute.measured_at, temperature_celsius?, humidity_percent?))
+    }
+}
+
+/// The unit of the temperature column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TemperatureUnit {
+    #[value(alias = "c")]
+    Celsius,
+    #[value(alias = "f")]
+    Fahrenheit,
+}
+
+/// How to read the rows of an export.
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    /// The device the measurements are attributed to. Required for CSV files.
+    pub device_id: Option<MacAddr6>,
+    /// The timezone of timestamps without an offset. Required for CSV files.
+    pub timezone: Option<Tz>,
+    /// Which time to take for a local time that occurs twice.
+    pub ambiguous_time: AmbiguousTime,
+    /// Overrides the unit detected from the header, which defaults to Celsius.
+    pub temperature_unit: Option<TemperatureUnit>,
+    /// Overrides the columns found by their names in the header.
+    pub columns: Option<Columns>,
+    /// Overrides the timestamp layouts of the detected format, in `strftime` syntax.
+    pub timestamp_format: Option<String>,
+    /// How to read InfluxDB line protocol.
+    pub influx: InfluxOptions,
+    /// Only the measurements in this range are imported.
+    pub range: TimeRange,
+    /// Which values to keep of the rows with the same device and time.
+    pub duplicates: Duplicates,
+    /// How to check that the measurements follow on from each other.
+    pub plausibility: PlausibilityOptions,
+}
+
+#[derive(Debug)]
+pub struct CsvMeasurementIter<R> {
+    reader: Reader<R>,
+    /// The first row of a file without a header, which is data.
+    first_row: Option<StringRecord>,
     format: CsvFormat,
+    columns: Columns,
+    timestamp_format: Option<String>,
+    pivot: Option<Pivot>,
+    temperature_unit: TemperatureUnit,
     device_id: MacAddr6,
     timezone: Tz,
+    ambiguous_time: AmbiguousTime,
+    device_type: Option<Inferred>,
 }
 
-impl CsvMeasurementIter {
-    pub fn new(mut file: File, device_id: MacAddr6, timezone: Tz) -> Result<Self> {
-        let mut buf_reader = BufReader::new(&file);
-        let mut header = String::new();
-        buf_reader
-            .read_line(&mut header)
-            .context("failed to read CSV header")?;
+impl<R: Read> CsvMeasurementIter<R> {
+    /// Reads the header of the input named `name`, whose file name can tell the app that exported
+    /// it and the type of the device.
+    pub fn new(reader: R, name: &str, options: &ParseOptions) -> Result<Self> {
+        let device_id = options
+            .device_id
+            .context("--device-id is required for CSV files")?;
+        let timezone = options
+            .timezone
+            .context("--timezone is required for CSV files")?;
 
-        let format = detect_format(&header);
+        // A truncated row is reported as missing a column, along with the row itself.
+        let mut reader = ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(reader);
+        let mut header = StringRecord::new();
+        let has_header = reader
+            .read_record(&mut header)
+            .context("failed to read CSV header")?;
 
-        file.seek(SeekFrom::Start(0))
-            .context("failed to seek to start of file")?;
-        let reader = Reader::from_reader(file);
+        let joined = header.iter().collect::<Vec<_>>().join(",");
+        let format = detect_format(&joined, &device_type::file_name_words(name));
+        let pivot = Pivot::detect(&header).filter(|_| options.columns.is_none());
+        let temperature_unit = options
+            .temperature_unit
+            .unwrap_or_else(|| detect_temperature_unit(&joined));
+        let (columns, first_row) = match (options.columns, Columns::from_header(&header)) {
+            // The header names are not to be relied on, so it is only told apart from data by its
+            // timestamp.
+            (Some(columns), _) => {
+                let is_data = header.get(columns.measured_at).is_some_and(|field| {
+                    parse_timestamp(field, format, options.timestamp_format.as_deref()).is_some()
+                });
+                (columns, (has_header && is_data).then_some(header))
+            }
+            _ if pivot.is_some() => (Columns::SWITCHBOT, None),
+            (None, Some(columns)) => (columns?, None),
+            (None, None) => (Columns::SWITCHBOT, has_header.then_some(header)),
+        };
+        // Only SwitchBot makes a meter with a CO2 sensor, the MeterPro(CO2).
+        let device_type = match format {
+            CsvFormat::SwitchBot if columns.co2_ppm.is_some() => Some(Inferred {
+                device_type: DeviceType::MeterProCO2,
+                source: "CO2 column",
+            }),
+            CsvFormat::SwitchBot => device_type::from_file_name(name).map(|device_type| Inferred {
+                device_type,
+                source: "file name",
+            }),
+            _ => None,
+        };
 
         Ok(Self {
             reader,
+            first_row,
             format,
+            columns,
+            timestamp_format: options.timestamp_format.clone(),
+            pivot,
+            temperature_unit,
             device_id,
             timezone,
+            ambiguous_time: options.ambiguous_time,
+            device_type,
         })
     }
+
+    /// The type of the device that the export looks like it is of.
+    pub fn device_type(&self) -> Option<Inferred> {
+        self.device_type
+    }
+
+    fn read_row(&mut self) -> Option<Result<StringRecord>> {
+        if let Some(row) = self.first_row.take() {
+            return Some(Ok(row));
+        }
+
+        let mut row = StringRecord::new();
+        match self.reader.read_record(&mut row) {
+            Ok(true) => Some(Ok(row)),
+            Ok(false) => None,
+            Err(e) => Some(Err(e.into())),
+        }
+    }
+
+    fn celsius(&self, temperature: f32) -> f32 {
+        match self.temperature_unit {
+            TemperatureUnit::Celsius => temperature,
+            TemperatureUnit::Fahrenheit => (temperature - 32.0) * 5.0 / 9.0,
+        }
+    }
+
+    /// Reads rows until a minute of the Mi Home export is complete.
+    fn next_pivoted(&mut self) -> Option<Result<Measurement>> {
+        loop {
+            let row = match self.read_row() {
+                Some(Ok(row)) => row,
+                Some(Err(e)) => return Some(Err(e)),
+                None => {
+                    let pivot = self.pivot.as_mut()?;
+                    let (measured_at, temperature_celsius, humidity_percent) =
+                        pivot.finish_minute()?;
+                    return Some(humidity(humidity_percent).map(|humidity_percent| {
+                        self.measurement(measured_at, temperature_celsius, humidity_percent)
+                    }));
+                }
+            };
+            let pivot = self.pivot.as_mut()?;
+            let line = row.position().map_or(0, |p| p.line());
+            let reading = (|| -> Result<Option<(DateTime<Utc>, bool, f32)>> {
+                let get = |index| field(&row, index);
+
+                let key = get(pivot.key)?.trim().to_lowercase();
+                let is_temperature = match key.as_str() {
+                    "temperature" | "temp" => true,
+                    "humidity" | "humi" => false,
+                    // Such as the battery level.
+                    _ => return Ok(None),
+                };
+
+                let field = get(pivot.time)?;
+                let measured_at = field
+                    .parse()
+                    .ok()
+                    .and_then(|secs| DateTime::from_timestamp(secs, 0))
+                    .with_context(|| format!("failed to parse timestamp: {field}"))?
+                    .duration_trunc(TimeDelta::minutes(1))
+                    .context("failed to truncate timestamp to the minute")?;
+                let field = get(pivot.value)?;
+                let value = field
+                    .parse()
+                    .with_context(|| format!("failed to parse {key}: {field}"))?;
+
+                Ok(Some((measured_at, is_temperature, value)))
+            })()
+            .with_context(|| invalid_record(line, &row));
+            let (measured_at, is_temperature, value) = match reading {
+                Ok(Some(reading)) => reading,
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            };
+
+            let finished = match &pivot.minute {
+                Some(minute) if minute.measured_at != measured_at => pivot.finish_minute(),
+                _ => None,
+            };
+            let minute = pivot.minute.get_or_insert(Minute {
+                measured_at,
+                temperature_celsius: None,
+                humidity_percent: None,
+            });
+            if is_temperature {
+                minute.temperature_celsius = Some(value);
+            } else {
+                minute.humidity_percent = Some(value);
+            }
+
+            if let Some((measured_at, temperature_celsius, humidity_percent)) = finished {
+                return Some(humidity(humidity_percent).map(|humidity_percent| {
+                    self.measurement(measured_at, temperature_celsius, humidity_percent)
+                }));
+            }
+        }
+    }
+
+    fn measurement(
+        &self,
+        measured_at: DateTime<Utc>,
+        temperature_celsius: f32,
+        humidity_percent: u8,
+    ) -> Measurement {
+        Measurement {
+            device_id: self.device_id,
+            measured_at,
+            temperature_celsius: self.celsius(temperature_celsius),
+            humidity_percent,
+            co2_ppm: None,
+            light_level: None,
+            rssi_dbm: None,
+            source: None,
+        }
+    }
 }
 
-impl Iterator for CsvMeasurementIter {
+impl<R: Read> Iterator for CsvMeasurementIter<R> {
     type Item = Result<Measurement>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let row = match self.reader.records().next()? {
+        if self.pivot.is_some() {
+            return self.next_pivoted();
+        }
+
+        let row = match self.read_row()? {
             Ok(row) => row,
-            Err(e) => return Some(Err(e.into())),
+            Err(e) => return Some(Err(e)),
         };
 
+        let line = row.position().map_or(0, |p| p.line());
+        let columns = self.columns;
         let record = (|| -> Result<Measurement> {
-            let naive = NaiveDateTime::parse_from_str(&row[MEASURED_AT_INDEX], "%Y-%m-%d %H:%M")
-                .with_context(|| {
-                    format!("failed to parse timestamp: {}", &row[MEASURED_AT_INDEX])
-                })?;
-            let measured_at = match naive.and_local_timezone(self.timezone) {
-                LocalResult::Single(dt) => dt,
-                LocalResult::Ambiguous(dt, _) => dt,
-                LocalResult::None => bail!("invalid timestamp: {}", &row[MEASURED_AT_INDEX]),
-            };
+            let get = |index| field(&row, index);
+
+            let field = get(columns.measured_at)?;
+            let measured_at =
+                match parse_timestamp(field, self.format, self.timestamp_format.as_deref())
+                    .with_context(|| format!("failed to parse timestamp: {field}"))?
+                {
+                    Timestamp::Local(naive) => to_utc(naive, self.timezone, self.ambiguous_time)?,
+                    Timestamp::Fixed(dt) => dt.with_timezone(&Utc),
+                };
+
+            let field = get(columns.temperature_celsius)?;
+            let temperature_celsius = self.celsius(
+                field
+                    .parse()
+                    .with_context(|| format!("failed to parse temperature: {field}"))?,
+            );
+
+            let field = get(columns.humidity_percent)?;
+            let humidity_percent = humidity(
+                field
+                    .parse()
+                    .with_context(|| format!("failed to parse humidity: {field}"))?,
+            )?;
 
-            let temperature_celsius =
-                row[TEMPERATURE_CELSIUS_INDEX].parse().with_context(|| {
-                    format!(
-                        "failed to parse temperature: {}",
-                        &row[TEMPERATURE_CELSIUS_INDEX]
-                    )
-                })?;
-            let humidity_percent = row[HUMIDITY_PERCENT_INDEX].parse().with_context(|| {
-                format!("failed to parse humidity: {}", &row[HUMIDITY_PERCENT_INDEX])
-            })?;
-            let co2_ppm = match self.format {
-                CsvFormat::TemperatureHumidity => None,
-                CsvFormat::TemperatureHumidityCo2 => Some(
-                    row[CO2_PPM_INDEX]
+            let co2_ppm = columns
+                .co2_ppm
+                .map(|index| {
+                    let field = get(index)?;
+                    field
                         .parse()
-                        .with_context(|| format!("failed to parse CO2: {}", &row[CO2_PPM_INDEX]))?,
-                ),
-                CsvFormat::TemperatureHumidityLightLevel => None,
-            };
-            let light_level = match self.format {
-                CsvFormat::TemperatureHumidity => None,
-                CsvFormat::TemperatureHumidityCo2 => None,
-                CsvFormat::TemperatureHumidityLightLevel => {
-                    Some(row[LIGHT_LEVEL_INDEX].parse().with_context(|| {
-                        format!("failed to parse light level: {}", &row[LIGHT_LEVEL_INDEX])
-                    })?)
-                }
-            };
+                        .with_context(|| format!("failed to parse CO2: {field}"))
+                })
+                .transpose()?;
+            let light_level = columns
+                .light_level
+                .map(|index| {
+                    let field = get(index)?;
+                    field
+                        .parse()
+                        .with_context(|| format!("failed to parse light level: {field}"))
+                })
+                .transpose()?;
 
             Ok(Measurement {
                 device_id: self.device_id,
@@ -108,21 +519,114 @@ impl Iterator for CsvMeasurementIter {
                 humidity_percent,
                 co2_ppm,
                 light_level,
+                rssi_dbm: None,
+                source: None,
             })
-        })();
+        })()
+        .with_context(|| invalid_record(line, &row));
 
         Some(record)
     }
 }
 
-fn detect_format(header: &str) -> CsvFormat {
-    if header.contains("Co2") {
-        return CsvFormat::TemperatureHumidityCo2;
+/// The field at `index`, which a truncated row or a column given with `--map` may be missing.
+fn field(row: &StringRecord, index: usize) -> Result<&str> {
+    row.get(index)
+        .with_context(|| format!("record has no column {index}"))
+}
+
+fn invalid_record(line: u64, row: &StringRecord) -> InvalidRecord {
+    InvalidRecord {
+        line,
+        record: row.iter().collect::<Vec<_>>().join(","),
     }
+}
+
+/// The layouts of timestamps with a UTC offset, tried before those of the format along with RFC
+/// 3339.
+const OFFSET_TIMESTAMP_FORMATS: [&str; 4] = [
+    "%Y-%m-%d %H:%M:%S%.f%z",
+    "%Y-%m-%dT%H:%M:%S%.f%z",
+    "%Y-%m-%d %H:%M%z",
+    "%Y-%m-%dT%H:%M%z",
+];
 
-    if header.contains("Light_Value") {
-        return CsvFormat::TemperatureHumidityLightLevel;
+/// The ISO 8601 layouts of local timestamps, tried after those of the format.
+const ISO_TIMESTAMP_FORMATS: [&str; 2] = ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%dT%H:%M"];
+
+/// A timestamp as written in a file.
+#[derive(Debug, Clone, Copy)]
+enum Timestamp {
+    /// In `--timezone`.
+    Local(NaiveDateTime),
+    /// With its own offset, which takes precedence over `--timezone`.
+    Fixed(DateTime<FixedOffset>),
+}
+
+fn parse_timestamp(
+    field: &str,
+    format: CsvFormat,
+    timestamp_format: Option<&str>,
+) -> Option<Timestamp> {
+    if let Some(timestamp_format) = timestamp_format {
+        return DateTime::parse_from_str(field, timestamp_format)
+            .map(Timestamp::Fixed)
+            .or_else(|_| {
+                NaiveDateTime::parse_from_str(field, timestamp_format).map(Timestamp::Local)
+            })
+            .ok();
+    }
+
+    DateTime::parse_from_rfc3339(field)
+        .ok()
+        .or_else(|| {
+            OFFSET_TIMESTAMP_FORMATS
+                .iter()
+                .find_map(|format| DateTime::parse_from_str(field, format).ok())
+        })
+        .map(Timestamp::Fixed)
+        .or_else(|| {
+            format
+                .timestamp_formats()
+                .iter()
+                .chain(&ISO_TIMESTAMP_FORMATS)
+                .find_map(|format| NaiveDateTime::parse_from_str(field, format).ok())
+                .map(Timestamp::Local)
+        })
+}
+
+/// Some apps export a fractional humidity, which is rounded to fit the column.
+pub fn humidity(humidity_percent: f32) -> Result<u8> {
+    if !(0.0..=100.0).contains(&humidity_percent) {
+        bail!("humidity out of range: {humidity_percent}");
+    }
+
+    Ok(humidity_percent.round() as u8)
+}
+
+/// SwitchBot and Govee name the unit in the header, as in `Temperature_Fahrenheit(°F)`, and so
+/// does Aranet.
+fn detect_temperature_unit(header: &str) -> TemperatureUnit {
+    if header.contains("Fahrenheit") || header.contains("°F") || header.contains('℉') {
+        return TemperatureUnit::Fahrenheit;
+    }
+
+    TemperatureUnit::Celsius
+}
+
+/// Detects the app that exported the file from the words of its file name, such as `Aranet4 1A2B`,
+/// or else from its header.
+fn detect_format(header: &str, file_name_words: &[String]) -> CsvFormat {
+    let named = |name: &str| file_name_words.iter().any(|word| word == name);
+    if named("govee") || header.starts_with("Timestamp for sample frequency") {
+        return CsvFormat::Govee;
+    }
+
+    if named("aranet") || named("aranet4") || header.contains("Carbon dioxide") {
+        return CsvFormat::Aranet4 {
+            month_first: header.to_lowercase().contains("mm/dd/yyyy"),
+        };
     }
 
-    CsvFormat::TemperatureHumidity
+    CsvFormat::SwitchBot
 }
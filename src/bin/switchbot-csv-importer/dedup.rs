@@ -0,0 +1,210 @@
+use std::{
+    collections::{HashMap, HashSet},
+    mem,
+};
+
+use anyhow::{Result, ensure};
+use chrono::{DateTime, TimeDelta, Utc};
+use clap::ValueEnum;
+use home_environments::switchbot::Measurement;
+use macaddr::MacAddr6;
+
+/// Which values to keep of the rows of a file that have the same device and time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Duplicates {
+    /// The first row, as if the others were inserted after it.
+    #[default]
+    First,
+    /// The last row.
+    Last,
+    /// The average of the rows. The other values are those of the first row.
+    Average,
+}
+
+/// The values of the rows that are averaged.
+#[derive(Debug, Default)]
+struct Sum {
+    rows: u32,
+    temperature_celsius: f64,
+    humidity_percent: f64,
+    co2_ppm: (f64, u32),
+    light_level: (f64, u32),
+}
+
+impl Sum {
+    fn add(&mut self, m: &Measurement) {
+        self.rows += 1;
+        self.temperature_celsius += f64::from(m.temperature_celsius);
+        self.humidity_percent += f64::from(m.humidity_percent);
+        if let Some(co2_ppm) = m.co2_ppm {
+            self.co2_ppm.0 += f64::from(co2_ppm);
+            self.co2_ppm.1 += 1;
+        }
+        if let Some(light_level) = m.light_level {
+            self.light_level.0 += f64::from(light_level);
+            self.light_level.1 += 1;
+        }
+    }
+
+    fn average_into(&self, m: &mut Measurement) {
+        let average = |(sum, rows): (f64, u32)| (rows > 0).then(|| sum / f64::from(rows));
+        m.temperature_celsius = (self.temperature_celsius / f64::from(self.rows)) as f32;
+        m.humidity_percent = (self.humidity_percent / f64::from(self.rows)).round() as u8;
+        m.co2_ppm = average(self.co2_ppm).map(|v| v.round() as u16);
+        m.light_level = average(self.light_level).map(|v| v.round() as u8);
+    }
+}
+
+/// A batch of measurements in which the rows of the same device and time are collapsed into one,
+/// since a single insert cannot write a row twice.
+///
+/// A row is held back until a row of its device at least [`WINDOW`] newer follows, so that the
+/// duplicates of a file sorted by time are all collapsed into it, whatever the batch size. A later
+/// duplicate of a row that was already sent is dropped with `First`, so that the first one is kept
+/// whatever the order the batches are committed in, and is an error with `Last` and `Average`,
+/// which cannot be guaranteed then.
+#[derive(Debug)]
+pub struct DedupBatch {
+    duplicates: Duplicates,
+    batch_size: usize,
+    measurements: Vec<Measurement>,
+    /// The record that each measurement was first parsed from.
+    records: Vec<usize>,
+    /// Where each device and time is in `measurements`, and its values so far with `Average`.
+    index: HashMap<Key, (usize, Sum)>,
+    /// How many measurements were held back by the last [`take_settled`](Self::take_settled).
+    held: usize,
+    /// The newest time of every device so far.
+    newest: HashMap<MacAddr6, DateTime<Utc>>,
+    /// The devices and times sent in earlier batches, up to [`SENT_WINDOW`] before the newest time
+    /// of their device.
+    sent: HashSet<Key>,
+}
+
+type Key = (MacAddr6, DateTime<Utc>);
+
+/// How far apart in time the duplicates of a row can be, given that a file is sorted by time.
+const WINDOW: TimeDelta = TimeDelta::hours(1);
+
+/// How long the rows that were sent are remembered, so that a late duplicate is dropped with
+/// `First`.
+const SENT_WINDOW: TimeDelta = TimeDelta::days(1);
+
+impl DedupBatch {
+    pub fn new(duplicates: Duplicates, batch_size: usize) -> Self {
+        Self {
+            duplicates,
+            batch_size,
+            measurements: Vec::with_capacity(batch_size),
+            records: Vec::with_capacity(batch_size),
+            index: HashMap::with_capacity(batch_size),
+            held: 0,
+            newest: HashMap::new(),
+            sent: HashSet::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.measurements.is_empty()
+    }
+
+    /// Whether the batch has `batch_size` measurements on top of those held back last time.
+    pub fn is_full(&self) -> bool {
+        self.measurements.len() >= self.held + self.batch_size
+    }
+
+    /// Adds `m`, parsed from the `record`th record, or collapses it into the measurement of the
+    /// same device and time. Returns whether it was a duplicate.
+    pub fn push(&mut self, m: Measurement, record: usize) -> Result<bool> {
+        let key = (m.device_id, m.measured_at);
+        let newest = self
+            .newest
+            .entry(m.device_id)
+            .and_modify(|newest| *newest = (*newest).max(m.measured_at))
+            .or_insert(m.measured_at);
+        if self.duplicates == Duplicates::First && self.sent.contains(&key) {
+            return Ok(true);
+        }
+        ensure!(
+            self.duplicates == Duplicates::First
+                || self.index.contains_key(&key)
+                || m.measured_at >= *newest - WINDOW,
+            "the row of {} at {} is more than {} older than a row before it, so its duplicates \
+             may already be inserted; --duplicates last and average need the rows of every device \
+             sorted by time",
+            m.device_id,
+            m.measured_at,
+            humantime::format_duration(WINDOW.to_std()?),
+        );
+
+        if let Some((index, sum)) = self.index.get_mut(&key) {
+            let existing = &mut self.measurements[*index];
+            match self.duplicates {
+                Duplicates::First => {}
+                Duplicates::Last => *existing = m,
+                Duplicates::Average => {
+                    sum.add(&m);
+                    sum.average_into(existing);
+                }
+            }
+            return Ok(true);
+        }
+
+        let mut sum = Sum::default();
+        if self.duplicates == Duplicates::Average {
+            sum.add(&m);
+        }
+        self.index.insert(key, (self.measurements.len(), sum));
+        self.measurements.push(m);
+        self.records.push(record);
+        Ok(false)
+    }
+
+    /// Takes the measurements that no later row can be a duplicate of, given that the file is
+    /// sorted by time, and holds back the others. Also returns the first record that a held back
+    /// measurement was parsed from, before which every record is taken.
+    pub fn take_settled(&mut self) -> (Vec<Measurement>, Option<usize>) {
+        let mut index = HashMap::with_capacity(self.index.len());
+        let mut held = Vec::new();
+        let mut held_records = Vec::new();
+        let mut settled = Vec::with_capacity(self.measurements.len());
+        let records = mem::take(&mut self.records);
+        for (m, record) in mem::take(&mut self.measurements).into_iter().zip(records) {
+            let key = (m.device_id, m.measured_at);
+            if m.measured_at < self.newest[&m.device_id] - WINDOW {
+                self.index.remove(&key);
+                self.sent.insert(key);
+                settled.push(m);
+            } else {
+                let (_, sum) = self.index.remove(&key).unwrap_or_default();
+                index.insert(key, (held.len(), sum));
+                held.push(m);
+                held_records.push(record);
+            }
+        }
+
+        let first_held = held_records.iter().min().copied();
+        self.held = held.len();
+        self.index = index;
+        self.measurements = held;
+        self.records = held_records;
+        self.forget_sent();
+
+        (settled, first_held)
+    }
+
+    /// Takes all the measurements, leaving the batch empty.
+    pub fn take(&mut self) -> Vec<Measurement> {
+        self.sent.extend(self.index.drain().map(|(key, _)| key));
+        self.records.clear();
+        self.held = 0;
+        self.forget_sent();
+        mem::take(&mut self.measurements)
+    }
+
+    fn forget_sent(&mut self) {
+        let newest = &self.newest;
+        self.sent
+            .retain(|(device_id, at)| *at >= newest[device_id] - SENT_WINDOW);
+    }
+}
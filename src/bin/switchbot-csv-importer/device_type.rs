@@ -0,0 +1,82 @@
+use home_environments::switchbot::DeviceType;
+use indicatif::ProgressBar;
+use tracing::warn;
+
+/// The words of the file names of the exports of each device type, as in `Meter_Plus_7A.csv` or
+/// `MeterPlus 7A.csv`, the most specific first. The app names the file after the device, whose name
+/// defaults to its model.
+const FILE_NAMES: [(DeviceType, &[&[&str]]); 8] = [
+    (
+        DeviceType::MeterProCO2,
+        &[&["co2"], &["meterproco2"], &["meterpro", "co2"]],
+    ),
+    (DeviceType::MeterPro, &[&["meter", "pro"], &["meterpro"]]),
+    (DeviceType::MeterPlus, &[&["meter", "plus"], &["meterplus"]]),
+    (DeviceType::WoIOSensor, &[&["outdoor"], &["woiosensor"]]),
+    (DeviceType::Hub3, &[&["hub", "3"], &["hub3"]]),
+    (DeviceType::Hub2, &[&["hub", "2"], &["hub2"]]),
+    (DeviceType::HubMini, &[&["hub", "mini"], &["hubmini"]]),
+    (DeviceType::Meter, &[&["meter"]]),
+];
+
+/// A device type told by an export, and what told it.
+#[derive(Debug, Clone, Copy)]
+pub struct Inferred {
+    pub device_type: DeviceType,
+    pub source: &'static str,
+}
+
+/// The lowercase words of the file name of the input named `name`, the entry for a file in a zip
+/// archive.
+pub fn file_name_words(name: &str) -> Vec<String> {
+    let file_name = name.rsplit(['/', '\\', ':']).next().unwrap_or(name);
+    let stem = file_name
+        .rsplit_once('.')
+        .map_or(file_name, |(stem, _)| stem);
+
+    stem.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// The device type that the file name of the input named `name` suggests.
+pub fn from_file_name(name: &str) -> Option<DeviceType> {
+    let words = file_name_words(name);
+    FILE_NAMES
+        .iter()
+        .find(|(_, names)| {
+            names.iter().any(|name| {
+                words
+                    .windows(name.len())
+                    .any(|window| window.iter().zip(name.iter()).all(|(a, b)| a == b))
+            })
+        })
+        .map(|(device_type, _)| *device_type)
+}
+
+/// Warns if the export named `name` looks like it is of another type of device than the one it is
+/// imported into, which is usually the wrong `--device-id`.
+pub fn check(
+    name: &str,
+    inferred: Option<Inferred>,
+    registered: Option<DeviceType>,
+    bar: &ProgressBar,
+) {
+    let (Some(inferred), Some(registered)) = (inferred, registered) else {
+        return;
+    };
+    if inferred.device_type == registered {
+        return;
+    }
+
+    bar.suspend(|| {
+        warn!(
+            file = name,
+            inferred = inferred.device_type.as_str(),
+            from = inferred.source,
+            registered = registered.as_str(),
+            "the file looks like an export of another type of device"
+        )
+    });
+}
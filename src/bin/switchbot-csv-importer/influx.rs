@@ -0,0 +1,218 @@
+use std::io::{BufRead, BufReader, Lines, Read};
+
+use anyhow::{Context as _, Result, bail};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use home_environments::switchbot::Measurement;
+use macaddr::MacAddr6;
+
+use crate::{csv::humidity, input::InvalidRecord};
+
+/// The unit of the timestamps of line protocol, nanoseconds unless the writer was told otherwise.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Precision {
+    #[default]
+    Ns,
+    Us,
+    Ms,
+    S,
+}
+
+impl Precision {
+    fn to_utc(self, timestamp: i64) -> Option<DateTime<Utc>> {
+        match self {
+            Precision::Ns => Some(DateTime::from_timestamp_nanos(timestamp)),
+            Precision::Us => DateTime::from_timestamp_micros(timestamp),
+            Precision::Ms => DateTime::from_timestamp_millis(timestamp),
+            Precision::S => DateTime::from_timestamp(timestamp, 0),
+        }
+    }
+}
+
+/// How to read InfluxDB line protocol.
+#[derive(Debug, Clone)]
+pub struct InfluxOptions {
+    /// The tag holding the MAC address of the device. Lines without it are attributed to
+    /// `--device-id`.
+    pub device_tag: String,
+    /// Only lines of this measurement are read, the others are skipped.
+    pub measurement: Option<String>,
+    pub precision: Precision,
+}
+
+/// Reads one point of InfluxDB line protocol per line, as written by Telegraf, skipping blank lines
+/// and comments. The fields are matched by name, such as `temperature` or `humidity_percent`.
+#[derive(Debug)]
+pub struct InfluxMeasurementIter<R> {
+    lines: Lines<BufReader<R>>,
+    line: usize,
+    device_id: Option<MacAddr6>,
+    options: InfluxOptions,
+}
+
+impl<R: Read> InfluxMeasurementIter<R> {
+    pub fn new(reader: R, device_id: Option<MacAddr6>, options: InfluxOptions) -> Self {
+        Self {
+            lines: BufReader::new(reader).lines(),
+            line: 0,
+            device_id,
+            options,
+        }
+    }
+
+    /// Parses a point, or returns `None` for one of another measurement.
+    fn parse(&self, line: &str) -> Result<Option<Measurement>> {
+        let sections = split(line, ' ', true);
+        let [series, fields, timestamp] = sections[..] else {
+            bail!("expected a measurement, fields and a timestamp");
+        };
+
+        let mut series = split(series, ',', false).into_iter();
+        let measurement = unescape(series.next().unwrap_or_default());
+        if self
+            .options
+            .measurement
+            .as_ref()
+            .is_some_and(|m| *m != measurement)
+        {
+            return Ok(None);
+        }
+
+        let mut device_id = self.device_id;
+        for tag in series {
+            let (key, value) = key_value(tag)?;
+            if key == self.options.device_tag {
+                device_id = Some(
+                    value
+                        .parse()
+                        .with_context(|| format!("invalid {key} tag: {value}"))?,
+                );
+            }
+        }
+        let device_id = device_id.with_context(|| {
+            format!(
+                "point has no {} tag and --device-id is not given",
+                self.options.device_tag
+            )
+        })?;
+
+        let measured_at = timestamp
+            .parse()
+            .ok()
+            .and_then(|timestamp| self.options.precision.to_utc(timestamp))
+            .with_context(|| format!("invalid timestamp: {timestamp}"))?;
+
+        let (mut temperature_celsius, mut humidity_percent) = (None, None);
+        let (mut co2_ppm, mut light_level, mut rssi_dbm) = (None, None, None);
+        for field in split(fields, ',', true) {
+            let (key, value) = key_value(field)?;
+            let number = || -> Result<f64> {
+                value
+                    .trim_end_matches(['i', 'u'])
+                    .parse()
+                    .with_context(|| format!("invalid {key} field: {value}"))
+            };
+            match key.as_str() {
+                "temperature" | "temperature_celsius" | "temp" => {
+                    temperature_celsius = Some(number()? as f32);
+                }
+                "humidity" | "humidity_percent" | "humi" => {
+                    humidity_percent = Some(humidity(number()? as f32)?);
+                }
+                "co2" | "co2_ppm" => co2_ppm = Some(number()? as u16),
+                "light" | "light_level" => light_level = Some(number()? as u8),
+                "rssi" | "rssi_dbm" => rssi_dbm = Some(number()? as i16),
+                // Such as the battery level.
+                _ => {}
+            }
+        }
+
+        Ok(Some(Measurement {
+            device_id,
+            measured_at,
+            temperature_celsius: temperature_celsius.context("point has no temperature field")?,
+            humidity_percent: humidity_percent.context("point has no humidity field")?,
+            co2_ppm,
+            light_level,
+            rssi_dbm,
+            source: None,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for InfluxMeasurementIter<R> {
+    type Item = Result<Measurement>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.line += 1;
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e.into())),
+            };
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+
+            match self.parse(trimmed) {
+                Ok(Some(measurement)) => return Some(Ok(measurement)),
+                Ok(None) => continue,
+                Err(e) => {
+                    return Some(Err(e.context(InvalidRecord {
+                        line: self.line as _,
+                        record: line,
+                    })));
+                }
+            }
+        }
+    }
+}
+
+/// Splits `s` at the `delimiter`s that are neither escaped with a backslash nor, if `quotes`, in a
+/// double-quoted string field.
+fn split(s: &str, delimiter: char, quotes: bool) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut start, mut escaped, mut quoted) = (0, false, false);
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' if quotes => quoted = !quoted,
+            _ if c == delimiter && !quoted => {
+                parts.push(&s[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&s[start..]);
+
+    parts
+}
+
+/// Splits a tag or a field at its `=`, unescaping the key and the value.
+fn key_value(s: &str) -> Result<(String, String)> {
+    let [key, value] = split(s, '=', true)[..] else {
+        bail!("expected key=value: {s}");
+    };
+    let value = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value);
+
+    Ok((unescape(key), unescape(value)))
+}
+
+fn unescape(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            _ => unescaped.push(c),
+        }
+    }
+
+    unescaped
+}
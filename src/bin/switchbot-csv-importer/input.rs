@@ -0,0 +1,262 @@
+use std::{
+    collections::HashSet,
+    fmt,
+    fs::File,
+    io::{self, BufReader, Cursor, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context as _, Result, bail};
+use clap::ValueEnum;
+use flate2::read::MultiGzDecoder;
+use home_environments::switchbot::Measurement;
+use indicatif::ProgressBar;
+use zip::ZipArchive;
+
+use crate::{
+    csv::{CsvMeasurementIter, ParseOptions},
+    device_type::Inferred,
+    influx::InfluxMeasurementIter,
+    ndjson::NdjsonMeasurementIter,
+};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+const ZIP_MAGIC: [u8; 4] = [b'P', b'K', 0x03, 0x04];
+
+const STDIN: &str = "-";
+
+/// How the standard input is named in logs.
+pub const STDIN_NAME: &str = "stdin";
+
+/// The entries of a zip archive that are imported.
+const EXTENSIONS: [&str; 4] = [".csv", ".ndjson", ".jsonl", ".lp"];
+
+/// The format of the files, the same for all of them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// A CSV export, whose layout is detected from the header.
+    #[default]
+    Csv,
+    /// One JSON measurement per line, as written by the ndjson output of ble-ingester.
+    Ndjson,
+    /// InfluxDB line protocol, such as a backup of the points written by Telegraf.
+    InfluxLp,
+}
+
+/// Context of the error of a record, keeping the record itself to be written to `--errors-file`.
+#[derive(Debug)]
+pub struct InvalidRecord {
+    pub line: u64,
+    pub record: String,
+}
+
+impl fmt::Display for InvalidRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid record on line {}", self.line)
+    }
+}
+
+pub type Measurements = Box<dyn Iterator<Item = Result<Measurement>> + Send>;
+
+/// Reads the measurements of `reader`, the input named `name`, in `format`. Also returns the type
+/// of device that a CSV export looks like it is of.
+pub fn measurements(
+    reader: Box<dyn Read + Send>,
+    name: &str,
+    format: Format,
+    options: &ParseOptions,
+) -> Result<(Measurements, Option<Inferred>)> {
+    match format {
+        Format::Csv => {
+            let measurements = CsvMeasurementIter::new(reader, name, options)
+                .context("failed to create CSV measurement iterator")?;
+            let device_type = measurements.device_type();
+            Ok((Box::new(measurements), device_type))
+        }
+        Format::Ndjson => Ok((
+            Box::new(NdjsonMeasurementIter::new(reader, options.device_id)),
+            None,
+        )),
+        Format::InfluxLp => Ok((
+            Box::new(InfluxMeasurementIter::new(
+                reader,
+                options.device_id,
+                options.influx.clone(),
+            )),
+            None,
+        )),
+    }
+}
+
+/// A CSV export, decompressed on the fly.
+pub struct Input {
+    /// The path, followed by the entry for files in a zip archive.
+    pub name: String,
+    pub reader: Box<dyn Read + Send>,
+}
+
+/// Expands the `--file` arguments into the files to import. Glob patterns such as
+/// `exports/Meter_*.csv` are expanded here rather than by the shell, so they also work on Windows,
+/// and their matches are sorted by path; the files are otherwise imported in the given order, once
+/// each.
+pub fn expand(patterns: &[String]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for pattern in patterns {
+        if !is_glob(pattern) {
+            files.push(PathBuf::from(pattern));
+            continue;
+        }
+
+        let mut matches = glob::glob(pattern)
+            .with_context(|| format!("invalid glob pattern: {pattern}"))?
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("failed to expand glob pattern: {pattern}"))?;
+        if matches.is_empty() {
+            bail!("no files match {pattern}");
+        }
+        matches.sort();
+        files.extend(matches);
+    }
+
+    let mut seen = HashSet::new();
+    files.retain(|file| seen.insert(file.clone()));
+
+    Ok(files)
+}
+
+/// Whether `path` is `-`, which stands for the standard input, as in
+/// `unzip -p export.zip | switchbot-csv-importer --file -`.
+pub fn is_stdin(path: &Path) -> bool {
+    path == Path::new(STDIN)
+}
+
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+/// Opens `path` as a plain CSV file, a gzipped one, or a zip archive such as those shared from the
+/// SwitchBot app, telling them apart by their content rather than the extension. A zip archive
+/// yields every CSV or NDJSON file in it, sorted by name. `progress` advances with the bytes read from the
+/// file, or from the extracted CSV files of a zip archive.
+pub fn open(path: &Path, progress: &ProgressBar) -> Result<Vec<Input>> {
+    if is_stdin(path) {
+        return open_stdin(progress);
+    }
+
+    let mut file = File::open(path).with_context(|| format!("failed to open file: {path:?}"))?;
+
+    let mut magic = Vec::with_capacity(ZIP_MAGIC.len());
+    (&mut file)
+        .take(ZIP_MAGIC.len() as u64)
+        .read_to_end(&mut magic)
+        .with_context(|| format!("failed to read file: {path:?}"))?;
+    file.seek(SeekFrom::Start(0))
+        .context("failed to seek to start of file")?;
+
+    let name = path.display().to_string();
+    if magic.starts_with(&ZIP_MAGIC) {
+        return open_zip(&name, file, progress);
+    }
+
+    let len = file
+        .metadata()
+        .with_context(|| format!("failed to read metadata: {path:?}"))?
+        .len();
+    progress.set_length(len);
+    let file = progress.wrap_read(file);
+
+    if magic.starts_with(&GZIP_MAGIC) {
+        return Ok(vec![Input {
+            name,
+            reader: Box::new(MultiGzDecoder::new(BufReader::new(file))),
+        }]);
+    }
+
+    Ok(vec![Input {
+        name,
+        reader: Box::new(file),
+    }])
+}
+
+/// Like [`open`], but for the standard input, whose length is unknown. A zip archive has to be
+/// read into memory first, since it cannot be read as a stream.
+fn open_stdin(progress: &ProgressBar) -> Result<Vec<Input>> {
+    let mut stdin = io::stdin();
+
+    let mut magic = Vec::with_capacity(ZIP_MAGIC.len());
+    (&mut stdin)
+        .take(ZIP_MAGIC.len() as u64)
+        .read_to_end(&mut magic)
+        .context("failed to read standard input")?;
+
+    let name = STDIN_NAME.to_string();
+    let reader = Cursor::new(magic.clone()).chain(stdin);
+    if magic.starts_with(&ZIP_MAGIC) {
+        let mut content = Vec::new();
+        BufReader::new(reader)
+            .read_to_end(&mut content)
+            .context("failed to read standard input")?;
+        return open_zip(&name, Cursor::new(content), progress);
+    }
+
+    let reader = progress.wrap_read(reader);
+    if magic.starts_with(&GZIP_MAGIC) {
+        return Ok(vec![Input {
+            name,
+            reader: Box::new(MultiGzDecoder::new(BufReader::new(reader))),
+        }]);
+    }
+
+    Ok(vec![Input {
+        name,
+        reader: Box::new(reader),
+    }])
+}
+
+/// Reads the CSV files of a zip archive into memory, since its entries cannot outlive it.
+fn open_zip(name: &str, file: impl Read + Seek, progress: &ProgressBar) -> Result<Vec<Input>> {
+    let mut archive =
+        ZipArchive::new(BufReader::new(file)).context("failed to read zip archive")?;
+
+    let mut entries: Vec<String> = archive
+        .file_names()
+        .filter(|entry| {
+            let entry = entry.to_lowercase();
+            EXTENSIONS
+                .iter()
+                .any(|extension| entry.ends_with(extension))
+        })
+        .map(str::to_string)
+        .collect();
+    if entries.is_empty() {
+        bail!("zip archive contains no CSV or NDJSON files: {name}");
+    }
+    entries.sort();
+
+    let contents = entries
+        .into_iter()
+        .map(|entry| {
+            let mut content = Vec::new();
+            archive
+                .by_name(&entry)
+                .and_then(|mut file| Ok(file.read_to_end(&mut content)?))
+                .with_context(|| format!("failed to extract {entry} from {name}"))?;
+            Ok((entry, content))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    progress.set_length(
+        contents
+            .iter()
+            .map(|(_, content)| content.len() as u64)
+            .sum(),
+    );
+    Ok(contents
+        .into_iter()
+        .map(|(entry, content)| Input {
+            name: format!("{name}:{entry}"),
+            reader: Box::new(progress.wrap_read(Cursor::new(content))),
+        })
+        .collect())
+}
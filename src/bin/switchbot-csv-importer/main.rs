@@ -1,65 +1,637 @@
 mod args;
+mod checkpoint;
 mod csv;
+mod dedup;
+mod device_type;
+mod influx;
+mod input;
+mod ndjson;
+mod plausibility;
+mod progress;
+mod range;
+mod rejects;
+mod summary;
+mod timezone;
+mod validate;
 
-use std::{fs::File, process::ExitCode};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
 
-use anyhow::Context as _;
+use anyhow::{Context as _, bail};
 use args::Args;
 use clap::Parser as _;
-use home_environments::db::bulk_insert_switchbot_measurements;
-use sqlx::postgres::PgPoolOptions;
+use home_environments::{
+    db::{
+        count_existing_switchbot_measurements, get_switchbot_devices,
+        insert_switchbot_measurements, update_switchbot_measurements_derived_metrics,
+    },
+    logging,
+    switchbot::{Device, Measurement},
+};
+use sqlx::{PgConnection, PgPool, postgres::PgPoolOptions};
+use tokio::{
+    sync::mpsc,
+    task::{self, JoinSet},
+};
+use tracing::{error, info, warn};
 
-use crate::csv::CsvMeasurementIter;
-
-const BULK_INSERT_SIZE: usize = 1000;
+use crate::{
+    checkpoint::Checkpoint,
+    csv::ParseOptions,
+    dedup::DedupBatch,
+    influx::InfluxOptions,
+    input::{Format, Input, Measurements},
+    plausibility::{Implausible, Plausibility, PlausibilityOptions, Warnings},
+    progress::Progress,
+    range::TimeRange,
+    rejects::{OnError, Rejects},
+    summary::{Counts, Stats, Summary},
+    timezone::FileTimezone,
+    validate::{Report, is_read_error, validate},
+};
 
 #[tokio::main]
 async fn main() -> ExitCode {
-    if let Err(e) = run().await {
-        eprintln!("{e:#}");
+    let args = Args::parse();
+
+    if let Err(e) = logging::init(
+        args.log_level.as_deref(),
+        args.log_format.unwrap_or_default(),
+    ) {
+        eprintln!("failed to initialize logging: {e:#}");
+        return ExitCode::from(1);
+    }
+
+    if let Err(e) = run(args).await {
+        error!("{e:#}");
         return ExitCode::from(1);
     }
 
     ExitCode::from(0)
 }
 
-async fn run() -> anyhow::Result<()> {
-    let args = Args::parse();
+async fn run(mut args: Args) -> anyhow::Result<()> {
+    let files = input::expand(&args.files)?;
+    if args.jobs == 0 {
+        bail!("--jobs must be greater than zero");
+    }
+    if args.batch_size == 0 {
+        bail!("--batch-size must be greater than zero");
+    }
+    if args.checkpoint.is_some() && files.iter().any(|file| input::is_stdin(file)) {
+        bail!("--checkpoint cannot be used with the standard input");
+    }
+    if args.format == Format::Csv && args.device_id.is_none() && args.device_name.is_none() {
+        bail!("--device-id or --device-name is required for CSV files");
+    }
+    for file in &files {
+        // Also checks that `--from` and `--to` resolve.
+        let options = parse_options(&args, file)?;
+        if args.format == Format::Csv && options.timezone.is_none() {
+            bail!(
+                "--timezone is required for CSV files, such as {}",
+                file.display()
+            );
+        }
+    }
+
+    let pool = match &args.database_url {
+        Some(database_url)
+            if !args.dry_run || args.check_existing || args.device_name.is_some() =>
+        {
+            Some(
+                PgPoolOptions::new()
+                    .connect(database_url)
+                    .await
+                    .context("failed to connect to database")?,
+            )
+        }
+        _ if args.check_existing => bail!("--check-existing needs --database-url"),
+        _ if args.device_name.is_some() => bail!("--device-name needs --database-url"),
+        _ => None,
+    };
+    let device = match (&pool, &args.device_name, args.device_id) {
+        (Some(pool), Some(name), _) => Some(find_device(pool, name).await?),
+        (Some(pool), None, Some(device_id)) => get_switchbot_devices(pool)
+            .await
+            .context("failed to get SwitchBot devices")?
+            .into_iter()
+            .find(|device| device.id == device_id),
+        _ => None,
+    };
+    if let Some(device) = device {
+        args.device_id = Some(device.id);
+        args.device_type = Some(device.r#type);
+    }
+
+    if args.dry_run {
+        let pool = pool.as_ref().filter(|_| args.check_existing);
+        return dry_run(&args, pool, &files).await;
+    }
+    let pool = pool.context("--database-url is required")?;
+    let mut checkpoint = match &args.checkpoint {
+        Some(path) => Some(Checkpoint::load(path.clone(), args.resume).await?),
+        None => None,
+    };
+
+    let reports = Arc::new(Mutex::new(Reports {
+        rejects: (args.on_error == OnError::Skip).then(|| Rejects::new(args.errors_file.clone())),
+        warnings: Warnings::new(args.warnings_file.clone()),
+    }));
 
-    let file =
-        File::open(&args.file).with_context(|| format!("failed to open file: {:?}", args.file))?;
-    let iter = CsvMeasurementIter::new(file, args.device_id, args.timezone)
-        .context("failed to create CSV measurement iterator")?;
+    let started_at = Instant::now();
+    let mut total = Counts::default();
+    let mut stats = Stats::new(args.min_gap);
+    for file in &files {
+        let sha256 = match checkpoint {
+            Some(_) => Some(checkpoint::sha256(file)?),
+            None => None,
+        };
+        let mut progress = Progress::new(file, args.quiet)?;
+        for input in input::open(file, progress.bar())? {
+            let name = input.name.clone();
+            if let (Some(checkpoint), Some(sha256)) = (&mut checkpoint, &sha256)
+                && checkpoint.start(&name, sha256)?.done
+            {
+                progress
+                    .bar()
+                    .suspend(|| info!(file = name, "already imported, skipping"));
+                continue;
+            }
+
+            let (counts, file_stats) = import(
+                &pool,
+                input,
+                &args,
+                parse_options(&args, file)?,
+                checkpoint.as_mut(),
+                reports.clone(),
+                &mut progress,
+            )
+            .await
+            .with_context(|| format!("failed to import {name}"))?;
+            total.add(counts);
+            stats.merge(file_stats);
+        }
+
+        progress.finish();
+    }
+    if let Some(checkpoint) = checkpoint {
+        checkpoint.remove().await?;
+    }
+
+    let elapsed = started_at.elapsed();
+    let summary = Summary::new(files.len(), total, stats, elapsed);
+    info!(
+        files = summary.files,
+        rows = summary.rows,
+        written = summary.written,
+        skipped = summary.skipped,
+        existing = summary.existing,
+        rejected = summary.rejected,
+        duplicates = summary.duplicates,
+        implausible = summary.implausible,
+        out_of_range = summary.out_of_range,
+        first = summary.first.map(|t| t.to_rfc3339()),
+        last = summary.last.map(|t| t.to_rfc3339()),
+        temperature_min = summary.temperature_celsius.map(|t| t.min),
+        temperature_max = summary.temperature_celsius.map(|t| t.max),
+        humidity_min = summary.humidity_percent.map(|h| h.min),
+        humidity_max = summary.humidity_percent.map(|h| h.max),
+        gaps = summary.gaps.len(),
+        rows_per_sec = summary.rows_per_sec,
+        ?elapsed,
+        "imported all files"
+    );
+    for gap in summary.longest_gaps() {
+        info!(
+            device_id = gap.device_id,
+            from = gap.from.to_rfc3339(),
+            to = gap.to.to_rfc3339(),
+            duration = %humantime::format_duration(gap.duration().to_std().unwrap_or_default()),
+            "gap in the measurements"
+        );
+    }
+    let reports = reports.lock().unwrap();
+    if let Some(rejects) = &reports.rejects
+        && rejects.count() > 0
+    {
+        warn!(
+            rejected = rejects.count(),
+            errors_file = %rejects.path().display(),
+            "skipped records that failed to parse"
+        );
+    }
+    if reports.warnings.count() > 0 {
+        warn!(
+            implausible = reports.warnings.count(),
+            warnings_file = %reports.warnings.path().display(),
+            "imported implausible records"
+        );
+    }
+    if args.summary_json {
+        println!("{}", serde_json::to_string(&summary)?);
+    }
+
+    Ok(())
+}
+
+async fn dry_run(args: &Args, pool: Option<&PgPool>, files: &[PathBuf]) -> anyhow::Result<()> {
+    let mut total = Report::default();
+    for file in files {
+        let mut progress = Progress::new(file, args.quiet)?;
+        for input in input::open(file, progress.bar())? {
+            let name = input.name.clone();
+            let options = parse_options(args, file)?;
+            let mut report = input::measurements(input.reader, &name, args.format, &options)
+                .and_then(|(measurements, device_type)| {
+                    device_type::check(&name, device_type, args.device_type, progress.bar());
+                    validate(&name, measurements, &options, pool.is_some(), &mut progress)
+                })
+                .with_context(|| format!("failed to validate {name}"))?;
+            if let Some(pool) = pool {
+                let mut existing = 0;
+                for keys in report.keys.chunks(args.batch_size) {
+                    existing += count_existing_switchbot_measurements(pool, keys).await?;
+                }
+                report.existing = Some(existing);
+            }
+            progress.bar().suspend(|| {
+                info!(
+                    rows = report.rows,
+                    errors = report.errors,
+                    duplicates = report.duplicates,
+                    implausible = report.implausible,
+                    out_of_range = report.out_of_range,
+                    existing = report.existing,
+                    first = report.first.map(|t| t.to_rfc3339()),
+                    last = report.last.map(|t| t.to_rfc3339()),
+                    file = name,
+                    "validated records"
+                )
+            });
+            total.merge(report);
+        }
+        progress.finish();
+    }
+
+    info!(
+        files = files.len(),
+        rows = total.rows,
+        errors = total.errors,
+        duplicates = total.duplicates,
+        implausible = total.implausible,
+        out_of_range = total.out_of_range,
+        existing = total.existing,
+        first = total.first.map(|t| t.to_rfc3339()),
+        last = total.last.map(|t| t.to_rfc3339()),
+        "validated all files, nothing was inserted"
+    );
+    if total.errors > 0 {
+        bail!("{} records failed to parse", total.errors);
+    }
 
-    let pool = PgPoolOptions::new()
-        .connect(&args.database_url)
+    Ok(())
+}
+
+/// Looks up the device named `name`, ignoring case, and lists the known devices if there is none.
+async fn find_device(pool: &PgPool, name: &str) -> anyhow::Result<Device> {
+    let devices = get_switchbot_devices(pool)
         .await
-        .context("failed to connect to database")?;
+        .context("failed to get SwitchBot devices")?;
+    let mut matches = devices
+        .iter()
+        .filter(|device| device.name.trim().eq_ignore_ascii_case(name.trim()));
+    match (matches.next(), matches.next()) {
+        (Some(device), None) => {
+            info!(device_id = %device.id, name = device.name, "found device");
+            Ok(device.clone())
+        }
+        (Some(_), Some(_)) => bail!("several devices are named {name:?}, use --device-id instead"),
+        (None, _) if devices.is_empty() => bail!("no device is named {name:?}, none are known"),
+        (None, _) => {
+            let known = devices
+                .iter()
+                .map(|device| format!("{:?} ({})", device.name, device.id))
+                .collect::<Vec<_>>()
+                .join(", ");
+            bail!("no device is named {name:?}, known devices are: {known}")
+        }
+    }
+}
 
-    let mut buffer = Vec::with_capacity(BULK_INSERT_SIZE);
-    let mut total = 0;
+/// How to read `file`, in its own timezone if `--file-timezone` gives one.
+fn parse_options(args: &Args, file: &Path) -> anyhow::Result<ParseOptions> {
+    let timezone = FileTimezone::find(&args.file_timezones, file).or(args.timezone);
+    Ok(ParseOptions {
+        device_id: args.device_id,
+        timezone,
+        range: TimeRange::new(args.from, args.to, timezone)?,
+        duplicates: args.duplicates,
+        plausibility: PlausibilityOptions {
+            max_temperature_jump: args.max_temperature_jump,
+            strict: args.strict,
+        },
+        ambiguous_time: args.ambiguous_time,
+        temperature_unit: args.temperature_unit,
+        columns: args.map,
+        timestamp_format: args.timestamp_format.clone(),
+        influx: InfluxOptions {
+            device_tag: args.device_tag.clone(),
+            measurement: args.measurement.clone(),
+            precision: args.precision,
+        },
+    })
+}
+
+/// Imports `input`, skipping the records that the checkpoint records as committed by an
+/// interrupted run. The records are parsed on a blocking thread while up to `--jobs` batches are inserted
+/// at once, or one after another in a single transaction with `--atomic`.
+async fn import(
+    pool: &PgPool,
+    input: Input,
+    args: &Args,
+    options: ParseOptions,
+    mut checkpoint: Option<&mut Checkpoint>,
+    reports: Arc<Mutex<Reports>>,
+    progress: &mut Progress,
+) -> anyhow::Result<(Counts, Stats)> {
+    let resume_from = checkpoint
+        .as_ref()
+        .map_or(0, |checkpoint| checkpoint.committed(&input.name));
+    if resume_from > 0 {
+        progress.bar().suspend(|| {
+            info!(
+                rows = resume_from,
+                file = input.name,
+                "resuming after the committed records"
+            )
+        });
+    }
 
-    for result in iter {
-        let record = result.context("failed to parse CSV record")?;
-        buffer.push(record);
+    let (batches_tx, mut batches_rx) = mpsc::channel(args.jobs);
+    let (format, batch_size, min_gap) = (args.format, args.batch_size, args.min_gap);
+    let registered_type = args.device_type;
+    let name = input.name.clone();
+    let bar = progress.bar().clone();
+    let parser = task::spawn_blocking(move || {
+        let (measurements, device_type) =
+            input::measurements(input.reader, &name, format, &options)?;
+        device_type::check(&name, device_type, registered_type, &bar);
+        parse(
+            Box::new(measurements.skip(resume_from)),
+            &options,
+            batch_size,
+            &name,
+            &mut reports.lock().unwrap(),
+            Stats::new(min_gap),
+            batches_tx,
+        )
+    });
 
-        if buffer.len() >= BULK_INSERT_SIZE {
-            bulk_insert_switchbot_measurements(&pool, &buffer)
+    // Nothing of the input is committed, nor recorded in the checkpoint, unless all of it is.
+    let mut tx = match args.atomic {
+        true => Some(pool.begin().await.context("failed to begin transaction")?),
+        false => None,
+    };
+    let mut counts = Counts::default();
+    if let Some(tx) = &mut tx {
+        while let Some(Batch {
+            measurements: batch,
+            ..
+        }) = batches_rx.recv().await
+        {
+            counts.add(
+                insert(
+                    pool,
+                    Some(tx),
+                    &batch,
+                    args.check_existing,
+                    args.derived_metrics,
+                )
                 .await
-                .context("failed to bulk insert measurements")?;
-            total += buffer.len();
-            buffer.clear();
+                .context("failed to bulk insert measurements")?,
+            );
+            progress.add_rows(batch.len());
         }
     }
 
-    if !buffer.is_empty() {
-        bulk_insert_switchbot_measurements(&pool, &buffer)
-            .await
-            .context("failed to bulk insert remaining measurements")?;
-        total += buffer.len();
+    let mut inserts = JoinSet::new();
+    // With `--atomic`, every batch is already inserted.
+    let mut receiving = tx.is_none();
+    let mut next_batch = 0;
+    // Batches can finish out of order, but the checkpoint only covers those committed without a
+    // gap before them.
+    let mut finished = BTreeMap::new();
+    let mut next_committed = 0;
+    let mut committed = resume_from;
+
+    while receiving || !inserts.is_empty() {
+        tokio::select! {
+            batch = batches_rx.recv(), if receiving && inserts.len() < args.jobs => {
+                let Some(Batch { measurements: batch, records }) = batch else {
+                    receiving = false;
+                    continue;
+                };
+                let pool = pool.clone();
+                let (check_existing, derived_metrics) = (args.check_existing, args.derived_metrics);
+                let index = next_batch;
+                next_batch += 1;
+                inserts.spawn(async move {
+                    let counts = insert(&pool, None, &batch, check_existing, derived_metrics).await;
+                    (index, batch.len(), records, counts)
+                });
+            }
+            Some(joined) = inserts.join_next() => {
+                let (index, len, records, result) = joined.context("insert task panicked")?;
+                counts.add(result.context("failed to bulk insert measurements")?);
+                progress.add_rows(len);
+
+                finished.insert(index, records);
+                let before = committed;
+                while let Some(records) = finished.remove(&next_committed) {
+                    committed += records;
+                    next_committed += 1;
+                }
+                if let Some(checkpoint) = &mut checkpoint
+                    && committed > before
+                {
+                    checkpoint.commit(&input.name, committed).await?;
+                }
+            }
+        }
     }
 
-    println!("Inserted {} records from {:?}", total, args.file);
+    let stats = parser.await.context("parser task panicked")??;
+    if let Some(tx) = tx {
+        tx.commit().await.context("failed to commit transaction")?;
+    }
+    if let Some(checkpoint) = checkpoint {
+        checkpoint.finish(&input.name).await?;
+    }
 
-    Ok(())
+    progress.bar().suspend(|| {
+        info!(
+            sent = counts.sent,
+            written = counts.written,
+            skipped = counts.sent - counts.written,
+            existing = counts.existing,
+            rejected = stats.rejected,
+            duplicates = stats.duplicates,
+            implausible = stats.implausible,
+            out_of_range = stats.out_of_range,
+            file = input.name,
+            "inserted records"
+        )
+    });
+
+    Ok((counts, stats))
+}
+
+/// Measurements to insert at once.
+struct Batch {
+    measurements: Vec<Measurement>,
+    /// The records parsed for the batch, counting those that were skipped, so that the checkpoint
+    /// can point past them.
+    records: usize,
+}
+
+/// The files that the records of every input are written to when they are skipped or imported
+/// with a warning.
+struct Reports {
+    /// With `--on-error skip`.
+    rejects: Option<Rejects>,
+    warnings: Warnings,
+}
+
+/// Parses the records of the input named `name` into batches of `batch_size`. Stops at the
+/// first invalid record, after sending the batches before it, unless `reports` has a file for
+/// rejected records. Returns `stats` with the parsed records added.
+fn parse(
+    measurements: Measurements,
+    options: &ParseOptions,
+    batch_size: usize,
+    name: &str,
+    reports: &mut Reports,
+    mut stats: Stats,
+    batches: mpsc::Sender<Batch>,
+) -> anyhow::Result<Stats> {
+    let mut batch = DedupBatch::new(options.duplicates, batch_size);
+    let mut plausibility = Plausibility::new(options.plausibility);
+    // The records parsed, and those covered by the batches sent.
+    let (mut records, mut sent) = (0, 0);
+    for result in measurements {
+        let result = match result {
+            Ok(record) if options.range.contains(record.measured_at) => {
+                match plausibility.check(&record) {
+                    Ok(Some(implausible)) => {
+                        reports.warnings.add(name, &implausible)?;
+                        stats.implausible += 1;
+                        Ok(record)
+                    }
+                    Ok(None) => Ok(record),
+                    Err(implausible) => Err(implausible.into()),
+                }
+            }
+            result => result,
+        };
+
+        match (result, &mut reports.rejects) {
+            (Ok(record), _) if !options.range.contains(record.measured_at) => {
+                stats.out_of_range += 1;
+            }
+            (Ok(record), _) => {
+                stats.add(&record);
+                if batch.push(record, records)? {
+                    stats.duplicates += 1;
+                }
+                if batch.is_full() {
+                    let (measurements, first_held) = batch.take_settled();
+                    // The checkpoint must not skip the records of the measurements held back.
+                    let settled = first_held.unwrap_or(records + 1);
+                    if !measurements.is_empty() {
+                        let full = Batch {
+                            measurements,
+                            records: settled - sent,
+                        };
+                        sent = settled;
+                        if batches.blocking_send(full).is_err() {
+                            // The import failed and stopped receiving.
+                            return Ok(stats);
+                        }
+                    }
+                }
+            }
+            (Err(err), Some(rejects)) if !is_read_error(&err) => {
+                rejects.add(name, &err)?;
+                stats.rejected += 1;
+            }
+            (Err(err), None) if err.is::<Implausible>() => return Err(err),
+            (Err(err), _) => return Err(err.context("failed to parse record")),
+        }
+        records += 1;
+    }
+
+    if !batch.is_empty() {
+        let _ = batches.blocking_send(Batch {
+            measurements: batch.take(),
+            records: records - sent,
+        });
+    }
+
+    Ok(stats)
+}
+
+/// Inserts `measurements` in `tx` if given, or in a transaction of their own.
+async fn insert(
+    pool: &PgPool,
+    tx: Option<&mut PgConnection>,
+    measurements: &[Measurement],
+    check_existing: bool,
+    derived_metrics: bool,
+) -> anyhow::Result<Counts> {
+    let existing = if check_existing {
+        let keys: Vec<_> = measurements
+            .iter()
+            .map(|m| (m.device_id, m.measured_at))
+            .collect();
+        Some(count_existing_switchbot_measurements(pool, &keys).await?)
+    } else {
+        None
+    };
+    let written = match tx {
+        Some(tx) => write(tx, measurements, derived_metrics).await?,
+        None => {
+            let mut tx = pool.begin().await.context("failed to begin transaction")?;
+            let written = write(&mut tx, measurements, derived_metrics).await?;
+            tx.commit().await.context("failed to commit transaction")?;
+            written
+        }
+    };
+
+    Ok(Counts {
+        sent: measurements.len() as _,
+        written,
+        existing,
+    })
+}
+
+/// Inserts `measurements`, then stores their dew point and absolute humidity if `derived_metrics`.
+/// Returns how many rows were written.
+async fn write(
+    conn: &mut PgConnection,
+    measurements: &[Measurement],
+    derived_metrics: bool,
+) -> anyhow::Result<u64> {
+    let written = insert_switchbot_measurements(conn, measurements).await?;
+    if derived_metrics {
+        update_switchbot_measurements_derived_metrics(conn, measurements).await?;
+    }
+
+    Ok(written)
 }
@@ -0,0 +1,87 @@
+use std::io::{BufRead, BufReader, Lines, Read};
+
+use anyhow::{Context as _, Result};
+use chrono::{DateTime, FixedOffset, Utc};
+use home_environments::switchbot::Measurement;
+use macaddr::MacAddr6;
+use serde::Deserialize;
+
+use crate::input::InvalidRecord;
+
+/// A measurement as written by the ndjson output of ble-ingester, or by other systems using the
+/// same field names. Its time carries an offset, so `--timezone` does not apply.
+#[derive(Debug, Deserialize)]
+struct NdjsonMeasurement {
+    device_id: Option<String>,
+    measured_at: DateTime<FixedOffset>,
+    temperature_celsius: f32,
+    humidity_percent: u8,
+    co2_ppm: Option<u16>,
+    light_level: Option<u8>,
+    rssi_dbm: Option<i16>,
+    source: Option<String>,
+}
+
+/// Reads one JSON measurement per line, skipping blank lines. A line without a `device_id` is
+/// attributed to `device_id`.
+#[derive(Debug)]
+pub struct NdjsonMeasurementIter<R> {
+    lines: Lines<BufReader<R>>,
+    line: usize,
+    device_id: Option<MacAddr6>,
+}
+
+impl<R: Read> NdjsonMeasurementIter<R> {
+    pub fn new(reader: R, device_id: Option<MacAddr6>) -> Self {
+        Self {
+            lines: BufReader::new(reader).lines(),
+            line: 0,
+            device_id,
+        }
+    }
+}
+
+impl<R: Read> Iterator for NdjsonMeasurementIter<R> {
+    type Item = Result<Measurement>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let line = loop {
+            self.line += 1;
+            match self.lines.next()? {
+                Ok(line) if line.trim().is_empty() => continue,
+                Ok(line) => break line,
+                Err(e) => return Some(Err(e.into())),
+            }
+        };
+
+        let record = (|| -> Result<Measurement> {
+            let m: NdjsonMeasurement =
+                serde_json::from_str(&line).context("failed to parse JSON")?;
+            let device_id = match m.device_id {
+                Some(device_id) => device_id
+                    .parse()
+                    .with_context(|| format!("invalid device_id: {device_id}"))?,
+                None => self
+                    .device_id
+                    .context("record has no device_id and --device-id is not given")?,
+            };
+
+            Ok(Measurement {
+                device_id,
+                measured_at: m.measured_at.with_timezone(&Utc),
+                temperature_celsius: m.temperature_celsius,
+                humidity_percent: m.humidity_percent,
+                co2_ppm: m.co2_ppm,
+                light_level: m.light_level,
+                rssi_dbm: m.rssi_dbm,
+                source: m.source,
+            })
+        })()
+        .with_context(|| InvalidRecord {
+            line: self.line as _,
+            record: line.clone(),
+        });
+
+        Some(record)
+    }
+}
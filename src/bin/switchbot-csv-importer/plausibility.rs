@@ -0,0 +1,181 @@
+use std::{collections::HashMap, fmt, fs::File, path::PathBuf};
+
+use anyhow::{Context as _, Result};
+use chrono::{DateTime, Utc};
+use csv::Writer;
+use home_environments::switchbot::Measurement;
+use macaddr::MacAddr6;
+
+/// How to check that the measurements follow on from each other.
+#[derive(Debug, Clone, Copy)]
+pub struct PlausibilityOptions {
+    /// The largest change of temperature between consecutive rows of a device, in Celsius.
+    pub max_temperature_jump: f32,
+    /// Whether an implausible row is an invalid record rather than a warning.
+    pub strict: bool,
+}
+
+/// Something implausible about a measurement, compared to the previous one of its device.
+#[derive(Debug, Clone, Copy)]
+pub enum Issue {
+    Backwards {
+        previous: DateTime<Utc>,
+    },
+    TemperatureJump {
+        previous: f32,
+        temperature_celsius: f32,
+    },
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Issue::Backwards { previous } => {
+                write!(f, "time went backwards from {}", previous.to_rfc3339())
+            }
+            Issue::TemperatureJump {
+                previous,
+                temperature_celsius,
+            } => write!(
+                f,
+                "temperature jumped from {previous} to {temperature_celsius} °C"
+            ),
+        }
+    }
+}
+
+/// A measurement with at least one issue.
+#[derive(Debug)]
+pub struct Implausible {
+    pub device_id: MacAddr6,
+    pub measured_at: DateTime<Utc>,
+    pub issues: Vec<Issue>,
+}
+
+impl fmt::Display for Implausible {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "implausible measurement of {} at {}",
+            self.device_id,
+            self.measured_at.to_rfc3339()
+        )?;
+        for (i, issue) in self.issues.iter().enumerate() {
+            write!(f, "{} {issue}", if i == 0 { ":" } else { ";" })?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for Implausible {}
+
+/// Compares each measurement of a file to the previous one of its device.
+#[derive(Debug)]
+pub struct Plausibility {
+    options: PlausibilityOptions,
+    previous: HashMap<MacAddr6, (DateTime<Utc>, f32)>,
+}
+
+impl Plausibility {
+    pub fn new(options: PlausibilityOptions) -> Self {
+        Self {
+            options,
+            previous: HashMap::new(),
+        }
+    }
+
+    /// Returns what is implausible about `m`, as an error with `--strict`. A rejected measurement
+    /// is not compared to the next one, so that a single spike is reported once.
+    pub fn check(&mut self, m: &Measurement) -> Result<Option<Implausible>, Implausible> {
+        let mut issues = Vec::new();
+        if let Some(&(measured_at, temperature_celsius)) = self.previous.get(&m.device_id) {
+            if m.measured_at < measured_at {
+                issues.push(Issue::Backwards {
+                    previous: measured_at,
+                });
+            }
+            if (m.temperature_celsius - temperature_celsius).abs()
+                > self.options.max_temperature_jump
+            {
+                issues.push(Issue::TemperatureJump {
+                    previous: temperature_celsius,
+                    temperature_celsius: m.temperature_celsius,
+                });
+            }
+        }
+        if issues.is_empty() || !self.options.strict {
+            self.previous
+                .insert(m.device_id, (m.measured_at, m.temperature_celsius));
+        }
+        if issues.is_empty() {
+            return Ok(None);
+        }
+
+        let implausible = Implausible {
+            device_id: m.device_id,
+            measured_at: m.measured_at,
+            issues,
+        };
+        if self.options.strict {
+            return Err(implausible);
+        }
+
+        Ok(Some(implausible))
+    }
+}
+
+/// The implausible measurements that were imported anyway. The file is only created once there is
+/// one.
+#[derive(Debug)]
+pub struct Warnings {
+    path: PathBuf,
+    writer: Option<Writer<File>>,
+    count: usize,
+}
+
+impl Warnings {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            writer: None,
+            count: 0,
+        }
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Writes a row per issue of the measurement of the input named `name`.
+    pub fn add(&mut self, name: &str, implausible: &Implausible) -> Result<()> {
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => {
+                let mut writer = Writer::from_path(&self.path).with_context(|| {
+                    format!("failed to create warnings file {}", self.path.display())
+                })?;
+                writer.write_record(["file", "device_id", "measured_at", "warning"])?;
+                self.writer.insert(writer)
+            }
+        };
+
+        let device_id = implausible.device_id.to_string();
+        let measured_at = implausible.measured_at.to_rfc3339();
+        for issue in &implausible.issues {
+            writer
+                .write_record([name, &device_id, &measured_at, &issue.to_string()])
+                .context("failed to write warning")?;
+        }
+        writer
+            .flush()
+            .with_context(|| format!("failed to write to {}", self.path.display()))?;
+        self.count += 1;
+
+        Ok(())
+    }
+}
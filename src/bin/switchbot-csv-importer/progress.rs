@@ -0,0 +1,72 @@
+use std::{path::Path, time::Instant};
+
+use anyhow::{Context as _, Result};
+use indicatif::{ProgressBar, ProgressStyle};
+
+use crate::input;
+
+const TEMPLATE: &str = "{prefix} [{bar:30}] {percent:>3}% {msg}, ETA {eta}";
+
+/// For the standard input, whose length is unknown.
+const STDIN_TEMPLATE: &str = "{prefix} {bytes} read, {msg}";
+
+/// A progress bar over the bytes read from one file, which also reports the inserted rows and their
+/// rate.
+/// The ETA is based on the file size, compressed or not.
+pub struct Progress {
+    bar: ProgressBar,
+    started_at: Instant,
+    rows: usize,
+}
+
+impl Progress {
+    /// With `quiet`, nothing is drawn. Nor is it when stderr is not a terminal.
+    pub fn new(path: &Path, quiet: bool) -> Result<Self> {
+        let bar = if quiet {
+            ProgressBar::hidden()
+        } else {
+            ProgressBar::new(0)
+        };
+        let (template, prefix) = if input::is_stdin(path) {
+            (STDIN_TEMPLATE, input::STDIN_NAME.to_string())
+        } else {
+            (TEMPLATE, path.display().to_string())
+        };
+        bar.set_style(
+            ProgressStyle::with_template(template)
+                .context("invalid progress bar template")?
+                .progress_chars("=> "),
+        );
+        bar.set_prefix(prefix);
+
+        Ok(Self {
+            bar,
+            started_at: Instant::now(),
+            rows: 0,
+        })
+    }
+
+    /// The bar to set the length of and to wrap the file reader with.
+    pub fn bar(&self) -> &ProgressBar {
+        &self.bar
+    }
+
+    pub fn add_rows(&mut self, rows: usize) {
+        self.rows += rows;
+        self.bar.set_message(format!(
+            "{} rows, {:.0} rows/s",
+            self.rows,
+            self.rows_per_sec()
+        ));
+    }
+
+    /// Clears the bar and returns the rows inserted from the file.
+    pub fn finish(self) -> usize {
+        self.bar.finish_and_clear();
+        self.rows
+    }
+
+    fn rows_per_sec(&self) -> f64 {
+        self.rows as f64 / self.started_at.elapsed().as_secs_f64().max(f64::EPSILON)
+    }
+}
@@ -0,0 +1,90 @@
+use std::str::FromStr;
+
+use anyhow::{Context as _, Result, bail};
+use chrono::{DateTime, Days, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use chrono_tz::Tz;
+
+use crate::timezone::{AmbiguousTime, to_utc};
+
+/// A bound of `--from` or `--to`: a day, a local time, or a time with an offset.
+#[derive(Debug, Clone, Copy)]
+pub enum Bound {
+    Date(NaiveDate),
+    Local(NaiveDateTime),
+    Fixed(DateTime<FixedOffset>),
+}
+
+impl Bound {
+    /// The first instant the bound includes.
+    fn start(self, timezone: Option<Tz>) -> Result<DateTime<Utc>> {
+        let naive = match self {
+            Bound::Fixed(dt) => return Ok(dt.with_timezone(&Utc)),
+            Bound::Local(naive) => naive,
+            Bound::Date(date) => date.and_time(NaiveTime::MIN),
+        };
+        let timezone =
+            timezone.context("--timezone is required for --from and --to without an offset")?;
+
+        to_utc(naive, timezone, AmbiguousTime::Earliest)
+    }
+
+    /// The first instant after the bound, which includes the whole day of a date.
+    fn end(self, timezone: Option<Tz>) -> Result<DateTime<Utc>> {
+        match self {
+            Bound::Date(date) => Bound::Date(
+                date.checked_add_days(Days::new(1))
+                    .context("--to is too late")?,
+            )
+            .start(timezone),
+            _ => self.start(timezone),
+        }
+    }
+}
+
+impl FromStr for Bound {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Ok(dt) = DateTime::parse_from_rfc3339(s) {
+            return Ok(Bound::Fixed(dt));
+        }
+        for format in [
+            "%Y-%m-%dT%H:%M:%S",
+            "%Y-%m-%d %H:%M:%S",
+            "%Y-%m-%dT%H:%M",
+            "%Y-%m-%d %H:%M",
+        ] {
+            if let Ok(naive) = NaiveDateTime::parse_from_str(s, format) {
+                return Ok(Bound::Local(naive));
+            }
+        }
+        if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+            return Ok(Bound::Date(date));
+        }
+
+        bail!("expected a date such as 2025-01-31, or a time such as `2025-01-31 18:00`")
+    }
+}
+
+/// The times that are imported, from `--from` and up to `--to`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TimeRange {
+    from: Option<DateTime<Utc>>,
+    /// Excluded.
+    to: Option<DateTime<Utc>>,
+}
+
+impl TimeRange {
+    /// Resolves the bounds in `timezone`. A day given to `--to` is included.
+    pub fn new(from: Option<Bound>, to: Option<Bound>, timezone: Option<Tz>) -> Result<Self> {
+        Ok(Self {
+            from: from.map(|from| from.start(timezone)).transpose()?,
+            to: to.map(|to| to.end(timezone)).transpose()?,
+        })
+    }
+
+    pub fn contains(&self, measured_at: DateTime<Utc>) -> bool {
+        self.from.is_none_or(|from| measured_at >= from)
+            && self.to.is_none_or(|to| measured_at < to)
+    }
+}
@@ -0,0 +1,73 @@
+use std::{fs::File, path::PathBuf};
+
+use anyhow::{Context as _, Result};
+use clap::ValueEnum;
+use csv::Writer;
+
+use crate::input::InvalidRecord;
+
+/// What to do with a record that fails to parse.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OnError {
+    /// Stop the import. The batches before the record stay imported.
+    #[default]
+    Abort,
+    /// Write the record to `--errors-file` and import the rest.
+    Skip,
+}
+
+/// The records skipped with `--on-error skip`. The file is only created once there is one.
+#[derive(Debug)]
+pub struct Rejects {
+    path: PathBuf,
+    writer: Option<Writer<File>>,
+    count: usize,
+}
+
+impl Rejects {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            writer: None,
+            count: 0,
+        }
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Writes the record that failed with `err` in the input named `name`, along with the error.
+    pub fn add(&mut self, name: &str, err: &anyhow::Error) -> Result<()> {
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => {
+                let mut writer = Writer::from_path(&self.path).with_context(|| {
+                    format!("failed to create errors file {}", self.path.display())
+                })?;
+                writer.write_record(["file", "line", "error", "record"])?;
+                self.writer.insert(writer)
+            }
+        };
+
+        let (line, record) = match err.downcast_ref::<InvalidRecord>() {
+            Some(invalid) => (invalid.line.to_string(), invalid.record.as_str()),
+            None => (String::new(), ""),
+        };
+        let error = format!("{err:#}");
+        writer
+            .write_record([name, &line, &error, record])
+            .context("failed to write rejected record")?;
+        // The file is read while the import goes on, or after it failed.
+        writer
+            .flush()
+            .with_context(|| format!("failed to write to {}", self.path.display()))?;
+        self.count += 1;
+
+        Ok(())
+    }
+}
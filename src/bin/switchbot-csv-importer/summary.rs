@@ -0,0 +1,217 @@
+use std::{cmp::Reverse, collections::HashMap, time::Duration};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use home_environments::switchbot::Measurement;
+use macaddr::MacAddr6;
+use serde::Serialize;
+
+/// The number of gaps that are logged, the longest first. The JSON summary has all of them.
+const LOGGED_GAPS: usize = 10;
+
+/// The smallest and largest of the values.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct MinMax<T> {
+    pub min: T,
+    pub max: T,
+}
+
+impl<T: PartialOrd + Copy> MinMax<T> {
+    fn add(range: &mut Option<Self>, value: T) {
+        match range {
+            Some(range) if value < range.min => range.min = value,
+            Some(range) if value > range.max => range.max = value,
+            Some(_) => {}
+            None => {
+                *range = Some(Self {
+                    min: value,
+                    max: value,
+                })
+            }
+        }
+    }
+
+    fn merge(range: &mut Option<Self>, other: Option<Self>) {
+        if let Some(other) = other {
+            Self::add(range, other.min);
+            Self::add(range, other.max);
+        }
+    }
+}
+
+/// A stretch without measurements of a device, longer than `--min-gap`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Gap {
+    pub device_id: String,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+}
+
+impl Gap {
+    pub fn duration(&self) -> TimeDelta {
+        self.to - self.from
+    }
+}
+
+/// What was parsed of the measurements, in the order of the files and their records.
+#[derive(Debug)]
+pub struct Stats {
+    /// Records that failed to parse and were skipped, with `--on-error skip`.
+    pub rejected: usize,
+    /// Records outside of `--from` and `--to`, which were skipped.
+    pub out_of_range: usize,
+    /// Records collapsed into another of the same device and time in the file.
+    pub duplicates: usize,
+    /// Records imported with a warning since their time went backwards or their temperature
+    /// jumped.
+    pub implausible: usize,
+    min_gap: TimeDelta,
+    /// The first and the last measurement of each device.
+    devices: HashMap<MacAddr6, MinMax<DateTime<Utc>>>,
+    temperature_celsius: Option<MinMax<f32>>,
+    humidity_percent: Option<MinMax<u8>>,
+    gaps: Vec<Gap>,
+}
+
+impl Stats {
+    pub fn new(min_gap: Duration) -> Self {
+        Self {
+            rejected: 0,
+            out_of_range: 0,
+            duplicates: 0,
+            implausible: 0,
+            min_gap: TimeDelta::from_std(min_gap).unwrap_or(TimeDelta::MAX),
+            devices: HashMap::new(),
+            temperature_celsius: None,
+            humidity_percent: None,
+            gaps: Vec::new(),
+        }
+    }
+
+    /// Adds the measurement that follows the ones added so far. Only a measurement later than the
+    /// previous one of its device can end a gap, so unsorted files report fewer of them.
+    pub fn add(&mut self, m: &Measurement) {
+        MinMax::add(&mut self.temperature_celsius, m.temperature_celsius);
+        MinMax::add(&mut self.humidity_percent, m.humidity_percent);
+        self.add_span(
+            m.device_id,
+            MinMax {
+                min: m.measured_at,
+                max: m.measured_at,
+            },
+        );
+    }
+
+    /// Adds the measurements of `other`, which follow the ones added so far.
+    pub fn merge(&mut self, other: Stats) {
+        self.rejected += other.rejected;
+        self.out_of_range += other.out_of_range;
+        self.duplicates += other.duplicates;
+        self.implausible += other.implausible;
+        MinMax::merge(&mut self.temperature_celsius, other.temperature_celsius);
+        MinMax::merge(&mut self.humidity_percent, other.humidity_percent);
+        self.gaps.extend(other.gaps);
+        for (device_id, span) in other.devices {
+            self.add_span(device_id, span);
+        }
+    }
+
+    fn add_span(&mut self, device_id: MacAddr6, span: MinMax<DateTime<Utc>>) {
+        let Some(previous) = self.devices.get_mut(&device_id) else {
+            self.devices.insert(device_id, span);
+            return;
+        };
+
+        if span.min - previous.max > self.min_gap {
+            self.gaps.push(Gap {
+                device_id: device_id.to_string(),
+                from: previous.max,
+                to: span.min,
+            });
+        }
+        previous.min = previous.min.min(span.min);
+        previous.max = previous.max.max(span.max);
+    }
+}
+
+/// The rows of an import that reached the database.
+#[derive(Debug, Default)]
+pub struct Counts {
+    /// Rows sent to the database.
+    pub sent: u64,
+    /// Rows actually inserted. The rest already existed.
+    pub written: u64,
+    /// Rows found to exist before they were sent, with `--check-existing`.
+    pub existing: Option<u64>,
+}
+
+impl Counts {
+    pub fn add(&mut self, other: Counts) {
+        self.sent += other.sent;
+        self.written += other.written;
+        if let Some(existing) = other.existing {
+            *self.existing.get_or_insert(0) += existing;
+        }
+    }
+}
+
+/// The outcome of an import, logged at the end and printed as JSON with `--summary-json`.
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    pub files: usize,
+    /// Records parsed and sent to the database.
+    pub rows: u64,
+    /// Rows actually inserted.
+    pub written: u64,
+    /// Rows that conflicted with existing ones and were left untouched.
+    pub skipped: u64,
+    /// Rows found to exist before they were sent, with `--check-existing`.
+    pub existing: Option<u64>,
+    /// Records that failed to parse, with `--on-error skip`.
+    pub rejected: usize,
+    /// Records outside of `--from` and `--to`.
+    pub out_of_range: usize,
+    /// Records collapsed into another of the same device and time in the file.
+    pub duplicates: usize,
+    /// Records imported with a warning since their time went backwards or their temperature
+    /// jumped.
+    pub implausible: usize,
+    pub first: Option<DateTime<Utc>>,
+    pub last: Option<DateTime<Utc>>,
+    pub temperature_celsius: Option<MinMax<f32>>,
+    pub humidity_percent: Option<MinMax<u8>>,
+    /// Every gap, the longest first.
+    pub gaps: Vec<Gap>,
+    pub elapsed_secs: f64,
+    pub rows_per_sec: f64,
+}
+
+impl Summary {
+    pub fn new(files: usize, counts: Counts, stats: Stats, elapsed: Duration) -> Self {
+        let mut gaps = stats.gaps;
+        gaps.sort_by_key(|gap| Reverse(gap.duration()));
+
+        Self {
+            files,
+            rows: counts.sent,
+            written: counts.written,
+            skipped: counts.sent - counts.written,
+            existing: counts.existing,
+            rejected: stats.rejected,
+            out_of_range: stats.out_of_range,
+            duplicates: stats.duplicates,
+            implausible: stats.implausible,
+            first: stats.devices.values().map(|span| span.min).min(),
+            last: stats.devices.values().map(|span| span.max).max(),
+            temperature_celsius: stats.temperature_celsius,
+            humidity_percent: stats.humidity_percent,
+            gaps,
+            elapsed_secs: elapsed.as_secs_f64(),
+            rows_per_sec: (counts.sent as f64 / elapsed.as_secs_f64().max(f64::EPSILON)).round(),
+        }
+    }
+
+    /// The longest gaps, up to [`LOGGED_GAPS`].
+    pub fn longest_gaps(&self) -> &[Gap] {
+        &self.gaps[..self.gaps.len().min(LOGGED_GAPS)]
+    }
+}
@@ -0,0 +1,74 @@
+use std::{path::Path, str::FromStr};
+
+use anyhow::{Context as _, Result, anyhow, bail};
+use chrono::{DateTime, LocalResult, NaiveDateTime, TimeZone as _, Utc};
+use chrono_tz::Tz;
+use clap::ValueEnum;
+use glob::Pattern;
+
+/// The timezone of the files matching a pattern, given as `PATTERN=TZ` with `--file-timezone`.
+#[derive(Debug, Clone)]
+pub struct FileTimezone {
+    pattern: Pattern,
+    timezone: Tz,
+}
+
+impl FileTimezone {
+    /// The timezone of the first of `timezones` whose pattern matches `file`.
+    pub fn find(timezones: &[FileTimezone], file: &Path) -> Option<Tz> {
+        timezones
+            .iter()
+            .find(|t| t.pattern.matches_path(file))
+            .map(|t| t.timezone)
+    }
+}
+
+impl FromStr for FileTimezone {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (pattern, timezone) = s
+            .rsplit_once('=')
+            .context("expected PATTERN=TZ, such as `exports/berlin/*.csv=Europe/Berlin`")?;
+        Ok(Self {
+            pattern: Pattern::new(pattern)
+                .with_context(|| format!("invalid glob pattern: {pattern}"))?,
+            timezone: timezone
+                .parse()
+                .map_err(|e| anyhow!("invalid timezone {timezone}: {e}"))?,
+        })
+    }
+}
+
+/// Which time to take for a local time that occurs twice, when the clocks are turned back at the
+/// end of daylight saving time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum AmbiguousTime {
+    /// The first occurrence, still in daylight saving time.
+    #[default]
+    Earliest,
+    /// The second occurrence, after the clocks were turned back.
+    Latest,
+    /// Treat the record as invalid.
+    Reject,
+}
+
+/// Converts `naive` in `timezone` to UTC. A time skipped when the clocks are turned forward is
+/// always invalid.
+pub fn to_utc(
+    naive: NaiveDateTime,
+    timezone: Tz,
+    ambiguous: AmbiguousTime,
+) -> Result<DateTime<Utc>> {
+    let local = match (timezone.from_local_datetime(&naive), ambiguous) {
+        (LocalResult::Single(dt), _) => dt,
+        (LocalResult::Ambiguous(earliest, _), AmbiguousTime::Earliest) => earliest,
+        (LocalResult::Ambiguous(_, latest), AmbiguousTime::Latest) => latest,
+        (LocalResult::Ambiguous(..), AmbiguousTime::Reject) => {
+            bail!("{naive} occurs twice in {timezone}")
+        }
+        (LocalResult::None, _) => bail!("{naive} does not exist in {timezone}"),
+    };
+
+    Ok(local.with_timezone(&Utc))
+}
@@ -0,0 +1,129 @@
+use std::{collections::HashSet, io};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use macaddr::MacAddr6;
+use tracing::warn;
+
+use crate::{
+    csv::ParseOptions, input::Measurements, plausibility::Plausibility, progress::Progress,
+};
+
+/// What a dry run found in the files.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub rows: usize,
+    pub errors: usize,
+    /// Rows outside of `--from` and `--to`, which are not counted in `rows`.
+    pub out_of_range: usize,
+    /// Rows of the same device and time as an earlier one in the file, which are not counted in
+    /// `rows` either.
+    pub duplicates: usize,
+    /// Rows whose time went backwards or whose temperature jumped, which are counted in `errors`
+    /// instead with `--strict`.
+    pub implausible: usize,
+    pub first: Option<DateTime<Utc>>,
+    pub last: Option<DateTime<Utc>>,
+    /// With `--check-existing`, how many of the rows are already in the database.
+    pub existing: Option<u64>,
+    /// The device and time of every parsed row, kept only to look them up with
+    /// `--check-existing`.
+    pub keys: Vec<(MacAddr6, DateTime<Utc>)>,
+}
+
+impl Report {
+    /// Adds up the counts and widens the time range. The keys of the rows are not kept.
+    pub fn merge(&mut self, other: Report) {
+        self.rows += other.rows;
+        self.errors += other.errors;
+        self.out_of_range += other.out_of_range;
+        self.duplicates += other.duplicates;
+        self.implausible += other.implausible;
+        self.first = match (self.first, other.first) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        self.last = self.last.max(other.last);
+        if let Some(existing) = other.existing {
+            *self.existing.get_or_insert(0) += existing;
+        }
+    }
+}
+
+/// Parses every record of the input named `name`, logging the ones that fail or are implausible
+/// instead of stopping at the first. Only a failed read ends the file early.
+pub fn validate(
+    name: &str,
+    measurements: Measurements,
+    options: &ParseOptions,
+    keep_keys: bool,
+    progress: &mut Progress,
+) -> Result<Report> {
+    let mut report = Report::default();
+    let mut seen = HashSet::new();
+    let mut plausibility = Plausibility::new(options.plausibility);
+    for result in measurements {
+        let result = match result {
+            Ok(record) if options.range.contains(record.measured_at) => {
+                match plausibility.check(&record) {
+                    Ok(Some(implausible)) => {
+                        report.implausible += 1;
+                        progress.bar().suspend(|| {
+                            warn!(
+                                warning = %implausible,
+                                file = name,
+                                "implausible measurement"
+                            )
+                        });
+                        Ok(record)
+                    }
+                    Ok(None) => Ok(record),
+                    Err(implausible) => Err(implausible.into()),
+                }
+            }
+            result => result,
+        };
+
+        match result {
+            Ok(record) if !options.range.contains(record.measured_at) => report.out_of_range += 1,
+            Ok(record) if !seen.insert((record.device_id, record.measured_at)) => {
+                report.duplicates += 1;
+            }
+            Ok(record) => {
+                report.rows += 1;
+                progress.add_rows(1);
+                report.merge(Report {
+                    first: Some(record.measured_at),
+                    last: Some(record.measured_at),
+                    ..Default::default()
+                });
+                if keep_keys {
+                    report.keys.push((record.device_id, record.measured_at));
+                }
+            }
+            Err(err) if is_read_error(&err) => {
+                return Err(err.context("failed to read record"));
+            }
+            Err(err) => {
+                report.errors += 1;
+                progress.bar().suspend(|| {
+                    warn!(
+                        error = format!("{err:#}"),
+                        file = name,
+                        "failed to parse record"
+                    )
+                });
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// Whether `err` is a failure to read the input rather than an invalid record, after which no
+/// record can be read.
+pub fn is_read_error(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<::csv::Error>()
+        .is_some_and(|e| e.is_io_error())
+        || err.is::<io::Error>()
+}
@@ -1,44 +1,301 @@
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    fmt::Write as _,
+    ops::Range,
+};
+
 use anyhow::{Context as _, Result, anyhow};
-use chrono::DateTime;
+use chrono::{DateTime, TimeDelta, Utc};
 use chrono_tz::Tz;
 use macaddr::MacAddr6;
-use sqlx::{PgPool, postgres::PgPoolOptions};
+use sqlx::{Executor as _, PgConnection, PgPool, postgres::PgPoolOptions};
+use tokio_stream::{Stream, StreamExt as _};
+use uuid::Uuid;
 
-use crate::switchbot::{Device, DeviceType, Measurement};
+use crate::{
+    home::Home,
+    raw_advertisement::RawAdvertisement,
+    room::Room,
+    switchbot::{
+        BucketSize, Device, DeviceLocation, DeviceStatistics, DeviceType, LatestMeasurement,
+        Measurement, MeasurementBucket,
+    },
+};
 
 pub async fn new_pool(database_url: &str) -> Result<PgPool> {
     Ok(PgPoolOptions::new().connect(database_url).await?)
 }
 
+/// Connection pools split by access pattern. Writes always go to the primary, while queries may
+/// be served by a read replica.
+#[derive(Debug, Clone)]
+pub struct Pools {
+    pub write: PgPool,
+
+    pub read: PgPool,
+}
+
+/// Connects to the primary and, if given, a read replica. Without a replica both pools share the
+/// primary's connections.
+pub async fn new_pools(database_url: &str, read_database_url: Option<&str>) -> Result<Pools> {
+    let write = new_pool(database_url)
+        .await
+        .context("failed to connect to primary database")?;
+
+    let read = match read_database_url {
+        Some(read_database_url) => new_read_only_pool(read_database_url)
+            .await
+            .context("failed to connect to read replica database")?,
+        None => write.clone(),
+    };
+
+    Ok(Pools { write, read })
+}
+
+async fn new_read_only_pool(database_url: &str) -> Result<PgPool> {
+    Ok(PgPoolOptions::new()
+        .after_connect(|conn, _meta| {
+            Box::pin(async move {
+                conn.execute("SET default_transaction_read_only = on")
+                    .await?;
+                Ok(())
+            })
+        })
+        .connect(database_url)
+        .await?)
+}
+
 struct DeviceRow {
     id: Vec<u8>,
     r#type: String,
     name: String,
     sort_order: i64,
+    enabled: bool,
+    notes: Option<String>,
+    temperature_offset_celsius: f64,
+    humidity_offset_percent: i64,
+    room_id: Option<Uuid>,
+    room_home_id: Option<Uuid>,
+    room_name: Option<String>,
+    room_sort_order: Option<i64>,
 }
 
 impl TryFrom<DeviceRow> for Device {
     type Error = anyhow::Error;
 
     fn try_from(row: DeviceRow) -> Result<Self> {
-        let id_bytes: [u8; 6] = row
-            .id
-            .try_into()
-            .map_err(|v: Vec<u8>| anyhow!("invalid MAC address length: {}", v.len()))?;
         Ok(Device {
-            id: MacAddr6::from(id_bytes),
+            id: mac_address_from_bytes(row.id)?,
             r#type: row.r#type.parse::<DeviceType>()?,
             name: row.name,
             sort_order: row.sort_order as u8,
+            enabled: row.enabled,
+            room: room_from_columns(
+                row.room_id,
+                row.room_home_id,
+                row.room_name,
+                row.room_sort_order,
+            ),
+            notes: row.notes,
+            temperature_offset_celsius: row.temperature_offset_celsius as f32,
+            humidity_offset_percent: row.humidity_offset_percent as i8,
+        })
+    }
+}
+
+struct MeasurementRow {
+    device_id: Vec<u8>,
+    measured_at: DateTime<Utc>,
+    temperature_celsius: f64,
+    humidity_percent: i64,
+    co2_ppm: Option<i64>,
+    light_level: Option<i64>,
+    rssi_dbm: Option<i64>,
+    source: Option<String>,
+}
+
+impl TryFrom<MeasurementRow> for Measurement {
+    type Error = anyhow::Error;
+
+    fn try_from(row: MeasurementRow) -> Result<Self> {
+        Ok(Measurement {
+            device_id: mac_address_from_bytes(row.device_id)?,
+            measured_at: row.measured_at,
+            temperature_celsius: row.temperature_celsius as f32,
+            humidity_percent: row.humidity_percent as u8,
+            co2_ppm: row.co2_ppm.map(|v| v as u16),
+            light_level: row.light_level.map(|v| v as u8),
+            rssi_dbm: row.rssi_dbm.map(|v| v as i16),
+            source: row.source,
+        })
+    }
+}
+
+struct LatestMeasurementRow {
+    device_id: Vec<u8>,
+    measured_at: DateTime<Utc>,
+    temperature_celsius: f64,
+    humidity_percent: i64,
+    co2_ppm: Option<i64>,
+    light_level: Option<i64>,
+    rssi_dbm: Option<i64>,
+    source: Option<String>,
+    r#type: String,
+    name: String,
+    sort_order: i64,
+    enabled: bool,
+    notes: Option<String>,
+    temperature_offset_celsius: f64,
+    humidity_offset_percent: i64,
+    room_id: Option<Uuid>,
+    room_home_id: Option<Uuid>,
+    room_name: Option<String>,
+    room_sort_order: Option<i64>,
+}
+
+impl LatestMeasurementRow {
+    fn into_latest_measurement(self) -> Result<LatestMeasurement> {
+        let device_id = mac_address_from_bytes(self.device_id)?;
+        Ok(LatestMeasurement {
+            device: Device {
+                id: device_id,
+                r#type: self.r#type.parse::<DeviceType>()?,
+                name: self.name,
+                sort_order: self.sort_order as u8,
+                enabled: self.enabled,
+                room: room_from_columns(
+                    self.room_id,
+                    self.room_home_id,
+                    self.room_name,
+                    self.room_sort_order,
+                ),
+                notes: self.notes,
+                temperature_offset_celsius: self.temperature_offset_celsius as f32,
+                humidity_offset_percent: self.humidity_offset_percent as i8,
+            },
+            measurement: Measurement {
+                device_id,
+                measured_at: self.measured_at,
+                temperature_celsius: self.temperature_celsius as f32,
+                humidity_percent: self.humidity_percent as u8,
+                co2_ppm: self.co2_ppm.map(|v| v as u16),
+                light_level: self.light_level.map(|v| v as u8),
+                rssi_dbm: self.rssi_dbm.map(|v| v as i16),
+                source: self.source,
+            },
+        })
+    }
+}
+
+struct MeasurementBucketRow {
+    bucket_start: DateTime<Utc>,
+    sample_count: i64,
+    temperature_celsius_min: f64,
+    temperature_celsius_avg: f64,
+    temperature_celsius_max: f64,
+    humidity_percent_min: i64,
+    humidity_percent_avg: f64,
+    humidity_percent_max: i64,
+    co2_ppm_min: Option<i64>,
+    co2_ppm_avg: Option<f64>,
+    co2_ppm_max: Option<i64>,
+    light_level_min: Option<i64>,
+    light_level_avg: Option<f64>,
+    light_level_max: Option<i64>,
+}
+
+impl MeasurementBucketRow {
+    fn into_measurement_bucket(self, timezone: Tz) -> MeasurementBucket {
+        MeasurementBucket {
+            bucket_start: self.bucket_start.with_timezone(&timezone),
+            sample_count: self.sample_count as u32,
+            temperature_celsius_min: self.temperature_celsius_min as f32,
+            temperature_celsius_avg: self.temperature_celsius_avg,
+            temperature_celsius_max: self.temperature_celsius_max as f32,
+            humidity_percent_min: self.humidity_percent_min as u8,
+            humidity_percent_avg: self.humidity_percent_avg,
+            humidity_percent_max: self.humidity_percent_max as u8,
+            co2_ppm_min: self.co2_ppm_min.map(|v| v as u16),
+            co2_ppm_avg: self.co2_ppm_avg,
+            co2_ppm_max: self.co2_ppm_max.map(|v| v as u16),
+            light_level_min: self.light_level_min.map(|v| v as u8),
+            light_level_avg: self.light_level_avg,
+            light_level_max: self.light_level_max.map(|v| v as u8),
+        }
+    }
+}
+
+struct DeviceStatisticsRow {
+    id: Vec<u8>,
+    name: String,
+    record_count: i64,
+    first_measured_at: Option<DateTime<Utc>>,
+    last_measured_at: Option<DateTime<Utc>>,
+    /// Distinct 1-minute slots with a measurement, so that sub-minute data does not exceed 100%.
+    slot_count_24h: i64,
+    slot_count_7d: i64,
+}
+
+impl DeviceStatisticsRow {
+    fn into_device_statistics(self, timezone: Tz) -> Result<DeviceStatistics> {
+        Ok(DeviceStatistics {
+            device_id: mac_address_from_bytes(self.id)?,
+            device_name: self.name,
+            record_count: self.record_count as u64,
+            first_measured_at: self.first_measured_at.map(|t| t.with_timezone(&timezone)),
+            last_measured_at: self.last_measured_at.map(|t| t.with_timezone(&timezone)),
+            coverage_24h_percent: coverage_percent(self.slot_count_24h, TimeDelta::hours(24)),
+            coverage_7d_percent: coverage_percent(self.slot_count_7d, TimeDelta::days(7)),
         })
     }
 }
 
+fn coverage_percent(slot_count: i64, period: TimeDelta) -> f64 {
+    slot_count as f64 / period.num_minutes() as f64 * 100f64
+}
+
+fn room_from_columns(
+    id: Option<Uuid>,
+    home_id: Option<Uuid>,
+    name: Option<String>,
+    sort_order: Option<i64>,
+) -> Option<Room> {
+    Some(Room {
+        id: id?,
+        home_id: home_id?,
+        name: name?,
+        sort_order: sort_order?,
+    })
+}
+
+fn mac_address_from_bytes(bytes: Vec<u8>) -> Result<MacAddr6> {
+    let bytes: [u8; 6] = bytes
+        .try_into()
+        .map_err(|v: Vec<u8>| anyhow!("invalid MAC address length: {}", v.len()))?;
+    Ok(MacAddr6::from(bytes))
+}
+
 pub async fn get_switchbot_devices(pool: &PgPool) -> Result<Vec<Device>> {
     let rows = sqlx::query_as!(
         DeviceRow,
         r#"
-        SELECT id, type::TEXT as "type!", name, sort_order FROM switchbot_devices ORDER BY sort_order
+        SELECT
+            d.id,
+            d.type::TEXT AS "type!",
+            d.name,
+            d.sort_order,
+            d.enabled,
+            d.notes,
+            d.temperature_offset_celsius,
+            d.humidity_offset_percent,
+            r.id AS "room_id?",
+            r.home_id AS "room_home_id?",
+            r.name AS "room_name?",
+            r.sort_order AS "room_sort_order?"
+        FROM switchbot_devices AS d
+        LEFT JOIN switchbot_device_locations AS l ON l.device_id = d.id AND l.removed_at IS NULL
+        LEFT JOIN rooms AS r ON r.id = l.room_id
+        ORDER BY d.sort_order
         "#,
     )
     .fetch_all(pool)
@@ -50,16 +307,858 @@ pub async fn get_switchbot_devices(pool: &PgPool) -> Result<Vec<Device>> {
         .collect::<Result<Vec<_>>>()
 }
 
+/// Registers a device, after the last one unless `sort_order` is given.
+pub async fn insert_switchbot_device(
+    pool: &PgPool,
+    id: MacAddr6,
+    r#type: DeviceType,
+    name: &str,
+    sort_order: Option<u8>,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO switchbot_devices (id, type, name, sort_order)
+        VALUES (
+            $1::BYTEA,
+            CAST($2::TEXT AS switchbot_device_type),
+            $3::TEXT,
+            COALESCE($4::INT8, (SELECT COALESCE(MAX(sort_order), 0) + 1 FROM switchbot_devices))
+        )
+        "#,
+        id.as_bytes(),
+        r#type.as_str(),
+        name,
+        sort_order.map(i64::from),
+    )
+    .execute(pool)
+    .await
+    .context("failed to insert into switchbot_devices")?;
+
+    Ok(())
+}
+
+/// Registers `device` with all its settings but its room, which is restored with its locations,
+/// unless a device with its MAC address exists. It goes after the last device if its sort order is
+/// taken. Returns whether it was registered.
+pub async fn restore_switchbot_device(pool: &PgPool, device: &Device) -> Result<bool> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO switchbot_devices (id, type, name, sort_order, enabled, notes, temperature_offset_celsius, humidity_offset_percent)
+        SELECT
+            $1::BYTEA,
+            CAST($2::TEXT AS switchbot_device_type),
+            $3::TEXT,
+            CASE
+                WHEN EXISTS (SELECT 1 FROM switchbot_devices WHERE sort_order = $4::INT8)
+                THEN (SELECT COALESCE(MAX(sort_order), 0) + 1 FROM switchbot_devices)
+                ELSE $4::INT8
+            END,
+            $5::BOOL,
+            $6::TEXT,
+            $7::FLOAT8,
+            $8::INT8
+        ON CONFLICT (id) DO NOTHING
+        "#,
+        device.id.as_bytes(),
+        device.r#type.as_str(),
+        device.name,
+        i64::from(device.sort_order),
+        device.enabled,
+        device.notes,
+        f64::from(device.temperature_offset_celsius),
+        i64::from(device.humidity_offset_percent),
+    )
+    .execute(pool)
+    .await
+    .context("failed to insert into switchbot_devices")?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn get_homes(pool: &PgPool) -> Result<Vec<Home>> {
+    sqlx::query_as!(
+        Home,
+        "SELECT id, name, sort_order FROM homes ORDER BY sort_order"
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to select homes")
+}
+
+pub async fn get_rooms(pool: &PgPool) -> Result<Vec<Room>> {
+    sqlx::query_as!(
+        Room,
+        "SELECT id, home_id, name, sort_order FROM rooms ORDER BY home_id, sort_order"
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to select rooms")
+}
+
+struct DeviceLocationRow {
+    device_id: Vec<u8>,
+    room_id: Uuid,
+    placed_at: DateTime<Utc>,
+    removed_at: Option<DateTime<Utc>>,
+}
+
+/// Every location of every device, past and current.
+pub async fn get_switchbot_device_locations(pool: &PgPool) -> Result<Vec<DeviceLocation>> {
+    let rows = sqlx::query_as!(
+        DeviceLocationRow,
+        r#"
+        SELECT device_id, room_id, placed_at, removed_at
+        FROM switchbot_device_locations
+        ORDER BY device_id, placed_at
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to select switchbot_device_locations")?;
+
+    rows.into_iter()
+        .map(|row| {
+            Ok(DeviceLocation {
+                device_id: mac_address_from_bytes(row.device_id)?,
+                room_id: row.room_id,
+                placed_at: row.placed_at,
+                removed_at: row.removed_at,
+            })
+        })
+        .collect()
+}
+
+/// Inserts `home` unless a home with its ID exists. It goes after the last home if its sort order
+/// is taken. Returns whether it was inserted.
+pub async fn restore_home(pool: &PgPool, home: &Home) -> Result<bool> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO homes (id, name, sort_order)
+        SELECT
+            $1::UUID,
+            $2::TEXT,
+            CASE
+                WHEN EXISTS (SELECT 1 FROM homes WHERE sort_order = $3::INT8)
+                THEN (SELECT COALESCE(MAX(sort_order), 0) + 1 FROM homes)
+                ELSE $3::INT8
+            END
+        ON CONFLICT (id) DO NOTHING
+        "#,
+        home.id,
+        home.name,
+        home.sort_order,
+    )
+    .execute(pool)
+    .await
+    .context("failed to insert into homes")?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Inserts `room` unless a room with its ID exists. It goes after the last room of its home if its
+/// sort order is taken. Returns whether it was inserted.
+pub async fn restore_room(pool: &PgPool, room: &Room) -> Result<bool> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO rooms (id, home_id, name, sort_order)
+        SELECT
+            $1::UUID,
+            $2::UUID,
+            $3::TEXT,
+            CASE
+                WHEN EXISTS (SELECT 1 FROM rooms WHERE home_id = $2::UUID AND sort_order = $4::INT8)
+                THEN (SELECT COALESCE(MAX(sort_order), 0) + 1 FROM rooms WHERE home_id = $2::UUID)
+                ELSE $4::INT8
+            END
+        ON CONFLICT (id) DO NOTHING
+        "#,
+        room.id,
+        room.home_id,
+        room.name,
+        room.sort_order,
+    )
+    .execute(pool)
+    .await
+    .context("failed to insert into rooms")?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Inserts `location` unless the device already has a location placed at the same time, or a
+/// current one when `location` is current. Returns whether it was inserted.
+pub async fn restore_switchbot_device_location(
+    pool: &PgPool,
+    location: &DeviceLocation,
+) -> Result<bool> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO switchbot_device_locations (device_id, room_id, placed_at, removed_at)
+        VALUES ($1::BYTEA, $2::UUID, $3::TIMESTAMPTZ, $4::TIMESTAMPTZ)
+        ON CONFLICT DO NOTHING
+        "#,
+        location.device_id.as_bytes(),
+        location.room_id,
+        location.placed_at,
+        location.removed_at,
+    )
+    .execute(pool)
+    .await
+    .context("failed to insert into switchbot_device_locations")?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn update_switchbot_device_name(pool: &PgPool, id: MacAddr6, name: &str) -> Result<()> {
+    sqlx::query!(
+        "UPDATE switchbot_devices SET name = $2::TEXT WHERE id = $1::BYTEA",
+        id.as_bytes(),
+        name,
+    )
+    .execute(pool)
+    .await
+    .context("failed to update switchbot_devices")?;
+
+    Ok(())
+}
+
+pub async fn update_switchbot_device_sort_order(
+    pool: &PgPool,
+    id: MacAddr6,
+    sort_order: u8,
+) -> Result<()> {
+    sqlx::query!(
+        "UPDATE switchbot_devices SET sort_order = $2::INT8 WHERE id = $1::BYTEA",
+        id.as_bytes(),
+        i64::from(sort_order),
+    )
+    .execute(pool)
+    .await
+    .context("failed to update switchbot_devices")?;
+
+    Ok(())
+}
+
+pub async fn update_switchbot_device_type(
+    pool: &PgPool,
+    id: MacAddr6,
+    r#type: DeviceType,
+) -> Result<()> {
+    sqlx::query!(
+        "UPDATE switchbot_devices SET type = CAST($2::TEXT AS switchbot_device_type) WHERE id = $1::BYTEA",
+        id.as_bytes(),
+        r#type.as_str(),
+    )
+    .execute(pool)
+    .await
+    .context("failed to update switchbot_devices")?;
+
+    Ok(())
+}
+
+/// Unregisters a device. Its measurements, rollups, raw advertisements and locations are deleted
+/// with it if `purge` is set, and otherwise keep it from being deleted.
+pub async fn delete_switchbot_device(pool: &PgPool, id: MacAddr6, purge: bool) -> Result<()> {
+    let mut tx = pool.begin().await.context("failed to begin transaction")?;
+    if purge {
+        for table in [
+            "switchbot_measurements",
+            "switchbot_measurements_hourly",
+            "switchbot_measurements_daily",
+            "raw_advertisements",
+            "switchbot_device_locations",
+        ] {
+            sqlx::query(&format!("DELETE FROM {table} WHERE device_id = $1::BYTEA"))
+                .bind(id.as_bytes())
+                .execute(&mut *tx)
+                .await
+                .with_context(|| format!("failed to delete from {table}"))?;
+        }
+    }
+    sqlx::query!(
+        "DELETE FROM switchbot_devices WHERE id = $1::BYTEA",
+        id.as_bytes()
+    )
+    .execute(&mut *tx)
+    .await
+    .context("failed to delete from switchbot_devices")?;
+    tx.commit().await.context("failed to commit transaction")?;
+
+    Ok(())
+}
+
+/// Returns record counts, first/last measurement times and recent 1-minute slot coverage for every
+/// registered device, including devices that have never reported.
+pub async fn get_device_statistics(pool: &PgPool, timezone: Tz) -> Result<Vec<DeviceStatistics>> {
+    let rows = sqlx::query_as!(
+        DeviceStatisticsRow,
+        r#"
+        SELECT
+            d.id,
+            d.name,
+            count(m.measured_at) AS "record_count!",
+            min(m.measured_at) AS first_measured_at,
+            max(m.measured_at) AS last_measured_at,
+            count(DISTINCT date_trunc('minute', m.measured_at)) FILTER (WHERE m.measured_at >= now() - INTERVAL '24 hours') AS "slot_count_24h!",
+            count(DISTINCT date_trunc('minute', m.measured_at)) FILTER (WHERE m.measured_at >= now() - INTERVAL '7 days') AS "slot_count_7d!"
+        FROM switchbot_devices AS d
+        LEFT JOIN switchbot_measurements AS m ON m.device_id = d.id
+        GROUP BY d.id, d.name, d.sort_order
+        ORDER BY d.sort_order
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to select switchbot device statistics")?;
+
+    rows.into_iter()
+        .map(|row| row.into_device_statistics(timezone))
+        .collect::<Result<Vec<_>>>()
+}
+
+pub async fn get_latest_switchbot_measurements(pool: &PgPool) -> Result<Vec<LatestMeasurement>> {
+    let rows = sqlx::query_as!(
+        LatestMeasurementRow,
+        r#"
+        SELECT
+            m.device_id AS "device_id!",
+            m.measured_at AS "measured_at!",
+            m.temperature_celsius AS "temperature_celsius!",
+            m.humidity_percent AS "humidity_percent!",
+            m.co2_ppm,
+            m.light_level,
+            m.rssi_dbm,
+            m.source,
+            d.type::TEXT AS "type!",
+            d.name,
+            d.sort_order,
+            d.enabled,
+            d.notes,
+            d.temperature_offset_celsius,
+            d.humidity_offset_percent,
+            r.id AS "room_id?",
+            r.home_id AS "room_home_id?",
+            r.name AS "room_name?",
+            r.sort_order AS "room_sort_order?"
+        FROM (
+            SELECT DISTINCT ON (device_id) *
+            FROM switchbot_measurements
+            ORDER BY device_id, measured_at DESC
+        ) AS m
+        JOIN switchbot_devices AS d ON d.id = m.device_id
+        LEFT JOIN switchbot_device_locations AS l ON l.device_id = d.id AND l.removed_at IS NULL
+        LEFT JOIN rooms AS r ON r.id = l.room_id
+        ORDER BY d.sort_order
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to select latest switchbot_measurements")?;
+
+    rows.into_iter()
+        .map(LatestMeasurementRow::into_latest_measurement)
+        .collect::<Result<Vec<_>>>()
+}
+
+/// Returns the start of every `interval`-sized bucket in `[from, to)` that has no measurement for
+/// the given device.
+pub async fn get_switchbot_measurement_gaps(
+    pool: &PgPool,
+    device_id: MacAddr6,
+    from: DateTime<Tz>,
+    to: DateTime<Tz>,
+    interval: TimeDelta,
+) -> Result<Vec<DateTime<Tz>>> {
+    let timezone = from.timezone();
+
+    let rows = sqlx::query_scalar!(
+        r#"
+        SELECT bucket AS "bucket!"
+        FROM generate_series($2::TIMESTAMPTZ, $3::TIMESTAMPTZ, $4::INTERVAL) AS bucket
+        WHERE bucket < $3::TIMESTAMPTZ
+          AND NOT EXISTS (
+            SELECT 1
+            FROM switchbot_measurements
+            WHERE device_id = $1
+              AND measured_at >= bucket
+              AND measured_at < bucket + $4::INTERVAL
+          )
+        ORDER BY bucket
+        "#,
+        device_id.as_bytes(),
+        from,
+        to,
+        interval as _,
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to select gaps in switchbot_measurements")?;
+
+    Ok(rows
+        .into_iter()
+        .map(|bucket| bucket.with_timezone(&timezone))
+        .collect())
+}
+
+/// Recomputes the hourly and daily rollups of every bucket overlapping `range`. Bucket boundaries
+/// follow the timezone of `range.start`.
+pub async fn refresh_rollups(pool: &PgPool, range: Range<DateTime<Tz>>) -> Result<()> {
+    let timezone = range.start.timezone().name();
+
+    let mut tx = pool.begin().await.context("failed to begin transaction")?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO switchbot_measurements_hourly (
+            device_id,
+            bucket_start,
+            sample_count,
+            temperature_celsius_min,
+            temperature_celsius_avg,
+            temperature_celsius_max,
+            humidity_percent_min,
+            humidity_percent_avg,
+            humidity_percent_max,
+            co2_ppm_min,
+            co2_ppm_avg,
+            co2_ppm_max,
+            light_level_min,
+            light_level_avg,
+            light_level_max
+        )
+        SELECT
+            device_id,
+            date_trunc('hour', measured_at AT TIME ZONE $3) AT TIME ZONE $3,
+            count(*),
+            min(temperature_celsius),
+            avg(temperature_celsius),
+            max(temperature_celsius),
+            min(humidity_percent),
+            avg(humidity_percent)::FLOAT8,
+            max(humidity_percent),
+            min(co2_ppm),
+            avg(co2_ppm)::FLOAT8,
+            max(co2_ppm),
+            min(light_level),
+            avg(light_level)::FLOAT8,
+            max(light_level)
+        FROM switchbot_measurements
+        WHERE measured_at >= date_trunc('hour', $1::TIMESTAMPTZ AT TIME ZONE $3) AT TIME ZONE $3
+          AND measured_at < (date_trunc('hour', $2::TIMESTAMPTZ AT TIME ZONE $3) + INTERVAL '1 hour') AT TIME ZONE $3
+        GROUP BY 1, 2
+        ON CONFLICT (device_id, bucket_start) DO UPDATE SET
+            sample_count = excluded.sample_count,
+            temperature_celsius_min = excluded.temperature_celsius_min,
+            temperature_celsius_avg = excluded.temperature_celsius_avg,
+            temperature_celsius_max = excluded.temperature_celsius_max,
+            humidity_percent_min = excluded.humidity_percent_min,
+            humidity_percent_avg = excluded.humidity_percent_avg,
+            humidity_percent_max = excluded.humidity_percent_max,
+            co2_ppm_min = excluded.co2_ppm_min,
+            co2_ppm_avg = excluded.co2_ppm_avg,
+            co2_ppm_max = excluded.co2_ppm_max,
+            light_level_min = excluded.light_level_min,
+            light_level_avg = excluded.light_level_avg,
+            light_level_max = excluded.light_level_max
+        "#,
+        range.start,
+        range.end,
+        timezone,
+    )
+    .execute(&mut *tx)
+    .await
+    .context("failed to refresh switchbot_measurements_hourly")?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO switchbot_measurements_daily (
+            device_id,
+            bucket_start,
+            sample_count,
+            temperature_celsius_min,
+            temperature_celsius_avg,
+            temperature_celsius_max,
+            humidity_percent_min,
+            humidity_percent_avg,
+            humidity_percent_max,
+            co2_ppm_min,
+            co2_ppm_avg,
+            co2_ppm_max,
+            light_level_min,
+            light_level_avg,
+            light_level_max
+        )
+        SELECT
+            device_id,
+            date_trunc('day', measured_at AT TIME ZONE $3) AT TIME ZONE $3,
+            count(*),
+            min(temperature_celsius),
+            avg(temperature_celsius),
+            max(temperature_celsius),
+            min(humidity_percent),
+            avg(humidity_percent)::FLOAT8,
+            max(humidity_percent),
+            min(co2_ppm),
+            avg(co2_ppm)::FLOAT8,
+            max(co2_ppm),
+            min(light_level),
+            avg(light_level)::FLOAT8,
+            max(light_level)
+        FROM switchbot_measurements
+        WHERE measured_at >= date_trunc('day', $1::TIMESTAMPTZ AT TIME ZONE $3) AT TIME ZONE $3
+          AND measured_at < (date_trunc('day', $2::TIMESTAMPTZ AT TIME ZONE $3) + INTERVAL '1 day') AT TIME ZONE $3
+        GROUP BY 1, 2
+        ON CONFLICT (device_id, bucket_start) DO UPDATE SET
+            sample_count = excluded.sample_count,
+            temperature_celsius_min = excluded.temperature_celsius_min,
+            temperature_celsius_avg = excluded.temperature_celsius_avg,
+            temperature_celsius_max = excluded.temperature_celsius_max,
+            humidity_percent_min = excluded.humidity_percent_min,
+            humidity_percent_avg = excluded.humidity_percent_avg,
+            humidity_percent_max = excluded.humidity_percent_max,
+            co2_ppm_min = excluded.co2_ppm_min,
+            co2_ppm_avg = excluded.co2_ppm_avg,
+            co2_ppm_max = excluded.co2_ppm_max,
+            light_level_min = excluded.light_level_min,
+            light_level_avg = excluded.light_level_avg,
+            light_level_max = excluded.light_level_max
+        "#,
+        range.start,
+        range.end,
+        timezone,
+    )
+    .execute(&mut *tx)
+    .await
+    .context("failed to refresh switchbot_measurements_daily")?;
+
+    tx.commit().await.context("failed to commit transaction")?;
+
+    Ok(())
+}
+
+/// Aggregates the measurements of a device in `range` into buckets of `size`, oldest first, from
+/// the raw measurements rather than the rollup tables so that they need not be refreshed. Bucket
+/// boundaries follow the timezone of `range.start`, and buckets without measurements are left out.
+pub async fn get_switchbot_measurement_buckets(
+    pool: &PgPool,
+    device_id: MacAddr6,
+    range: Range<DateTime<Tz>>,
+    size: BucketSize,
+) -> Result<Vec<MeasurementBucket>> {
+    let timezone = range.start.timezone();
+
+    let rows = sqlx::query_as!(
+        MeasurementBucketRow,
+        r#"
+        SELECT
+            date_trunc($4, measured_at AT TIME ZONE $5) AT TIME ZONE $5 AS "bucket_start!",
+            count(*) AS "sample_count!",
+            min(temperature_celsius) AS "temperature_celsius_min!",
+            avg(temperature_celsius) AS "temperature_celsius_avg!",
+            max(temperature_celsius) AS "temperature_celsius_max!",
+            min(humidity_percent) AS "humidity_percent_min!",
+            avg(humidity_percent)::FLOAT8 AS "humidity_percent_avg!",
+            max(humidity_percent) AS "humidity_percent_max!",
+            min(co2_ppm) AS co2_ppm_min,
+            avg(co2_ppm)::FLOAT8 AS co2_ppm_avg,
+            max(co2_ppm) AS co2_ppm_max,
+            min(light_level) AS light_level_min,
+            avg(light_level)::FLOAT8 AS light_level_avg,
+            max(light_level) AS light_level_max
+        FROM switchbot_measurements
+        WHERE device_id = $1 AND measured_at >= $2 AND measured_at < $3
+        GROUP BY 1
+        ORDER BY 1
+        "#,
+        device_id.as_bytes(),
+        range.start,
+        range.end,
+        size.as_str(),
+        timezone.name(),
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to aggregate switchbot_measurements")?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| row.into_measurement_bucket(timezone))
+        .collect())
+}
+
+/// Streams the measurements of a device in `range`, oldest first, without buffering the whole
+/// result set in memory.
+pub fn stream_switchbot_measurements(
+    pool: &PgPool,
+    device_id: MacAddr6,
+    range: Range<DateTime<Tz>>,
+) -> impl Stream<Item = Result<Measurement>> + '_ {
+    sqlx::query_as!(
+        MeasurementRow,
+        r#"
+        SELECT device_id, measured_at, temperature_celsius, humidity_percent, co2_ppm, light_level, rssi_dbm, source
+        FROM switchbot_measurements
+        WHERE device_id = $1 AND measured_at >= $2 AND measured_at < $3
+        ORDER BY measured_at
+        "#,
+        device_id.as_bytes().to_vec(),
+        range.start,
+        range.end,
+    )
+    .fetch(pool)
+    .map(|row| {
+        row.context("failed to select switchbot_measurements")?
+            .try_into()
+    })
+}
+
+/// Rows destined for several tables that are written in a single transaction, so a flush either
+/// lands completely or not at all.
+#[derive(Debug, Default)]
+pub struct WriteBatch {
+    switchbot_measurements: Vec<Measurement>,
+    raw_advertisements: Vec<RawAdvertisement>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push_switchbot_measurement(&mut self, measurement: Measurement) {
+        self.switchbot_measurements.push(measurement);
+    }
+
+    pub fn extend_switchbot_measurements(
+        &mut self,
+        measurements: impl IntoIterator<Item = Measurement>,
+    ) {
+        self.switchbot_measurements.extend(measurements);
+    }
+
+    pub fn push_raw_advertisement(&mut self, raw_advertisement: RawAdvertisement) {
+        self.raw_advertisements.push(raw_advertisement);
+    }
+
+    pub fn extend_raw_advertisements(
+        &mut self,
+        raw_advertisements: impl IntoIterator<Item = RawAdvertisement>,
+    ) {
+        self.raw_advertisements.extend(raw_advertisements);
+    }
+
+    pub fn switchbot_measurements(&self) -> &[Measurement] {
+        &self.switchbot_measurements
+    }
+
+    pub fn raw_advertisements(&self) -> &[RawAdvertisement] {
+        &self.raw_advertisements
+    }
+
+    /// Returns the total number of rows across all tables.
+    pub fn len(&self) -> usize {
+        self.switchbot_measurements.len() + self.raw_advertisements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drops the rows measured or received before `cutoff` and returns how many were dropped.
+    pub fn evict_before(&mut self, cutoff: DateTime<Utc>) -> usize {
+        let len = self.len();
+        self.switchbot_measurements
+            .retain(|m| m.measured_at >= cutoff);
+        self.raw_advertisements.retain(|a| a.received_at >= cutoff);
+        len - self.len()
+    }
+
+    /// Drops the oldest rows of each table beyond `max_len` and returns how many were dropped.
+    pub fn evict_oldest(&mut self, max_len: usize) -> usize {
+        let len = self.len();
+        truncate_oldest(&mut self.switchbot_measurements, max_len, |m| m.measured_at);
+        truncate_oldest(&mut self.raw_advertisements, max_len, |a| a.received_at);
+        len - self.len()
+    }
+
+    /// Moves all rows of `other` into this batch, leaving `other` empty.
+    pub fn append(&mut self, other: &mut WriteBatch) {
+        self.switchbot_measurements
+            .append(&mut other.switchbot_measurements);
+        self.raw_advertisements
+            .append(&mut other.raw_advertisements);
+    }
+
+    #[tracing::instrument(
+        name = "insert",
+        skip_all,
+        fields(
+            switchbot_measurements = self.switchbot_measurements.len(),
+            raw_advertisements = self.raw_advertisements.len(),
+        )
+    )]
+    pub async fn commit(&self, pool: &PgPool) -> Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+
+        let mut tx = pool.begin().await.context("failed to begin transaction")?;
+
+        insert_switchbot_measurements(&mut tx, &self.switchbot_measurements).await?;
+        insert_raw_advertisements(&mut tx, &self.raw_advertisements).await?;
+
+        tx.commit().await.context("failed to commit transaction")?;
+
+        Ok(())
+    }
+}
+
+/// Returns how many rows were written. Rows that already exist with an equal or stronger signal
+/// are skipped.
+#[tracing::instrument(name = "insert", skip_all, fields(switchbot_measurements = measurments.len()))]
 pub async fn bulk_insert_switchbot_measurements(
     pool: &PgPool,
     measurments: &[Measurement],
-) -> Result<()> {
+) -> Result<u64> {
     if measurments.is_empty() {
-        return Ok(());
+        return Ok(0);
     }
 
+    let mut tx = pool.begin().await.context("failed to begin transaction")?;
+
+    let written = insert_switchbot_measurements(&mut tx, measurments).await?;
+
+    tx.commit().await.context("failed to commit transaction")?;
+
+    Ok(written)
+}
+
+/// Counts the measurements that already exist for `keys`, pairs of a device and a time.
+pub async fn count_existing_switchbot_measurements(
+    pool: &PgPool,
+    keys: &[(MacAddr6, DateTime<Utc>)],
+) -> Result<u64> {
+    if keys.is_empty() {
+        return Ok(0);
+    }
+
+    let device_ids: Vec<&[u8]> = keys.iter().map(|(d, _)| d.as_bytes()).collect();
+    let measured_ats: Vec<DateTime<Utc>> = keys.iter().map(|(_, t)| *t).collect();
+
+    let count = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM switchbot_measurements
+        JOIN UNNEST($1::BYTEA[], $2::TIMESTAMPTZ[]) AS k (device_id, measured_at)
+            USING (device_id, measured_at)
+        "#,
+        &device_ids as _,
+        &measured_ats,
+    )
+    .fetch_one(pool)
+    .await
+    .context("failed to count existing switchbot_measurements")?;
+
+    Ok(count as _)
+}
+
+/// Stores the dew point and the absolute humidity of `measurements`, on rows whose temperature and
+/// humidity are still theirs, rather than those of an existing row with a stronger signal.
+pub async fn update_switchbot_measurements_derived_metrics(
+    conn: &mut PgConnection,
+    measurements: &[Measurement],
+) -> Result<u64> {
+    if measurements.is_empty() {
+        return Ok(0);
+    }
+
+    let device_ids: Vec<&[u8]> = measurements
+        .iter()
+        .map(|m| m.device_id.as_bytes())
+        .collect();
+    let measured_ats: Vec<DateTime<Utc>> = measurements.iter().map(|m| m.measured_at).collect();
+    let temperature_celsiuses: Vec<f32> =
+        measurements.iter().map(|m| m.temperature_celsius).collect();
+    let humidity_percents: Vec<i16> = measurements
+        .iter()
+        .map(|m| m.humidity_percent as _)
+        .collect();
+    let dew_point_celsiuses: Vec<Option<f32>> =
+        measurements.iter().map(|m| m.dew_point_celsius()).collect();
+    let absolute_humidities: Vec<f32> = measurements
+        .iter()
+        .map(|m| m.absolute_humidity_g_m3())
+        .collect();
+
+    let result = sqlx::query!(
+        r#"
+        UPDATE switchbot_measurements AS m SET
+            dew_point_celsius = d.dew_point_celsius,
+            absolute_humidity_g_m3 = d.absolute_humidity_g_m3
+        FROM UNNEST($1::BYTEA[], $2::TIMESTAMPTZ[], $3::FLOAT4[], $4::INT2[], $5::FLOAT4[], $6::FLOAT4[])
+            AS d (device_id, measured_at, temperature_celsius, humidity_percent, dew_point_celsius, absolute_humidity_g_m3)
+        WHERE m.device_id = d.device_id
+            AND m.measured_at = d.measured_at
+            AND m.temperature_celsius = d.temperature_celsius::FLOAT8
+            AND m.humidity_percent = d.humidity_percent
+        "#,
+        &device_ids as _,
+        &measured_ats,
+        &temperature_celsiuses,
+        &humidity_percents,
+        &dew_point_celsiuses as _,
+        &absolute_humidities,
+    )
+    .execute(&mut *conn)
+    .await
+    .context("failed to update derived metrics of switchbot_measurements")?;
+
+    Ok(result.rows_affected())
+}
+
+/// The measurement with the strongest signal of every device and time, in the order they first
+/// appear. A batch can repeat a row, e.g. a late measurement merged with a batch that failed, and an
+/// upsert must not touch the same row twice.
+fn strongest_per_key(measurements: &[Measurement]) -> Vec<&Measurement> {
+    let mut indexes: HashMap<_, usize> = HashMap::with_capacity(measurements.len());
+    let mut strongest: Vec<&Measurement> = Vec::with_capacity(measurements.len());
+    for measurement in measurements {
+        match indexes.entry((measurement.device_id, measurement.measured_at)) {
+            Entry::Occupied(entry) => {
+                let kept = &mut strongest[*entry.get()];
+                if measurement.rssi_dbm > kept.rssi_dbm {
+                    *kept = measurement;
+                }
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(strongest.len());
+                strongest.push(measurement);
+            }
+        }
+    }
+
+    strongest
+}
+
+fn truncate_oldest<T>(rows: &mut Vec<T>, max_len: usize, time: impl Fn(&T) -> DateTime<Utc>) {
+    if rows.len() > max_len {
+        rows.sort_by_key(time);
+        rows.drain(..rows.len() - max_len);
+    }
+}
+
+/// Like [`bulk_insert_switchbot_measurements`], on a connection such as a transaction of the
+/// caller.
+pub async fn insert_switchbot_measurements(
+    conn: &mut PgConnection,
+    measurments: &[Measurement],
+) -> Result<u64> {
+    if measurments.is_empty() {
+        return Ok(0);
+    }
+    let measurments = strongest_per_key(measurments);
+
     let device_ids: Vec<&[u8]> = measurments.iter().map(|m| m.device_id.as_bytes()).collect();
-    let measured_ats: Vec<DateTime<Tz>> = measurments.iter().map(|m| m.measured_at).collect();
+    let measured_ats: Vec<DateTime<Utc>> = measurments.iter().map(|m| m.measured_at).collect();
     let temperature_celsiuses: Vec<f32> =
         measurments.iter().map(|m| m.temperature_celsius).collect();
     let humidity_percents: Vec<i16> = measurments
@@ -74,14 +1173,23 @@ pub async fn bulk_insert_switchbot_measurements(
         .iter()
         .map(|m| m.light_level.map(|v| v as _))
         .collect();
+    let rssi_dbms: Vec<Option<i16>> = measurments.iter().map(|m| m.rssi_dbm).collect();
+    let sources: Vec<Option<String>> = measurments.iter().map(|m| m.source.clone()).collect();
 
-    let mut tx = pool.begin().await.context("failed to begin transaction")?;
-
-    sqlx::query!(
+    // When several ingesters hear the same device, the row of the one with the strongest signal
+    // wins, regardless of which one flushes first. Inserting the same row again changes nothing.
+    let result = sqlx::query!(
         r#"
-        INSERT INTO switchbot_measurements (device_id, measured_at, temperature_celsius, humidity_percent, co2_ppm, light_level)
-        SELECT * FROM UNNEST($1::BYTEA[], $2::TIMESTAMPTZ[], $3::FLOAT4[], $4::INT2[], $5::INT2[], $6::INT2[])
-        ON CONFLICT (device_id, measured_at) DO NOTHING
+        INSERT INTO switchbot_measurements (device_id, measured_at, temperature_celsius, humidity_percent, co2_ppm, light_level, rssi_dbm, source)
+        SELECT * FROM UNNEST($1::BYTEA[], $2::TIMESTAMPTZ[], $3::FLOAT4[], $4::INT2[], $5::INT2[], $6::INT2[], $7::INT2[], $8::TEXT[])
+        ON CONFLICT (device_id, measured_at) DO UPDATE SET
+            temperature_celsius = excluded.temperature_celsius,
+            humidity_percent = excluded.humidity_percent,
+            co2_ppm = excluded.co2_ppm,
+            light_level = excluded.light_level,
+            rssi_dbm = excluded.rssi_dbm,
+            source = excluded.source
+        WHERE excluded.rssi_dbm > switchbot_measurements.rssi_dbm
         "#,
         &device_ids as _,
         &measured_ats,
@@ -89,12 +1197,116 @@ pub async fn bulk_insert_switchbot_measurements(
         &humidity_percents,
         &co2_ppms as  _,
         &light_levels as  _,
+        &rssi_dbms as _,
+        &sources as _,
     )
-    .execute(&mut *tx)
+    .execute(&mut *conn)
     .await
     .context("failed to bulk insert to switchbot_measurements")?;
 
-    tx.commit().await.context("failed to commit transaction")?;
+    Ok(result.rows_affected())
+}
+
+async fn insert_raw_advertisements(
+    conn: &mut PgConnection,
+    raw_advertisements: &[RawAdvertisement],
+) -> Result<()> {
+    if raw_advertisements.is_empty() {
+        return Ok(());
+    }
+
+    let device_ids: Vec<&[u8]> = raw_advertisements
+        .iter()
+        .map(|a| a.device_id.as_bytes())
+        .collect();
+    let received_ats: Vec<DateTime<Utc>> =
+        raw_advertisements.iter().map(|a| a.received_at).collect();
+    let rssi_dbms: Vec<Option<i16>> = raw_advertisements.iter().map(|a| a.rssi_dbm).collect();
+    let manufacturer_datas: Vec<serde_json::Value> = raw_advertisements
+        .iter()
+        .map(|a| {
+            hex_encode_map(&a.manufacturer_data, |company_id| {
+                format!("{company_id:04x}")
+            })
+        })
+        .collect();
+    let service_datas: Vec<serde_json::Value> = raw_advertisements
+        .iter()
+        .map(|a| hex_encode_map(&a.service_data, |uuid| uuid.to_string()))
+        .collect();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO raw_advertisements (device_id, received_at, rssi_dbm, manufacturer_data, service_data)
+        SELECT * FROM UNNEST($1::BYTEA[], $2::TIMESTAMPTZ[], $3::INT2[], $4::JSONB[], $5::JSONB[])
+        ON CONFLICT (device_id, received_at) DO NOTHING
+        "#,
+        &device_ids as _,
+        &received_ats,
+        &rssi_dbms as _,
+        &manufacturer_datas,
+        &service_datas,
+    )
+    .execute(&mut *conn)
+    .await
+    .context("failed to bulk insert to raw_advertisements")?;
 
     Ok(())
 }
+
+/// Encodes advertisement data as a JSON object of lowercase hex strings.
+fn hex_encode_map<K>(data: &HashMap<K, Vec<u8>>, key: impl Fn(&K) -> String) -> serde_json::Value {
+    data.iter()
+        .map(|(k, v)| {
+            let hex = v
+                .iter()
+                .fold(String::with_capacity(v.len() * 2), |mut s, b| {
+                    let _ = write!(s, "{b:02x}");
+                    s
+                });
+            (key(k), serde_json::Value::String(hex))
+        })
+        .collect::<serde_json::Map<_, _>>()
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn measurement(minute: i64, temperature_celsius: f32, rssi_dbm: Option<i16>) -> Measurement {
+        Measurement {
+            device_id: MacAddr6::new(0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x01),
+            measured_at: DateTime::UNIX_EPOCH + TimeDelta::minutes(minute),
+            temperature_celsius,
+            humidity_percent: 50,
+            co2_ppm: None,
+            light_level: None,
+            rssi_dbm,
+            source: None,
+        }
+    }
+
+    #[test]
+    fn strongest_per_key_keeps_the_strongest_of_a_repeated_key() {
+        let batch = [
+            measurement(0, 20.0, Some(-80)),
+            measurement(1, 21.0, None),
+            measurement(0, 20.5, Some(-60)),
+            measurement(1, 21.5, Some(-90)),
+            measurement(0, 20.9, Some(-70)),
+        ];
+
+        let strongest = strongest_per_key(&batch);
+
+        let temperatures: Vec<f32> = strongest.iter().map(|m| m.temperature_celsius).collect();
+        assert_eq!(temperatures, [20.5, 21.5]);
+    }
+
+    #[test]
+    fn strongest_per_key_keeps_distinct_keys() {
+        let batch = [measurement(0, 20.0, None), measurement(1, 21.0, None)];
+
+        assert_eq!(strongest_per_key(&batch).len(), 2);
+    }
+}
@@ -0,0 +1,180 @@
+use std::{io::Write, ops::Range, sync::Arc};
+
+use anyhow::{Context as _, Result};
+use arrow_array::{
+    ArrayRef, Float32Array, Int16Array, RecordBatch, StringArray, TimestampMillisecondArray,
+    UInt8Array, UInt16Array,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use chrono::DateTime;
+use chrono_tz::Tz;
+use macaddr::MacAddr6;
+use parquet::arrow::ArrowWriter;
+use sqlx::PgPool;
+use tokio_stream::StreamExt as _;
+
+use crate::{db::stream_switchbot_measurements, switchbot::Measurement};
+
+const RECORD_BATCH_SIZE: usize = 8192;
+
+/// The header of the CSV exports of the SwitchBot app, which switchbot-csv-importer reads back.
+const CSV_HEADER: [&str; 3] = ["Date", "Temperature_Celsius(°C)", "Relative_Humidity(%)"];
+
+/// The column of the CO2 concentration, which only the exports of CO2 meters have.
+const CSV_CO2_HEADER: &str = "CO2(ppm)";
+
+/// Writes the measurements of a device in `range` to `writer` as Parquet and returns the number
+/// of rows written.
+pub async fn export_switchbot_measurements_to_parquet<W: Write + Send>(
+    pool: &PgPool,
+    device_id: MacAddr6,
+    range: Range<DateTime<Tz>>,
+    writer: W,
+) -> Result<usize> {
+    let timezone = range.start.timezone();
+    let schema = measurement_schema(timezone);
+    let mut parquet_writer = ArrowWriter::try_new(writer, schema.clone(), None)
+        .context("failed to create Parquet writer")?;
+
+    let mut measurements = stream_switchbot_measurements(pool, device_id, range);
+    let mut buffer = Vec::with_capacity(RECORD_BATCH_SIZE);
+    let mut total = 0;
+
+    while let Some(measurement) = measurements.next().await {
+        buffer.push(measurement?);
+
+        if buffer.len() >= RECORD_BATCH_SIZE {
+            write_record_batch(&mut parquet_writer, &schema, timezone, &buffer)?;
+            total += buffer.len();
+            buffer.clear();
+        }
+    }
+
+    if !buffer.is_empty() {
+        write_record_batch(&mut parquet_writer, &schema, timezone, &buffer)?;
+        total += buffer.len();
+    }
+
+    parquet_writer
+        .close()
+        .context("failed to finish Parquet file")?;
+
+    Ok(total)
+}
+
+/// Writes the measurements of a device in `range` to `writer` in the CSV layout of the SwitchBot
+/// app, with the times in the timezone of `range` along with their offset, so that the hour a clock
+/// is turned back is not ambiguous, and returns the number of rows written. The CO2 column is only
+/// written if `co2` is set.
+pub async fn export_switchbot_measurements_to_csv<W: Write>(
+    pool: &PgPool,
+    device_id: MacAddr6,
+    range: Range<DateTime<Tz>>,
+    co2: bool,
+    writer: W,
+) -> Result<usize> {
+    let timezone = range.start.timezone();
+    let mut csv_writer = csv::Writer::from_writer(writer);
+    let mut header = CSV_HEADER.to_vec();
+    if co2 {
+        header.push(CSV_CO2_HEADER);
+    }
+    csv_writer
+        .write_record(&header)
+        .context("failed to write CSV header")?;
+
+    let mut measurements = stream_switchbot_measurements(pool, device_id, range);
+    let mut total = 0;
+
+    while let Some(measurement) = measurements.next().await {
+        let measurement = measurement?;
+        let mut record = vec![
+            measurement
+                .measured_at
+                .with_timezone(&timezone)
+                .format("%Y-%m-%d %H:%M%:z")
+                .to_string(),
+            measurement.temperature_celsius.to_string(),
+            measurement.humidity_percent.to_string(),
+        ];
+        if co2 {
+            record.push(
+                measurement
+                    .co2_ppm
+                    .map(|c| c.to_string())
+                    .unwrap_or_default(),
+            );
+        }
+        csv_writer
+            .write_record(&record)
+            .context("failed to write CSV record")?;
+        total += 1;
+    }
+
+    csv_writer.flush().context("failed to finish CSV file")?;
+
+    Ok(total)
+}
+
+fn measurement_schema(timezone: Tz) -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("device_id", DataType::Utf8, false),
+        Field::new(
+            "measured_at",
+            DataType::Timestamp(TimeUnit::Millisecond, Some(timezone.name().into())),
+            false,
+        ),
+        Field::new("temperature_celsius", DataType::Float32, false),
+        Field::new("humidity_percent", DataType::UInt8, false),
+        Field::new("co2_ppm", DataType::UInt16, true),
+        Field::new("light_level", DataType::UInt8, true),
+        Field::new("rssi_dbm", DataType::Int16, true),
+        Field::new("source", DataType::Utf8, true),
+    ]))
+}
+
+fn write_record_batch<W: Write + Send>(
+    writer: &mut ArrowWriter<W>,
+    schema: &SchemaRef,
+    timezone: Tz,
+    measurements: &[Measurement],
+) -> Result<()> {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from_iter_values(
+            measurements.iter().map(|m| m.device_id.to_string()),
+        )),
+        Arc::new(
+            TimestampMillisecondArray::from_iter_values(
+                measurements
+                    .iter()
+                    .map(|m| m.measured_at.timestamp_millis()),
+            )
+            .with_timezone(timezone.name()),
+        ),
+        Arc::new(Float32Array::from_iter_values(
+            measurements.iter().map(|m| m.temperature_celsius),
+        )),
+        Arc::new(UInt8Array::from_iter_values(
+            measurements.iter().map(|m| m.humidity_percent),
+        )),
+        Arc::new(UInt16Array::from_iter(
+            measurements.iter().map(|m| m.co2_ppm),
+        )),
+        Arc::new(UInt8Array::from_iter(
+            measurements.iter().map(|m| m.light_level),
+        )),
+        Arc::new(Int16Array::from_iter(
+            measurements.iter().map(|m| m.rssi_dbm),
+        )),
+        Arc::new(StringArray::from_iter(
+            measurements.iter().map(|m| m.source.as_deref()),
+        )),
+    ];
+
+    let batch = RecordBatch::try_new(schema.clone(), columns)
+        .context("failed to build Arrow record batch")?;
+
+    writer
+        .write(&batch)
+        .context("failed to write Parquet record batch")
+}
@@ -0,0 +1,278 @@
+//! Short-term forecasts of the temperature and the CO2 concentration of every room, by damped
+//! exponential smoothing (Holt's method) of its recent measurements.
+
+use anyhow::{Context as _, Result, ensure};
+use chrono::{DateTime, DurationRound as _, TimeDelta, Utc};
+use sqlx::PgPool;
+use tokio_stream::StreamExt as _;
+
+use crate::db::{get_switchbot_devices, stream_switchbot_measurements};
+
+/// The measurements of a room are averaged over steps of this length before they are smoothed.
+pub const STEP: TimeDelta = TimeDelta::minutes(10);
+
+const STEPS_PER_HOUR: i32 = (TimeDelta::hours(1).num_minutes() / STEP.num_minutes()) as i32;
+
+/// How far back the model is fitted.
+pub const HISTORY: TimeDelta = TimeDelta::hours(6);
+
+/// The longest forecast, beyond which a trend says little about a room.
+pub const MAX_HOURS: u32 = 3;
+
+/// A series needs an hour of steps to be forecast.
+const MIN_STEPS: usize = 6;
+
+/// A series whose last measurement is older than this is not forecast.
+const MAX_STALENESS: TimeDelta = TimeDelta::hours(1);
+
+/// How much of the trend carries over to the next step, so that it levels off over the hours
+/// rather than running away.
+const DAMPING: f64 = 0.98;
+
+/// The smoothing factors of the level and the trend that are tried when fitting.
+const FACTORS: [f64; 9] = [0.1, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7, 0.8, 0.9];
+
+/// The forecasts of a room, one per hour ahead. Devices that are not placed in a room are forecast
+/// on their own, under their name.
+#[derive(Debug, Clone)]
+pub struct RoomForecast {
+    pub room: String,
+    pub hours: Vec<HourForecast>,
+}
+
+/// `None` for a value without enough recent measurements, e.g. the CO2 of a room without a CO2
+/// meter.
+#[derive(Debug, Clone, Copy)]
+pub struct HourForecast {
+    pub at: DateTime<Utc>,
+    pub temperature_celsius: Option<f64>,
+    pub co2_ppm: Option<f64>,
+}
+
+/// The level and the trend of a series after smoothing it.
+#[derive(Debug, Clone, Copy)]
+struct Model {
+    level: f64,
+    trend: f64,
+}
+
+impl Model {
+    /// Fits the smoothing factors that minimize the squared one-step-ahead errors over `values`,
+    /// evenly spaced and oldest first.
+    fn fit(values: &[f64]) -> Option<Self> {
+        if values.len() < MIN_STEPS {
+            return None;
+        }
+
+        FACTORS
+            .iter()
+            .flat_map(|alpha| {
+                FACTORS
+                    .iter()
+                    .map(move |beta| smooth(values, *alpha, *beta))
+            })
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(model, _)| model)
+    }
+
+    /// The value `steps` steps after the last one.
+    fn predict(&self, steps: i32) -> f64 {
+        let damping = (1..=steps).map(|i| DAMPING.powi(i)).sum::<f64>();
+        self.level + self.trend * damping
+    }
+}
+
+/// Returns the model after the last of `values`, and its sum of squared errors.
+fn smooth(values: &[f64], alpha: f64, beta: f64) -> (Model, f64) {
+    let mut model = Model {
+        level: values[0],
+        trend: values[1] - values[0],
+    };
+    let mut errors = 0.0;
+    for value in &values[1..] {
+        let predicted = model.predict(1);
+        errors += (value - predicted).powi(2);
+
+        let level = alpha * value + (1.0 - alpha) * predicted;
+        model.trend = beta * (level - model.level) + (1.0 - beta) * DAMPING * model.trend;
+        model.level = level;
+    }
+
+    (model, errors)
+}
+
+/// The sums and counts of the measurements of a room in every step of the history.
+#[derive(Debug)]
+struct RoomSeries {
+    room: String,
+    temperature_celsius: Vec<(f64, u32)>,
+    co2_ppm: Vec<(f64, u32)>,
+}
+
+/// Forecasts every room `hours` hours ahead of `now`, from the measurements of its enabled devices
+/// over the last [`HISTORY`].
+pub async fn forecast_rooms(
+    pool: &PgPool,
+    now: DateTime<Utc>,
+    hours: u32,
+) -> Result<Vec<RoomForecast>> {
+    ensure!(
+        (1..=MAX_HOURS).contains(&hours),
+        "hours must be between 1 and {MAX_HOURS}"
+    );
+
+    let end = now
+        .duration_trunc(STEP)
+        .context("failed to align the forecast")?;
+    let start = end - HISTORY;
+    let steps = (HISTORY.num_minutes() / STEP.num_minutes()) as usize;
+
+    let mut rooms: Vec<RoomSeries> = Vec::new();
+    let devices = get_switchbot_devices(pool).await?;
+    for device in devices.into_iter().filter(|device| device.enabled) {
+        let room = device.room.map_or(device.name, |room| room.name);
+        let index = match rooms.iter().position(|series| series.room == room) {
+            Some(index) => index,
+            None => {
+                rooms.push(RoomSeries {
+                    room,
+                    temperature_celsius: vec![(0.0, 0); steps],
+                    co2_ppm: vec![(0.0, 0); steps],
+                });
+                rooms.len() - 1
+            }
+        };
+
+        let range = start.with_timezone(&chrono_tz::UTC)..end.with_timezone(&chrono_tz::UTC);
+        let mut measurements = stream_switchbot_measurements(pool, device.id, range);
+        while let Some(measurement) = measurements.next().await {
+            let measurement = measurement?;
+            let step =
+                ((measurement.measured_at - start).num_seconds() / STEP.num_seconds()) as usize;
+            let series = &mut rooms[index];
+            add(
+                &mut series.temperature_celsius[step],
+                measurement.temperature_celsius.into(),
+            );
+            if let Some(co2_ppm) = measurement.co2_ppm {
+                add(&mut series.co2_ppm[step], co2_ppm.into());
+            }
+        }
+    }
+
+    Ok(rooms
+        .into_iter()
+        .map(|series| {
+            let temperature = fit(&series.temperature_celsius);
+            let co2 = fit(&series.co2_ppm);
+            let hours = (1..=hours)
+                .map(|hour| {
+                    let at = end + TimeDelta::hours(hour.into());
+                    let predict = |(model, steps_ahead): (Model, i32)| {
+                        model.predict(steps_ahead + hour as i32 * STEPS_PER_HOUR)
+                    };
+                    HourForecast {
+                        at,
+                        temperature_celsius: temperature
+                            .map(predict)
+                            .map(|t| (t * 10.0).round() / 10.0),
+                        co2_ppm: co2.map(predict).map(|c| c.round().max(0.0)),
+                    }
+                })
+                .collect();
+
+            RoomForecast {
+                room: series.room,
+                hours,
+            }
+        })
+        .collect())
+}
+
+fn add((sum, count): &mut (f64, u32), value: f64) {
+    *sum += value;
+    *count += 1;
+}
+
+/// The model of the averages of `steps`, and how many steps after its last one the history ends.
+/// Gaps are filled with the value before them.
+fn fit(steps: &[(f64, u32)]) -> Option<(Model, i32)> {
+    let first = steps.iter().position(|(_, count)| *count > 0)?;
+    let last = steps.iter().rposition(|(_, count)| *count > 0)?;
+    let steps_after = (steps.len() - 1 - last) as i32;
+    if STEP * steps_after >= MAX_STALENESS {
+        return None;
+    }
+
+    let mut values = Vec::with_capacity(last + 1 - first);
+    for (sum, count) in &steps[first..=last] {
+        let value = match count {
+            0 => values.last().copied().unwrap_or_default(),
+            _ => sum / f64::from(*count),
+        };
+        values.push(value);
+    }
+
+    Model::fit(&values).map(|model| (model, steps_after))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Steps of two measurements each, `None` for a step without any.
+    fn steps(values: &[Option<f64>]) -> Vec<(f64, u32)> {
+        values
+            .iter()
+            .map(|value| value.map_or((0.0, 0), |value| (value * 2.0, 2)))
+            .collect()
+    }
+
+    #[test]
+    fn model_fit_of_a_constant_series_predicts_the_constant() {
+        let model = Model::fit(&[20.0; 12]).unwrap();
+
+        assert!((model.predict(STEPS_PER_HOUR) - 20.0).abs() < 1e-9);
+        assert!(model.trend.abs() < 1e-9);
+    }
+
+    #[test]
+    fn model_fit_follows_a_linear_trend() {
+        let values: Vec<f64> = (0..36).map(|i| 20.0 + 0.1 * f64::from(i)).collect();
+
+        let model = Model::fit(&values).unwrap();
+
+        assert!((model.level - 23.5).abs() < 0.01);
+        assert!((model.trend - 0.1).abs() < 0.01);
+        // Damped, so slightly short of the straight line.
+        let predicted = model.predict(STEPS_PER_HOUR);
+        assert!(predicted > 23.9 && predicted < 24.1, "{predicted}");
+    }
+
+    #[test]
+    fn model_fit_needs_an_hour_of_steps() {
+        assert!(Model::fit(&[20.0; MIN_STEPS - 1]).is_none());
+        assert!(Model::fit(&[20.0; MIN_STEPS]).is_some());
+    }
+
+    #[test]
+    fn fit_fills_gaps_with_the_value_before_them() {
+        let mut values = vec![None, Some(20.0), None, None];
+        values.extend([Some(20.0); 6]);
+        values.push(None);
+
+        let (model, steps_after) = fit(&steps(&values)).unwrap();
+
+        assert_eq!(steps_after, 1);
+        assert!((model.level - 20.0).abs() < 1e-9);
+        assert!(model.trend.abs() < 1e-9);
+    }
+
+    #[test]
+    fn fit_skips_a_stale_series() {
+        let mut values = vec![Some(20.0); 6];
+        values.extend([None; 6]);
+
+        assert!(fit(&steps(&values)).is_none());
+    }
+}
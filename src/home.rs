@@ -0,0 +1,10 @@
+use uuid::Uuid;
+
+#[derive(Debug, Clone)]
+pub struct Home {
+    pub id: Uuid,
+
+    pub name: String,
+
+    pub sort_order: i64,
+}
@@ -0,0 +1,17 @@
+//! The pipeline of `ble-ingester`, for embedding with other sources and sinks: [`Source`]s send
+//! measurements assigned to buckets, the [`Engine`] combines the samples of every bucket and
+//! flushes the settled ones to its [`Sink`]s.
+
+mod aggregator;
+mod engine;
+mod event;
+mod queue;
+mod replay;
+mod settings;
+
+pub use aggregator::{Preference, Strategies, Strategy};
+pub use engine::*;
+pub use event::*;
+pub use queue::QueuePolicy;
+pub use replay::*;
+pub use settings::*;
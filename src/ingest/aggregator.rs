@@ -0,0 +1,292 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Duration,
+};
+
+use chrono::{DateTime, DurationRound, TimeDelta, Utc};
+use chrono_tz::Tz;
+use clap::ValueEnum;
+use macaddr::MacAddr6;
+use metrics::gauge;
+use serde::Deserialize;
+use tokio::{
+    sync::{mpsc, watch},
+    time::Interval,
+};
+use tracing::warn;
+
+use super::{AcceptedMeasurement, DecodedMeasurement, Event, Settings, queue::Producer};
+use crate::{db::WriteBatch, raw_advertisement::RawAdvertisement, switchbot::Measurement};
+
+const BUFFERED_MEASUREMENTS: &str = "ble_ingester_buffered_measurements";
+
+/// How the samples of a bucket are combined into the stored value.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Strategy {
+    /// The value of the sample picked by [`Preference`], by default the one received closest to
+    /// the bucket boundary.
+    #[default]
+    #[value(alias = "preferred")]
+    #[serde(alias = "preferred")]
+    Closest,
+    Min,
+    Avg,
+    Max,
+}
+
+/// Which sample of a bucket represents it: its value is stored by [`Strategy::Closest`], and its
+/// signal strength always.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Preference {
+    /// The sample received closest to the bucket boundary.
+    #[default]
+    Closest,
+    /// The sample received last, for devices that advertise rarely and whose readings are only
+    /// fresh at the end of the window.
+    Latest,
+    /// The sample with the strongest signal, the closest one among equals.
+    Strongest,
+}
+
+/// The [`Preference`] and the [`Strategy`] of every field of a measurement.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Strategies {
+    pub prefer: Preference,
+    pub temperature_celsius: Strategy,
+    pub humidity_percent: Strategy,
+    pub co2_ppm: Strategy,
+    pub light_level: Strategy,
+}
+
+/// Keeps the samples of every device and bucket until they are combined and handed to the writer.
+#[derive(Debug, Default)]
+struct Aggregator {
+    measurements: HashMap<MacAddr6, BTreeMap<DateTime<Utc>, Vec<AcceptedMeasurement>>>,
+    raw_advertisements: Vec<RawAdvertisement>,
+}
+
+impl Aggregator {
+    fn push(&mut self, measurement: AcceptedMeasurement) {
+        let samples = self
+            .measurements
+            .entry(measurement.device_id)
+            .or_default()
+            .entry(measurement.bucket)
+            .or_default();
+
+        // The same reading, whether repeated by the device or heard by several adapters, is one
+        // sample, and keeps the copy with the strongest signal.
+        match samples
+            .iter_mut()
+            .find(|existing| existing.decoded == measurement.decoded)
+        {
+            Some(existing) => {
+                if measurement.rssi_dbm > existing.rssi_dbm {
+                    *existing = measurement;
+                }
+            }
+            None => samples.push(measurement),
+        }
+    }
+
+    fn push_raw_advertisement(&mut self, raw_advertisement: RawAdvertisement) {
+        self.raw_advertisements.push(raw_advertisement);
+    }
+
+    /// Removes the measurements of buckets before `cutoff` (or all of them if `cutoff` is `None`),
+    /// combined with `strategies` and attributed to `source`, and every raw advertisement.
+    fn take(
+        &mut self,
+        cutoff: Option<DateTime<Utc>>,
+        strategies: &Strategies,
+        source: Option<&str>,
+    ) -> WriteBatch {
+        let mut batch = WriteBatch::new();
+
+        for buckets in self.measurements.values_mut() {
+            let taken = match cutoff {
+                Some(cutoff) => {
+                    let kept = buckets.split_off(&cutoff);
+                    std::mem::replace(buckets, kept)
+                }
+                None => std::mem::take(buckets),
+            };
+
+            batch.extend_switchbot_measurements(
+                taken
+                    .into_values()
+                    .filter_map(|samples| combine(&samples, strategies, source)),
+            );
+        }
+        batch.extend_raw_advertisements(self.raw_advertisements.drain(..));
+
+        batch
+    }
+
+    /// Moves everything buffered by `jump`, assuming the clock was wrong before it was set, e.g.
+    /// restored from the last shutdown by `fake-hwclock` until NTP caught up.
+    fn shift(&mut self, jump: TimeDelta, interval: TimeDelta, timezone: Tz) {
+        let measurements: Vec<_> = self
+            .measurements
+            .drain()
+            .flat_map(|(_, buckets)| buckets.into_values().flatten())
+            .collect();
+
+        for mut measurement in measurements {
+            measurement.received_at += jump;
+            let Ok(bucket) = measurement
+                .received_at
+                .with_timezone(&timezone)
+                .duration_round(interval)
+            else {
+                warn!(received_at = %measurement.received_at, "failed to round received_at");
+                continue;
+            };
+            measurement.bucket = bucket.with_timezone(&Utc);
+            self.push(measurement);
+        }
+
+        for raw_advertisement in &mut self.raw_advertisements {
+            raw_advertisement.received_at += jump;
+        }
+    }
+
+    fn buffered_measurements(&self) -> usize {
+        self.measurements.values().map(BTreeMap::len).sum()
+    }
+}
+
+fn distance(measurement: &AcceptedMeasurement) -> TimeDelta {
+    (measurement.received_at - measurement.bucket).abs()
+}
+
+/// Combines the samples of one bucket into the row that is stored, taking the signal strength of
+/// the preferred sample.
+fn combine(
+    samples: &[AcceptedMeasurement],
+    strategies: &Strategies,
+    source: Option<&str>,
+) -> Option<Measurement> {
+    let preferred = match strategies.prefer {
+        Preference::Closest => samples.iter().min_by_key(|m| distance(m)),
+        Preference::Latest => samples.iter().max_by_key(|m| m.received_at),
+        Preference::Strongest => samples
+            .iter()
+            .max_by_key(|m| (m.rssi_dbm, std::cmp::Reverse(distance(m)))),
+    }?;
+
+    let temperature_celsius = apply(strategies.temperature_celsius, samples, preferred, |d| {
+        Some(d.temperature_celsius.into())
+    })?;
+    let humidity_percent = apply(strategies.humidity_percent, samples, preferred, |d| {
+        Some(d.humidity_percent.into())
+    })?;
+    let co2_ppm = apply(strategies.co2_ppm, samples, preferred, |d| {
+        d.co2_ppm.map(f64::from)
+    });
+    let light_level = apply(strategies.light_level, samples, preferred, |d| {
+        d.light_level.map(f64::from)
+    });
+
+    Some(Measurement {
+        device_id: preferred.device_id,
+        measured_at: preferred.bucket,
+        // Averages keep the resolution of the sensors.
+        temperature_celsius: ((temperature_celsius * 10.0).round() / 10.0) as f32,
+        humidity_percent: humidity_percent.round() as u8,
+        co2_ppm: co2_ppm.map(|v| v.round() as u16),
+        light_level: light_level.map(|v| v.round() as u8),
+        rssi_dbm: preferred.rssi_dbm,
+        source: source.map(str::to_string),
+    })
+}
+
+fn apply(
+    strategy: Strategy,
+    samples: &[AcceptedMeasurement],
+    preferred: &AcceptedMeasurement,
+    field: impl Fn(&DecodedMeasurement) -> Option<f64>,
+) -> Option<f64> {
+    let values = samples.iter().filter_map(|m| field(&m.decoded));
+
+    match strategy {
+        Strategy::Closest => field(&preferred.decoded),
+        Strategy::Min => values.reduce(f64::min),
+        Strategy::Max => values.reduce(f64::max),
+        Strategy::Avg => {
+            let (sum, count) = values.fold((0.0, 0), |(sum, count), v| (sum + v, count + 1));
+            (count > 0).then(|| sum / f64::from(count))
+        }
+    }
+}
+
+/// Ticks every `flush_interval`. A zero interval, which `tokio::time::interval` rejects with a
+/// panic, flushes every millisecond instead.
+fn flush_timer(flush_interval: Duration) -> Interval {
+    if flush_interval.is_zero() {
+        warn!("flush interval is zero, flushing every millisecond");
+    }
+    tokio::time::interval(flush_interval.max(Duration::from_millis(1)))
+}
+
+/// Owns the buffer: applies events from the sources and every flush interval hands the settled
+/// buckets to the writer. While the writer is still busy, rows stay buffered. Once every sender is
+/// dropped, the rest is handed over and the batch channel is closed.
+pub(super) async fn aggregate(
+    mut events: mpsc::Receiver<Event>,
+    batches: Producer,
+    mut settings: watch::Receiver<Settings>,
+) {
+    let mut aggregator = Aggregator::default();
+
+    let mut interval = flush_timer(settings.borrow().flush_interval);
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Some(Event::Measurement(measurement)) => {
+                    aggregator.push(measurement);
+                    set_buffered_measurements(aggregator.buffered_measurements());
+                }
+                Some(Event::RawAdvertisement(raw_advertisement)) => {
+                    aggregator.push_raw_advertisement(raw_advertisement);
+                }
+                Some(Event::ClockJump(jump)) => {
+                    let settings = settings.borrow().clone();
+                    aggregator.shift(jump, settings.interval, settings.timezone);
+                    set_buffered_measurements(aggregator.buffered_measurements());
+                }
+                None => break,
+            },
+            Ok(()) = settings.changed() => {
+                interval = flush_timer(settings.borrow_and_update().flush_interval);
+            }
+            _ = interval.tick() => {
+                // A bucket may still receive measurements until its window has passed.
+                let settings = settings.borrow().clone();
+                let cutoff = Utc::now() - settings.window * 2;
+                let batch = aggregator.take(
+                    Some(cutoff),
+                    &settings.aggregation,
+                    settings.source.as_deref(),
+                );
+                set_buffered_measurements(aggregator.buffered_measurements());
+                batches
+                    .push(batch, settings.queue_capacity, settings.queue_policy)
+                    .await;
+            }
+        }
+    }
+
+    let settings = settings.borrow().clone();
+    let batch = aggregator.take(None, &settings.aggregation, settings.source.as_deref());
+    set_buffered_measurements(0);
+    batches
+        .push(batch, settings.queue_capacity, settings.queue_policy)
+        .await;
+}
+
+fn set_buffered_measurements(count: usize) {
+    gauge!(BUFFERED_MEASUREMENTS).set(count as f64);
+}
@@ -0,0 +1,180 @@
+use std::pin::Pin;
+
+use anyhow::{Context as _, Result, bail};
+use chrono::Utc;
+use metrics::counter;
+use sqlx::PgPool;
+use tokio::{
+    sync::{mpsc, watch},
+    task::JoinSet,
+};
+use tracing::{error, info, warn};
+
+use super::{
+    Event, Settings,
+    aggregator::aggregate,
+    queue::{self, Consumer},
+};
+use crate::db::WriteBatch;
+
+/// How many events the sources may queue before they wait for the aggregator.
+const EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+const EVICTED_ROWS_TOTAL: &str = "ble_ingester_evicted_rows_total";
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Produces measurements, e.g. by scanning for BLE advertisements.
+pub trait Source: Send {
+    /// Sends events until there are none left. The future is dropped when the engine shuts down.
+    fn run(self: Box<Self>, events: mpsc::Sender<Event>) -> BoxFuture<'static, Result<()>>;
+}
+
+/// Writes the rows of settled buckets. A batch that fails is retried together with the next one,
+/// so a sink should tolerate rows it has already written.
+pub trait Sink: Send + Sync {
+    fn write<'a>(&'a self, batch: &'a WriteBatch) -> BoxFuture<'a, Result<()>>;
+}
+
+/// Inserts every batch in a single transaction.
+impl Sink for PgPool {
+    fn write<'a>(&'a self, batch: &'a WriteBatch) -> BoxFuture<'a, Result<()>> {
+        Box::pin(batch.commit(self))
+    }
+}
+
+/// Runs sources and writes what they produce to sinks, one batch per flush interval.
+pub struct Engine {
+    settings: watch::Receiver<Settings>,
+    sinks: Vec<Box<dyn Sink>>,
+}
+
+impl Engine {
+    pub fn new(settings: watch::Receiver<Settings>) -> Self {
+        Self {
+            settings,
+            sinks: Vec::new(),
+        }
+    }
+
+    /// Adds a sink. Every batch is written to the sinks in the order they were added.
+    pub fn sink(mut self, sink: impl Sink + 'static) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    /// Runs `sources` until all of them have finished or `shutdown` completes, then writes what is
+    /// still buffered. Fails if `shutdown` fails, the writer stops, the last write fails, or a
+    /// source failed; the other sources keep running after one failed.
+    pub async fn run(
+        self,
+        sources: Vec<Box<dyn Source>>,
+        shutdown: impl Future<Output = Result<()>>,
+    ) -> Result<()> {
+        let (events_tx, events_rx) = mpsc::channel(EVENT_CHANNEL_CAPACITY);
+        let (batches_tx, batches_rx) = queue::channel();
+
+        let mut tasks = JoinSet::new();
+        for source in sources {
+            tasks.spawn(source.run(events_tx.clone()));
+        }
+        drop(events_tx);
+
+        tokio::spawn(aggregate(events_rx, batches_tx, self.settings.clone()));
+        let mut writer = tokio::spawn(write_batches(self.sinks, batches_rx, self.settings));
+
+        let mut source_error = None;
+        tokio::select! {
+            result = shutdown => result?,
+            _ = async {
+                while let Some(result) = tasks.join_next().await {
+                    if let Ok(Err(err)) = result {
+                        error!(error = format!("{err:#}"), "source failed");
+                        source_error.get_or_insert(err);
+                    }
+                }
+            } => {}
+            result = &mut writer => {
+                result.context("writer task panicked")??;
+                bail!("writer stopped unexpectedly");
+            }
+        }
+
+        // Stopping the sources drops the last event senders, so the aggregator hands over
+        // everything it buffered and the writer returns once that is written.
+        tasks.shutdown().await;
+
+        info!("shutting down, flushing remaining measurements");
+        writer
+            .await
+            .context("writer task panicked")?
+            .context("failed to flush remaining measurements")?;
+
+        match source_error {
+            Some(err) => Err(err.context("source failed")),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Writes the batches handed over by the aggregator to the sinks until the aggregator closes the
+/// queue. A batch that fails is kept and retried together with the next one, within the buffer
+/// limits.
+async fn write_batches(
+    sinks: Vec<Box<dyn Sink>>,
+    batches: Consumer,
+    settings: watch::Receiver<Settings>,
+) -> Result<()> {
+    let mut pending = WriteBatch::new();
+    let mut last_result = Ok(());
+    while let Some(mut batch) = batches.pop().await {
+        pending.append(&mut batch);
+
+        last_result = flush(&sinks, &pending).await;
+        match &last_result {
+            Ok(()) => pending = WriteBatch::new(),
+            Err(err) => {
+                error!("{err:#}");
+                evict(&settings.borrow(), &mut pending);
+            }
+        }
+    }
+
+    last_result
+}
+
+#[tracing::instrument(name = "flush", skip_all)]
+async fn flush(sinks: &[Box<dyn Sink>], batch: &WriteBatch) -> Result<()> {
+    for sink in sinks {
+        sink.write(batch).await?;
+    }
+
+    Ok(())
+}
+
+/// Drops the rows of `pending` beyond the configured limits, oldest first, so that a long database
+/// outage cannot exhaust memory.
+fn evict(settings: &Settings, pending: &mut WriteBatch) {
+    if let Some(max_age) = settings.max_buffer_age {
+        let dropped = pending.evict_before(Utc::now() - max_age);
+        if dropped > 0 {
+            record_evicted_rows("age", dropped);
+            warn!(dropped, "dropped buffered rows older than max_buffer_age");
+        }
+    }
+
+    let dropped = pending.evict_oldest(settings.max_buffer_size);
+    if dropped > 0 {
+        record_evicted_rows("size", dropped);
+        warn!(
+            dropped,
+            "dropped the oldest buffered rows beyond max_buffer_size"
+        );
+    }
+}
+
+/// Counts buffered rows dropped without being written, because they exceeded `reason` (`age`,
+/// `size` or `queue`).
+pub(super) fn record_evicted_rows(reason: &'static str, count: usize) {
+    counter!(EVICTED_ROWS_TOTAL, "reason" => reason).increment(count as u64);
+}
@@ -0,0 +1,45 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use macaddr::MacAddr6;
+
+use crate::raw_advertisement::RawAdvertisement;
+
+/// The values read from a device, before they are assigned to a bucket.
+#[derive(Debug, PartialEq)]
+pub struct DecodedMeasurement {
+    pub temperature_celsius: f32,
+    pub humidity_percent: u8,
+    pub co2_ppm: Option<u16>,
+    pub light_level: Option<u8>,
+}
+
+impl DecodedMeasurement {
+    /// Applies the calibration offsets of a device. The temperature keeps the 0.1°C resolution of
+    /// the sensors and the humidity stays within 0-100%.
+    pub fn calibrate(&mut self, temperature_offset_celsius: f32, humidity_offset_percent: i8) {
+        self.temperature_celsius =
+            ((self.temperature_celsius + temperature_offset_celsius) * 10.0).round() / 10.0;
+        self.humidity_percent = self
+            .humidity_percent
+            .saturating_add_signed(humidity_offset_percent)
+            .min(100);
+    }
+}
+
+/// A decoded measurement received within the window around `bucket`.
+#[derive(Debug)]
+pub struct AcceptedMeasurement {
+    pub device_id: MacAddr6,
+    pub bucket: DateTime<Utc>,
+    pub received_at: DateTime<Utc>,
+    pub rssi_dbm: Option<i16>,
+    pub decoded: DecodedMeasurement,
+}
+
+/// Sent from the sources to the engine.
+#[derive(Debug)]
+pub enum Event {
+    Measurement(AcceptedMeasurement),
+    RawAdvertisement(RawAdvertisement),
+    /// The system clock was set by this much, after the events before it were timestamped.
+    ClockJump(TimeDelta),
+}
@@ -0,0 +1,124 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use clap::ValueEnum;
+use metrics::gauge;
+use serde::Deserialize;
+use tokio::sync::Notify;
+use tracing::warn;
+
+use super::engine::record_evicted_rows;
+use crate::db::WriteBatch;
+
+const QUEUE_DEPTH: &str = "ble_ingester_queue_depth";
+
+/// What the aggregator does with a batch when the queue to the writer is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum QueuePolicy {
+    /// Wait for the writer, which in turn holds up the sources.
+    #[default]
+    Block,
+    /// Drop the oldest queued batch to make room.
+    DropOldest,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    queue: Mutex<Queue>,
+    pushed: Notify,
+    popped: Notify,
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    batches: VecDeque<WriteBatch>,
+    closed: bool,
+}
+
+/// Creates the queue the aggregator hands its batches to the writer through.
+pub(super) fn channel() -> (Producer, Consumer) {
+    let shared = Arc::new(Shared::default());
+    (
+        Producer {
+            shared: shared.clone(),
+        },
+        Consumer { shared },
+    )
+}
+
+/// The aggregator's end. Dropping it closes the queue once the writer has taken what is left.
+#[derive(Debug)]
+pub(super) struct Producer {
+    shared: Arc<Shared>,
+}
+
+impl Producer {
+    /// Queues `batch`, waiting or dropping the oldest batch according to `policy` while `capacity`
+    /// batches are queued. `capacity` must not be zero.
+    pub(super) async fn push(&self, batch: WriteBatch, capacity: usize, policy: QueuePolicy) {
+        loop {
+            {
+                let mut queue = self.shared.queue.lock().unwrap();
+                while queue.batches.len() >= capacity && policy == QueuePolicy::DropOldest {
+                    let Some(dropped) = queue.batches.pop_front() else {
+                        break;
+                    };
+                    record_evicted_rows("queue", dropped.len());
+                    warn!(
+                        dropped = dropped.len(),
+                        "writer queue is full, dropped the oldest batch"
+                    );
+                }
+                if queue.batches.len() < capacity {
+                    queue.batches.push_back(batch);
+                    set_queue_depth(queue.batches.len());
+                    self.shared.pushed.notify_one();
+                    return;
+                }
+            }
+
+            self.shared.popped.notified().await;
+        }
+    }
+}
+
+impl Drop for Producer {
+    fn drop(&mut self) {
+        self.shared.queue.lock().unwrap().closed = true;
+        self.shared.pushed.notify_one();
+    }
+}
+
+/// The writer's end.
+#[derive(Debug)]
+pub(super) struct Consumer {
+    shared: Arc<Shared>,
+}
+
+impl Consumer {
+    /// Takes the oldest batch, or returns `None` once the queue is closed and empty.
+    pub(super) async fn pop(&self) -> Option<WriteBatch> {
+        loop {
+            {
+                let mut queue = self.shared.queue.lock().unwrap();
+                if let Some(batch) = queue.batches.pop_front() {
+                    set_queue_depth(queue.batches.len());
+                    self.shared.popped.notify_one();
+                    return Some(batch);
+                }
+                if queue.closed {
+                    return None;
+                }
+            }
+
+            self.shared.pushed.notified().await;
+        }
+    }
+}
+
+fn set_queue_depth(depth: usize) {
+    gauge!(QUEUE_DEPTH).set(depth as f64);
+}
@@ -0,0 +1,102 @@
+use std::path::PathBuf;
+
+use anyhow::{Context as _, Result};
+use tokio::{
+    fs::File,
+    io::{AsyncBufReadExt as _, BufReader},
+    sync::{mpsc, watch},
+};
+use tracing::{info, warn};
+
+use super::{AcceptedMeasurement, BoxFuture, DecodedMeasurement, Event, Settings, Source};
+use crate::raw_advertisement::{CapturedAdvertisement, RawAdvertisement};
+
+/// Feeds the advertisements of a capture file, one [`CapturedAdvertisement`] per line, through the
+/// pipeline with their original timestamps, e.g. to check a decoder change against recorded
+/// payloads. Advertisements outside the window of a bucket are skipped as if they were received
+/// live.
+pub struct ReplaySource<D> {
+    path: PathBuf,
+    settings: watch::Receiver<Settings>,
+    decode: D,
+}
+
+impl<D> ReplaySource<D>
+where
+    D: FnMut(&RawAdvertisement) -> Result<Option<DecodedMeasurement>> + Send + 'static,
+{
+    /// `decode` returns `None` for advertisements of devices that are not collected.
+    pub fn new(path: impl Into<PathBuf>, settings: watch::Receiver<Settings>, decode: D) -> Self {
+        Self {
+            path: path.into(),
+            settings,
+            decode,
+        }
+    }
+
+    async fn replay(mut self, events: mpsc::Sender<Event>) -> Result<()> {
+        let file = File::open(&self.path)
+            .await
+            .with_context(|| format!("failed to open replay file: {:?}", self.path))?;
+        let mut lines = BufReader::new(file).lines();
+
+        let mut line_number = 0;
+        let mut replayed = 0;
+        while let Some(line) = lines
+            .next_line()
+            .await
+            .with_context(|| format!("failed to read replay file: {:?}", self.path))?
+        {
+            line_number += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let advertisement = serde_json::from_str::<CapturedAdvertisement>(&line)
+                .map_err(anyhow::Error::from)
+                .and_then(RawAdvertisement::try_from)
+                .with_context(|| format!("failed to parse replay file line {line_number}"))?;
+
+            let Some(bucket) = self.settings.borrow().bucket(advertisement.received_at) else {
+                continue;
+            };
+            let decoded = match (self.decode)(&advertisement) {
+                Ok(Some(decoded)) => decoded,
+                Ok(None) => continue,
+                Err(err) => {
+                    warn!(
+                        error = format!("{err:#}"),
+                        device_id = %advertisement.device_id,
+                        received_at = %advertisement.received_at,
+                        "failed to decode replayed advertisement"
+                    );
+                    continue;
+                }
+            };
+
+            let measurement = AcceptedMeasurement {
+                device_id: advertisement.device_id,
+                bucket,
+                received_at: advertisement.received_at,
+                rssi_dbm: advertisement.rssi_dbm,
+                decoded,
+            };
+            if events.send(Event::Measurement(measurement)).await.is_err() {
+                break;
+            }
+            replayed += 1;
+        }
+
+        info!(replayed, path = ?self.path, "replayed advertisements");
+        Ok(())
+    }
+}
+
+impl<D> Source for ReplaySource<D>
+where
+    D: FnMut(&RawAdvertisement) -> Result<Option<DecodedMeasurement>> + Send + 'static,
+{
+    fn run(self: Box<Self>, events: mpsc::Sender<Event>) -> BoxFuture<'static, Result<()>> {
+        Box::pin(self.replay(events))
+    }
+}
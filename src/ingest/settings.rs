@@ -0,0 +1,50 @@
+use std::time::Duration;
+
+use chrono::{DateTime, DurationRound as _, TimeDelta, Utc};
+use chrono_tz::Tz;
+use tracing::warn;
+
+use super::{QueuePolicy, Strategies};
+
+/// How the engine buckets, combines and flushes measurements. Changes take effect while it runs.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// Buckets are aligned in this timezone.
+    pub timezone: Tz,
+    pub interval: TimeDelta,
+    /// Only measurements received within this distance of a bucket boundary are accepted.
+    pub window: TimeDelta,
+    pub aggregation: Strategies,
+    /// Stored with every measurement to tell several ingesters apart.
+    pub source: Option<String>,
+    pub flush_interval: Duration,
+    /// How many batches may wait for the writer before `queue_policy` applies.
+    pub queue_capacity: usize,
+    pub queue_policy: QueuePolicy,
+    /// Rows that could not be written are dropped once they are this old.
+    pub max_buffer_age: Option<TimeDelta>,
+    /// Rows that could not be written are dropped, oldest first, beyond this many per table.
+    pub max_buffer_size: usize,
+}
+
+impl Settings {
+    /// Returns the bucket `now` belongs to, or `None` if it is outside the window around the bucket
+    /// boundary. Buckets are aligned in the timezone, which only matters for intervals that do not
+    /// divide its UTC offset, and are returned in UTC like everything that is stored.
+    pub fn bucket(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let Ok(bucket) = now
+            .with_timezone(&self.timezone)
+            .duration_round(self.interval)
+        else {
+            warn!(%now, interval = %self.interval, "failed to round measured_at");
+            return None;
+        };
+        let bucket = bucket.with_timezone(&Utc);
+
+        if (now - bucket).abs() > self.window {
+            return None;
+        }
+
+        Some(bucket)
+    }
+}
@@ -1,2 +1,10 @@
 pub mod db;
+pub mod export;
+pub mod forecast;
+pub mod home;
+pub mod ingest;
+pub mod logging;
+pub mod mqtt;
+pub mod raw_advertisement;
+pub mod room;
 pub mod switchbot;
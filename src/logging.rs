@@ -0,0 +1,61 @@
+use std::io;
+
+use anyhow::{Context as _, Result, anyhow};
+use clap::ValueEnum;
+use serde::Deserialize;
+use tracing_subscriber::{EnvFilter, Layer as _, Registry, prelude::*, reload};
+
+const DEFAULT_LOG_LEVEL: &str = "info";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Changes the filter of the subscriber installed by [`init`] at runtime.
+#[derive(Debug, Clone)]
+pub struct LogHandle(reload::Handle<EnvFilter, Registry>);
+
+impl LogHandle {
+    pub fn set_level(&self, level: Option<&str>) -> Result<()> {
+        let filter = env_filter(level)?;
+        self.0.reload(filter).context("failed to reload log filter")
+    }
+}
+
+/// Installs the global tracing subscriber writing to stderr.
+///
+/// `level` accepts the same directives as `RUST_LOG` (e.g. `info,sqlx=warn`) and takes precedence
+/// over it. Without either, `info` is used.
+pub fn init(level: Option<&str>, format: LogFormat) -> Result<LogHandle> {
+    let (filter, handle) = reload::Layer::new(env_filter(level)?);
+
+    let fmt = tracing_subscriber::fmt::layer().with_writer(io::stderr);
+    let fmt = match format {
+        LogFormat::Text => fmt.boxed(),
+        LogFormat::Json => fmt.json().boxed(),
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt)
+        .try_init()
+        .map_err(|e| anyhow!(e))?;
+
+    Ok(LogHandle(handle))
+}
+
+fn env_filter(level: Option<&str>) -> Result<EnvFilter> {
+    match level {
+        Some(level) => {
+            EnvFilter::try_new(level).with_context(|| format!("invalid log level: {level}"))
+        }
+        None => {
+            Ok(EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| EnvFilter::new(DEFAULT_LOG_LEVEL)))
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use anyhow::{Context as _, Result, bail};
+use percent_encoding::percent_decode_str;
+use rumqttc::MqttOptions;
+use url::Url;
+
+const DEFAULT_PORT: u16 = 1883;
+
+/// Builds the options from `mqtt://[user[:password]@]host[:port][?client_id=...]`, with the
+/// credentials percent-decoded, e.g. `p%40ss` for `p@ss`.
+pub fn mqtt_options(url: &str, default_client_id: &str) -> Result<MqttOptions> {
+    let url = Url::parse(url)?;
+    if !matches!(url.scheme(), "mqtt" | "tcp") {
+        bail!("unsupported scheme: {}", url.scheme());
+    }
+
+    let host = url.host_str().context("missing host")?;
+    let port = url.port().unwrap_or(DEFAULT_PORT);
+    let client_id = url
+        .query_pairs()
+        .find(|(key, _)| key == "client_id")
+        .map_or_else(
+            || default_client_id.to_string(),
+            |(_, value)| value.into_owned(),
+        );
+
+    let mut options = MqttOptions::new(client_id, host, port);
+    if !url.username().is_empty() {
+        let decode = |value| {
+            percent_decode_str(value)
+                .decode_utf8()
+                .context("credentials are not valid UTF-8")
+        };
+        options.set_credentials(
+            decode(url.username())?,
+            decode(url.password().unwrap_or_default())?,
+        );
+    }
+
+    Ok(options)
+}
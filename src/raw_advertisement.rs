@@ -0,0 +1,108 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write as _,
+};
+
+use anyhow::{Context as _, Result};
+use chrono::{DateTime, Utc};
+use macaddr::MacAddr6;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// An undecoded BLE advertisement, kept so that it can be decoded again after a decoder fix.
+#[derive(Debug, Clone)]
+pub struct RawAdvertisement {
+    pub device_id: MacAddr6,
+
+    pub received_at: DateTime<Utc>,
+
+    pub rssi_dbm: Option<i16>,
+
+    pub manufacturer_data: HashMap<u16, Vec<u8>>,
+
+    pub service_data: HashMap<Uuid, Vec<u8>>,
+}
+
+/// A [`RawAdvertisement`] as one line of a capture file, with the payloads in hex.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CapturedAdvertisement {
+    pub device_id: String,
+    pub received_at: DateTime<Utc>,
+    pub rssi_dbm: Option<i16>,
+    /// Hex payloads keyed by company ID in hex, e.g. `0969`.
+    pub manufacturer_data: BTreeMap<String, String>,
+    pub service_data: BTreeMap<String, String>,
+}
+
+impl From<&RawAdvertisement> for CapturedAdvertisement {
+    fn from(advertisement: &RawAdvertisement) -> Self {
+        Self {
+            device_id: advertisement.device_id.to_string(),
+            received_at: advertisement.received_at,
+            rssi_dbm: advertisement.rssi_dbm,
+            manufacturer_data: advertisement
+                .manufacturer_data
+                .iter()
+                .map(|(company_id, data)| (format!("{company_id:04x}"), hex_encode(data)))
+                .collect(),
+            service_data: advertisement
+                .service_data
+                .iter()
+                .map(|(uuid, data)| (uuid.to_string(), hex_encode(data)))
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<CapturedAdvertisement> for RawAdvertisement {
+    type Error = anyhow::Error;
+
+    fn try_from(captured: CapturedAdvertisement) -> Result<Self> {
+        Ok(RawAdvertisement {
+            device_id: captured
+                .device_id
+                .parse()
+                .with_context(|| format!("invalid device ID: {}", captured.device_id))?,
+            received_at: captured.received_at,
+            rssi_dbm: captured.rssi_dbm,
+            manufacturer_data: captured
+                .manufacturer_data
+                .iter()
+                .map(|(company_id, data)| {
+                    let company_id = u16::from_str_radix(company_id, 16)
+                        .with_context(|| format!("invalid company ID: {company_id}"))?;
+                    Ok((company_id, hex_decode(data)?))
+                })
+                .collect::<Result<_>>()?,
+            service_data: captured
+                .service_data
+                .iter()
+                .map(|(uuid, data)| {
+                    let uuid = uuid
+                        .parse()
+                        .with_context(|| format!("invalid service UUID: {uuid}"))?;
+                    Ok((uuid, hex_decode(data)?))
+                })
+                .collect::<Result<_>>()?,
+        })
+    }
+}
+
+fn hex_encode(data: &[u8]) -> String {
+    data.iter()
+        .fold(String::with_capacity(data.len() * 2), |mut s, b| {
+            let _ = write!(s, "{b:02x}");
+            s
+        })
+}
+
+fn hex_decode(hex: &str) -> Result<Vec<u8>> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .with_context(|| format!("invalid hex payload: {hex}"))
+        })
+        .collect()
+}
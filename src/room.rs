@@ -0,0 +1,13 @@
+use uuid::Uuid;
+
+#[derive(Debug, Clone)]
+pub struct Room {
+    pub id: Uuid,
+
+    pub home_id: Uuid,
+
+    pub name: String,
+
+    /// The position of the room among those of its home.
+    pub sort_order: i64,
+}
@@ -1,7 +1,15 @@
 mod device;
+mod device_location;
+mod device_statistics;
 mod device_type;
+mod latest_measurement;
 mod measurement;
+mod measurement_bucket;
 
 pub use device::*;
+pub use device_location::*;
+pub use device_statistics::*;
 pub use device_type::*;
+pub use latest_measurement::*;
 pub use measurement::*;
+pub use measurement_bucket::*;
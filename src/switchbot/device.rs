@@ -1,8 +1,8 @@
 use macaddr::MacAddr6;
 
-use crate::switchbot::DeviceType;
+use crate::{room::Room, switchbot::DeviceType};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Device {
     pub id: MacAddr6,
 
@@ -11,4 +11,18 @@ pub struct Device {
     pub name: String,
 
     pub sort_order: u8,
+
+    /// Whether the ingester should collect measurements from the device.
+    pub enabled: bool,
+
+    /// The room the device is currently placed in, if any.
+    pub room: Option<Room>,
+
+    pub notes: Option<String>,
+
+    /// Added to the decoded temperature by the ingester, to correct a device that reads off.
+    pub temperature_offset_celsius: f32,
+
+    /// Added to the decoded humidity by the ingester.
+    pub humidity_offset_percent: i8,
 }
@@ -0,0 +1,16 @@
+use chrono::{DateTime, Utc};
+use macaddr::MacAddr6;
+use uuid::Uuid;
+
+/// A period during which a device was placed in a room.
+#[derive(Debug, Clone)]
+pub struct DeviceLocation {
+    pub device_id: MacAddr6,
+
+    pub room_id: Uuid,
+
+    pub placed_at: DateTime<Utc>,
+
+    /// `None` while the device is still in the room.
+    pub removed_at: Option<DateTime<Utc>>,
+}
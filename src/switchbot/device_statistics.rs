@@ -0,0 +1,22 @@
+use chrono::DateTime;
+use chrono_tz::Tz;
+use macaddr::MacAddr6;
+
+#[derive(Debug, Clone)]
+pub struct DeviceStatistics {
+    pub device_id: MacAddr6,
+
+    pub device_name: String,
+
+    pub record_count: u64,
+
+    pub first_measured_at: Option<DateTime<Tz>>,
+
+    pub last_measured_at: Option<DateTime<Tz>>,
+
+    /// Percentage of the 1-minute slots in the last 24 hours that have a measurement.
+    pub coverage_24h_percent: f64,
+
+    /// Percentage of the 1-minute slots in the last 7 days that have a measurement.
+    pub coverage_7d_percent: f64,
+}
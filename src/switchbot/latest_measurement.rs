@@ -0,0 +1,8 @@
+use crate::switchbot::{Device, Measurement};
+
+#[derive(Debug)]
+pub struct LatestMeasurement {
+    pub device: Device,
+
+    pub measurement: Measurement,
+}
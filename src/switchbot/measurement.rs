@@ -1,12 +1,11 @@
-use chrono::DateTime;
-use chrono_tz::Tz;
+use chrono::{DateTime, Utc};
 use macaddr::MacAddr6;
 
 #[derive(Debug, Clone)]
 pub struct Measurement {
     pub device_id: MacAddr6,
 
-    pub measured_at: DateTime<Tz>,
+    pub measured_at: DateTime<Utc>,
 
     pub temperature_celsius: f32,
 
@@ -15,4 +14,40 @@ pub struct Measurement {
     pub co2_ppm: Option<u16>,
 
     pub light_level: Option<u8>,
+
+    /// Signal strength of the advertisement the measurement was decoded from.
+    pub rssi_dbm: Option<i16>,
+
+    /// The ingester that heard the advertisement, e.g. its hostname.
+    pub source: Option<String>,
+}
+
+/// Saturation vapour pressure over water in hPa, by the Magnus formula.
+fn saturation_vapour_pressure_hpa(temperature_celsius: f64) -> f64 {
+    6.112 * (17.62 * temperature_celsius / (243.12 + temperature_celsius)).exp()
+}
+
+impl Measurement {
+    /// The temperature at which the air would be saturated, or `None` at 0 % humidity.
+    pub fn dew_point_celsius(&self) -> Option<f32> {
+        if self.humidity_percent == 0 {
+            return None;
+        }
+        let temperature_celsius = f64::from(self.temperature_celsius);
+        let gamma = (f64::from(self.humidity_percent) / 100.0).ln()
+            + 17.62 * temperature_celsius / (243.12 + temperature_celsius);
+
+        Some((243.12 * gamma / (17.62 - gamma)) as f32)
+    }
+
+    /// The mass of water vapour in a cubic metre of air.
+    pub fn absolute_humidity_g_m3(&self) -> f32 {
+        let temperature_celsius = f64::from(self.temperature_celsius);
+        let vapour_pressure_hpa = saturation_vapour_pressure_hpa(temperature_celsius)
+            * f64::from(self.humidity_percent)
+            / 100.0;
+
+        // The specific gas constant of water vapour is 461.5 J/(kg K).
+        (vapour_pressure_hpa * 100.0 / (461.5 * (temperature_celsius + 273.15)) * 1000.0) as f32
+    }
 }
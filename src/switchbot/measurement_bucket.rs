@@ -0,0 +1,51 @@
+use chrono::DateTime;
+use chrono_tz::Tz;
+
+/// The length of the buckets that measurements are aggregated into, aligned to the timezone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BucketSize {
+    Hour,
+    Day,
+}
+
+impl BucketSize {
+    /// The unit of `date_trunc`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BucketSize::Hour => "hour",
+            BucketSize::Day => "day",
+        }
+    }
+}
+
+/// The aggregates of the measurements of a device in a bucket, as in the rollup tables.
+#[derive(Debug, Clone)]
+pub struct MeasurementBucket {
+    pub bucket_start: DateTime<Tz>,
+
+    pub sample_count: u32,
+
+    pub temperature_celsius_min: f32,
+
+    pub temperature_celsius_avg: f64,
+
+    pub temperature_celsius_max: f32,
+
+    pub humidity_percent_min: u8,
+
+    pub humidity_percent_avg: f64,
+
+    pub humidity_percent_max: u8,
+
+    pub co2_ppm_min: Option<u16>,
+
+    pub co2_ppm_avg: Option<f64>,
+
+    pub co2_ppm_max: Option<u16>,
+
+    pub light_level_min: Option<u8>,
+
+    pub light_level_avg: Option<f64>,
+
+    pub light_level_max: Option<u8>,
+}